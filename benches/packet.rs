@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use ray_tracing::geometry::simd::Vec3A;
+use ray_tracing::geometry::Vec3;
+use ray_tracing::ray_tracing::packet::{RayPacket, PACKET_WIDTH};
+use ray_tracing::ray_tracing::Ray;
+use ray_tracing::ray_tracing::scene::bvh::Bvh;
+use ray_tracing::ray_tracing::scene::material::{Color, Material};
+use ray_tracing::ray_tracing::scene::object::Sphere;
+use ray_tracing::ray_tracing::scene::Collision;
+
+fn material() -> Material {
+    Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() }
+}
+
+fn scene() -> Bvh {
+    let mut rng = StdRng::seed_from_u64(1);
+    let spheres = (0..2000).map(|_| {
+        let center = Vec3::new(rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0));
+        Arc::new(Sphere::new(center, rng.gen_range(0.1..0.8), material())) as Arc<dyn Collision>
+    }).collect();
+    Bvh::new(spheres)
+}
+
+/// Camera rays through a 64x64 grid, grouped into packets of horizontally adjacent pixels.
+fn packets() -> Vec<RayPacket<PACKET_WIDTH>> {
+    let initial = Vec3::new(0.0, 0.0, -60.0);
+    let ray = |x: usize, y: usize| Ray::new(initial, Vec3::new(x as f64 / 64.0 - 0.5, y as f64 / 64.0 - 0.5, 1.0).normalize(), 0.0);
+    (0..64).flat_map(|y| (0..64 / PACKET_WIDTH).map(move |x| RayPacket::new(std::array::from_fn(|i| ray(x * PACKET_WIDTH + i, y))))).collect()
+}
+
+/// Number of rays of `packets` that hit `object`, traced one at a time.
+fn trace_scalar(object: &dyn Collision, packets: &[RayPacket<PACKET_WIDTH>]) -> usize {
+    packets.iter().flat_map(|packet| packet.rays().iter()).filter(|ray| object.collision(black_box(ray), 0.0, f64::INFINITY).is_some()).count()
+}
+
+/// Number of rays of `packet` that hit `object`.
+fn trace_packet(object: &dyn Collision, packet: &RayPacket<PACKET_WIDTH>) -> usize {
+    let mut t_max = [f64::INFINITY; PACKET_WIDTH];
+    let mut hits = std::array::from_fn(|_| None);
+    object.collision_packet(packet, &[true; PACKET_WIDTH], 0.0, &mut t_max, &mut hits);
+    hits.iter().filter(|hit| hit.is_some()).count()
+}
+
+fn bvh(c: &mut Criterion) {
+    let bvh = scene();
+    let packets = packets();
+    let mut group = c.benchmark_group("bvh");
+    group.bench_function("scalar f64", |b| b.iter(|| trace_scalar(&bvh, &packets)));
+    group.bench_function(format!("packet x{}", PACKET_WIDTH), |b| b.iter(|| {
+        packets.iter().map(|packet| trace_packet(&bvh, black_box(packet))).sum::<usize>()
+    }));
+    group.finish();
+}
+
+fn sphere(c: &mut Criterion) {
+    let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material());
+    let packets = packets();
+    let mut group = c.benchmark_group("sphere");
+    group.bench_function("scalar f64", |b| b.iter(|| trace_scalar(&sphere, &packets)));
+    group.bench_function(format!("packet x{}", PACKET_WIDTH), |b| b.iter(|| {
+        packets.iter().map(|packet| trace_packet(&sphere, black_box(packet))).sum::<usize>()
+    }));
+    group.finish();
+}
+
+/// The quadratic of a ray-sphere test alone, comparing the vector types.
+fn vector(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(2);
+    let rays: Vec<(Vec3<f64>, Vec3<f64>)> = (0..4096).map(|_| {
+        let initial = Vec3::new(rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0));
+        let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize().vec();
+        (initial, direction)
+    }).collect();
+    let rays_f32: Vec<(Vec3<f32>, Vec3<f32>)> = rays.iter().map(|&(o, d)| (o.cast(), d.cast())).collect();
+    let rays_a: Vec<(Vec3A, Vec3A)> = rays.iter().map(|&(o, d)| (o.into(), d.into())).collect();
+    let mut group = c.benchmark_group("vector");
+    group.bench_function("Vec3<f64>", |b| b.iter(|| {
+        rays.iter().filter(|&&(o, d)| { let b = o.inner_product(d); b * b - (o.squared_len() - 1.0) >= 0.0 }).count()
+    }));
+    group.bench_function("Vec3<f32>", |b| b.iter(|| {
+        rays_f32.iter().filter(|&&(o, d)| { let b = o.inner_product(d); b * b - (o.squared_len() - 1.0) >= 0.0 }).count()
+    }));
+    group.bench_function("Vec3A", |b| b.iter(|| {
+        rays_a.iter().filter(|&&(o, d)| { let b = o.dot(d); b * b - (o.length_squared() - 1.0) >= 0.0 }).count()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bvh, sphere, vector);
+criterion_main!(benches);
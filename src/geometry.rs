@@ -1,5 +1,51 @@
 use std::ops::{Add, Div, Mul, Neg, Sub};
 
+pub mod simd;
+
+/// Scalar type the vector types are generic over, so geometry code runs in single or double precision.
+pub trait Float: Copy + PartialOrd + Add<Output=Self> + Sub<Output=Self> + Mul<Output=Self> + Div<Output=Self> + Neg<Output=Self> {
+    fn sqrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+}
+
+impl Float for f32 {
+    fn sqrt(self) -> Self {
+        f32::sqrt(self)
+    }
+
+    fn abs(self) -> Self {
+        f32::abs(self)
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Float for f64 {
+    fn sqrt(self) -> Self {
+        f64::sqrt(self)
+    }
+
+    fn abs(self) -> Self {
+        f64::abs(self)
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Vec3<V> {
     x: V,
@@ -24,18 +70,18 @@ impl<V> Vec3<V> {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 
-    pub fn outer_product<Rhs: Clone, P: Sub<Output=P>>(self, rhs: Vec3<Rhs>) -> Vec3<P>
-        where V: Mul<Rhs, Output=P> + Clone {
+    pub fn outer_product<Rhs: Copy, P: Sub<Output=P>>(self, rhs: Vec3<Rhs>) -> Vec3<P>
+        where V: Mul<Rhs, Output=P> + Copy {
         Vec3::new(
-            self.y.clone() * rhs.z.clone() - self.z.clone() * rhs.y.clone(),
-            self.z * rhs.x.clone() - self.x.clone() * rhs.z,
+            self.y * rhs.z - self.z * rhs.y,
+            self.z * rhs.x - self.x * rhs.z,
             self.x * rhs.y - self.y * rhs.x,
         )
     }
 
     pub fn squared_len<P: Add<Output=P>>(self) -> P
-        where V: Mul<Output=P>, Self: Clone {
-        self.clone().inner_product(self)
+        where V: Mul<Output=P> + Copy {
+        self.inner_product(self)
     }
 
     pub fn x(&self) -> &V {
@@ -51,8 +97,23 @@ impl<V> Vec3<V> {
     }
 }
 
-impl Vec3<f64> {
-    pub fn normalize(self) -> NormalizedVec3<f64> {
+/// Bound on the relative rounding error accumulated over `n` floating-point operations (Higham 2002).
+pub fn gamma(n: u32) -> f64 {
+    let e = n as f64 * f64::EPSILON * 0.5;
+    e / (1.0 - e)
+}
+
+impl<F: Float> Vec3<F> {
+    pub fn abs(self) -> Self {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
+    /// Converts between precisions, rounding to nearest.
+    pub fn cast<G: Float>(self) -> Vec3<G> {
+        Vec3::new(G::from_f64(self.x.to_f64()), G::from_f64(self.y.to_f64()), G::from_f64(self.z.to_f64()))
+    }
+
+    pub fn normalize(self) -> NormalizedVec3<F> {
         let len = self.squared_len().sqrt();
         NormalizedVec3 {
             x: self.x / len,
@@ -78,6 +139,818 @@ impl<V> NormalizedVec3<V> {
     }
 }
 
+/// Position in space. Unlike a [`Vec3`], it is moved by translations.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Point3<V>(Vec3<V>);
+
+impl<V> Point3<V> {
+    pub fn new(x: V, y: V, z: V) -> Self {
+        Self(Vec3::new(x, y, z))
+    }
+
+    /// Offset from the origin.
+    pub fn vec(self) -> Vec3<V> {
+        self.0
+    }
+}
+
+impl<V> From<Vec3<V>> for Point3<V> {
+    fn from(value: Vec3<V>) -> Self {
+        Self(value)
+    }
+}
+
+impl<V: Add> Add<Vec3<V>> for Point3<V> {
+    type Output = Point3<V::Output>;
+
+    fn add(self, rhs: Vec3<V>) -> Self::Output {
+        Point3(self.0 + rhs)
+    }
+}
+
+impl<V: Sub> Sub<Vec3<V>> for Point3<V> {
+    type Output = Point3<V::Output>;
+
+    fn sub(self, rhs: Vec3<V>) -> Self::Output {
+        Point3(self.0 - rhs)
+    }
+}
+
+impl<V: Sub> Sub for Point3<V> {
+    type Output = Vec3<V::Output>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.0 - rhs.0
+    }
+}
+
+/// Surface normal. Unlike a [`Vec3`], it transforms by the inverse transpose and so stays perpendicular to the surface.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Normal3<V>(Vec3<V>);
+
+impl<V> Normal3<V> {
+    pub fn new(x: V, y: V, z: V) -> Self {
+        Self(Vec3::new(x, y, z))
+    }
+
+    pub fn vec(self) -> Vec3<V> {
+        self.0
+    }
+}
+
+impl<F: Float> Normal3<F> {
+    pub fn normalize(self) -> NormalizedVec3<F> {
+        self.0.normalize()
+    }
+}
+
+impl<V> From<Vec3<V>> for Normal3<V> {
+    fn from(value: Vec3<V>) -> Self {
+        Self(value)
+    }
+}
+
+impl<V> From<NormalizedVec3<V>> for Normal3<V> {
+    fn from(value: NormalizedVec3<V>) -> Self {
+        Self(value.into())
+    }
+}
+
+/// Orthonormal basis whose local z axis is the normal.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Frame {
+    tangent: Vec3<f64>,
+    bitangent: Vec3<f64>,
+    normal: Vec3<f64>,
+}
+
+impl Frame {
+    /// Builds an arbitrary but continuous basis around `normal` (Duff et al. 2017).
+    pub fn from_normal(normal: NormalizedVec3<f64>) -> Self {
+        let n = normal.vec();
+        let sign = 1f64.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        Self {
+            tangent: Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+            bitangent: Vec3::new(b, sign + n.y * n.y * a, -n.y),
+            normal: n,
+        }
+    }
+
+    /// Builds the basis whose tangent is `tangent` projected onto the plane of `normal`.
+    pub fn from_normal_tangent(normal: NormalizedVec3<f64>, tangent: Vec3<f64>) -> Self {
+        let n = normal.vec();
+        let tangent = tangent - n * n.inner_product(tangent);
+        let len = tangent.squared_len().sqrt();
+        if len.is_nan() || len <= 1e-12 {
+            return Self::from_normal(normal);
+        }
+        let tangent = tangent / len;
+        Self {
+            tangent,
+            bitangent: n.outer_product(tangent),
+            normal: n,
+        }
+    }
+
+    pub fn tangent(&self) -> Vec3<f64> {
+        self.tangent
+    }
+
+    pub fn bitangent(&self) -> Vec3<f64> {
+        self.bitangent
+    }
+
+    pub fn normal(&self) -> Vec3<f64> {
+        self.normal
+    }
+
+    pub fn to_world(&self, v: Vec3<f64>) -> Vec3<f64> {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+
+    pub fn to_local(&self, v: Vec3<f64>) -> Vec3<f64> {
+        Vec3::new(self.tangent.inner_product(v), self.bitangent.inner_product(v), self.normal.inner_product(v))
+    }
+}
+
+/// Row-major 3x3 matrix acting on column vectors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Matrix3 {
+    m: [[f64; 3]; 3],
+}
+
+impl Matrix3 {
+    pub const fn new(m: [[f64; 3]; 3]) -> Self {
+        Self { m }
+    }
+
+    pub const fn identity() -> Self {
+        Self::new([
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// The matrix mapping the unit axes onto `x`, `y` and `z`.
+    pub fn from_columns(x: Vec3<f64>, y: Vec3<f64>, z: Vec3<f64>) -> Self {
+        Self::new([
+            [x.x, y.x, z.x],
+            [x.y, y.y, z.y],
+            [x.z, y.z, z.z],
+        ])
+    }
+
+    pub fn get(&self, row: usize, column: usize) -> f64 {
+        self.m[row][column]
+    }
+
+    pub fn row(&self, row: usize) -> Vec3<f64> {
+        Vec3::new(self.m[row][0], self.m[row][1], self.m[row][2])
+    }
+
+    pub fn column(&self, column: usize) -> Vec3<f64> {
+        Vec3::new(self.m[0][column], self.m[1][column], self.m[2][column])
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self::from_columns(self.row(0), self.row(1), self.row(2))
+    }
+
+    pub fn determinant(&self) -> f64 {
+        self.column(0).inner_product(self.column(1).outer_product(self.column(2)))
+    }
+
+    /// Adjugate over determinant; `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let (x, y, z) = (self.column(0), self.column(1), self.column(2));
+        let determinant = x.inner_product(y.outer_product(z));
+        if determinant.abs() < 1e-300 {
+            return None;
+        }
+        // The rows of the inverse are perpendicular to all columns but one.
+        let rows = [y.outer_product(z) / determinant, z.outer_product(x) / determinant, x.outer_product(y) / determinant];
+        Some(Self::new(rows.map(|row| [row.x, row.y, row.z])))
+    }
+}
+
+impl Mul for Matrix3 {
+    type Output = Matrix3;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut m = [[0.0; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.row(i).inner_product(rhs.column(j));
+            }
+        }
+        Matrix3::new(m)
+    }
+}
+
+impl Mul<Vec3<f64>> for Matrix3 {
+    type Output = Vec3<f64>;
+
+    fn mul(self, rhs: Vec3<f64>) -> Self::Output {
+        Vec3::new(self.row(0).inner_product(rhs), self.row(1).inner_product(rhs), self.row(2).inner_product(rhs))
+    }
+}
+
+/// Row-major 4x4 matrix acting on column vectors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Matrix4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub const fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub const fn identity() -> Self {
+        Self::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn get(&self, row: usize, column: usize) -> f64 {
+        self.m[row][column]
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self::new(m)
+    }
+
+    /// The upper-left 3x3 block, which holds the linear part of an affine transform.
+    pub fn linear(&self) -> Matrix3 {
+        let m = &self.m;
+        Matrix3::new([
+            [m[0][0], m[0][1], m[0][2]],
+            [m[1][0], m[1][1], m[1][2]],
+            [m[2][0], m[2][1], m[2][2]],
+        ])
+    }
+
+    /// Gaussian elimination with partial pivoting.
+    pub fn determinant(&self) -> f64 {
+        let mut a = self.m;
+        let mut determinant = 1.0;
+        for column in 0..4 {
+            let pivot = (column..4).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs())).unwrap();
+            if a[pivot][column] == 0.0 {
+                return 0.0;
+            }
+            if pivot != column {
+                a.swap(column, pivot);
+                determinant = -determinant;
+            }
+            determinant *= a[column][column];
+            let pivot_row = a[column];
+            for row in &mut a[column + 1..] {
+                let factor = row[column] / pivot_row[column];
+                for (value, pivot) in row[column..].iter_mut().zip(&pivot_row[column..]) {
+                    *value -= factor * pivot;
+                }
+            }
+        }
+        determinant
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting; `None` for singular matrices and those with non-finite entries.
+    pub fn inverse(&self) -> Option<Self> {
+        if !self.m.iter().flatten().all(|value| value.is_finite()) {
+            return None;
+        }
+        let mut a = self.m;
+        let mut inverse = Self::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+            if a[pivot][column].abs() < 1e-300 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = a[row][column];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[column][j];
+                        inverse[row][j] -= factor * inverse[column][j];
+                    }
+                }
+            }
+        }
+        Some(Self::new(inverse))
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix4::new(m)
+    }
+}
+
+/// Affine transform stored together with its inverse.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transform {
+    pub fn new(matrix: Matrix4) -> Option<Self> {
+        Some(Self { matrix, inverse: matrix.inverse()? })
+    }
+
+    pub const fn identity() -> Self {
+        Self { matrix: Matrix4::identity(), inverse: Matrix4::identity() }
+    }
+
+    pub fn translate(offset: Vec3<f64>) -> Self {
+        let matrix = Matrix4::new([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let inverse = Matrix4::new([
+            [1.0, 0.0, 0.0, -offset.x],
+            [0.0, 1.0, 0.0, -offset.y],
+            [0.0, 0.0, 1.0, -offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Self { matrix, inverse }
+    }
+
+    /// Panics if any factor is zero.
+    pub fn scale(factor: Vec3<f64>) -> Self {
+        assert!(factor.x != 0.0 && factor.y != 0.0 && factor.z != 0.0, "scale must be invertible");
+        let matrix = Matrix4::new([
+            [factor.x, 0.0, 0.0, 0.0],
+            [0.0, factor.y, 0.0, 0.0],
+            [0.0, 0.0, factor.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let inverse = Matrix4::new([
+            [1.0 / factor.x, 0.0, 0.0, 0.0],
+            [0.0, 1.0 / factor.y, 0.0, 0.0],
+            [0.0, 0.0, 1.0 / factor.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Self { matrix, inverse }
+    }
+
+    /// Counter-clockwise rotation by `angle` radians around `axis`.
+    pub fn rotate(axis: NormalizedVec3<f64>, angle: f64) -> Self {
+        let (x, y, z) = (axis.x, axis.y, axis.z);
+        let (sin, cos) = angle.sin_cos();
+        let matrix = Matrix4::new([
+            [x * x + (1.0 - x * x) * cos, x * y * (1.0 - cos) - z * sin, x * z * (1.0 - cos) + y * sin, 0.0],
+            [x * y * (1.0 - cos) + z * sin, y * y + (1.0 - y * y) * cos, y * z * (1.0 - cos) - x * sin, 0.0],
+            [x * z * (1.0 - cos) - y * sin, y * z * (1.0 - cos) + x * sin, z * z + (1.0 - z * z) * cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Self { matrix, inverse: matrix.transpose() }
+    }
+
+    pub fn from_rotation(rotation: Quaternion) -> Self {
+        let matrix = rotation.to_matrix();
+        Self { matrix, inverse: matrix.transpose() }
+    }
+
+    /// Splits a translation * rotation * scale transform into its factors. Shear is not supported.
+    pub fn decompose(&self) -> (Vec3<f64>, Quaternion, Vec3<f64>) {
+        let m = &self.matrix.m;
+        let translation = Vec3::new(m[0][3], m[1][3], m[2][3]);
+        let column = |j: usize| Vec3::new(m[0][j], m[1][j], m[2][j]);
+        let (c0, c1, c2) = (column(0), column(1), column(2));
+        let mut scale = Vec3::new(c0.squared_len().sqrt(), c1.squared_len().sqrt(), c2.squared_len().sqrt());
+        if c0.outer_product(c1).inner_product(c2) < 0.0 {
+            scale.x = -scale.x;
+        }
+        let (r0, r1, r2) = (c0 / scale.x, c1 / scale.y, c2 / scale.z);
+        let rotation = Quaternion::from_matrix([
+            [r0.x, r1.x, r2.x],
+            [r0.y, r1.y, r2.y],
+            [r0.z, r1.z, r2.z],
+        ]);
+        (translation, rotation, scale)
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+
+    /// Applies the transform the way `value`'s type requires.
+    pub fn apply<T: Transformable>(&self, value: T) -> T {
+        value.transform(self)
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    pub fn apply_point(&self, p: Vec3<f64>) -> Vec3<f64> {
+        let m = &self.matrix.m;
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        ) / w
+    }
+
+    pub fn apply_vector(&self, v: Vec3<f64>) -> Vec3<f64> {
+        let m = &self.matrix.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Error bound of [`Transform::apply_point`] on `p`, given the error bound `error` already carried by `p`.
+    /// Assumes an affine transform.
+    pub fn apply_error(&self, p: Vec3<f64>, error: Vec3<f64>) -> Vec3<f64> {
+        let m = &self.matrix.m;
+        let row = |i: usize, v: Vec3<f64>, constant: f64| m[i][0].abs() * v.x + m[i][1].abs() * v.y + m[i][2].abs() * v.z + constant;
+        let p = p.abs();
+        Vec3::new(
+            row(0, error, 0.0) * (1.0 + gamma(3)) + row(0, p, m[0][3].abs()) * gamma(3),
+            row(1, error, 0.0) * (1.0 + gamma(3)) + row(1, p, m[1][3].abs()) * gamma(3),
+            row(2, error, 0.0) * (1.0 + gamma(3)) + row(2, p, m[2][3].abs()) * gamma(3),
+        )
+    }
+
+    /// Normals transform by the inverse transpose so that they stay perpendicular to transformed tangents.
+    pub fn apply_normal(&self, n: Vec3<f64>) -> Vec3<f64> {
+        let m = &self.inverse.m;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    /// Bounds of the eight transformed corners. Boxes reaching infinity stay infinite, since their corners cannot be
+    /// transformed.
+    pub fn apply_aabb(&self, aabb: &Aabb) -> Aabb {
+        if aabb.is_empty() {
+            return *aabb;
+        }
+        if !aabb.is_finite() {
+            return Aabb::infinite();
+        }
+        (0..8).map(|i| {
+            let corner = Vec3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            );
+            self.apply_point(corner)
+        }).fold(Aabb::empty(), |aabb, p| aabb.union(&Aabb::new(p, p)))
+    }
+}
+
+/// Geometric quantities that know how a [`Transform`] acts on them.
+pub trait Transformable {
+    fn transform(self, transform: &Transform) -> Self;
+}
+
+impl Transformable for Point3<f64> {
+    fn transform(self, transform: &Transform) -> Self {
+        Point3(transform.apply_point(self.0))
+    }
+}
+
+impl Transformable for Vec3<f64> {
+    fn transform(self, transform: &Transform) -> Self {
+        transform.apply_vector(self)
+    }
+}
+
+impl Transformable for Normal3<f64> {
+    fn transform(self, transform: &Transform) -> Self {
+        Normal3(transform.apply_normal(self.0))
+    }
+}
+
+impl Transformable for Aabb {
+    fn transform(self, transform: &Transform) -> Self {
+        transform.apply_aabb(&self)
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    /// `a * b` applies `b` first.
+    fn mul(self, rhs: Self) -> Self::Output {
+        Transform { matrix: self.matrix * rhs.matrix, inverse: rhs.inverse * self.inverse }
+    }
+}
+
+/// Unit quaternion representing a rotation.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quaternion {
+    w: f64,
+    v: Vec3<f64>,
+}
+
+impl Quaternion {
+    pub const fn identity() -> Self {
+        Self { w: 1.0, v: Vec3 { x: 0.0, y: 0.0, z: 0.0 } }
+    }
+
+    pub fn from_axis_angle(axis: NormalizedVec3<f64>, angle: f64) -> Self {
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Self { w: cos, v: axis.vec() * sin }
+    }
+
+    /// Converts an orthonormal rotation matrix (Shoemake's method).
+    pub fn from_matrix(m: [[f64; 3]; 3]) -> Self {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        if trace > 0.0 {
+            let s = (trace + 1.0).sqrt();
+            let w = s / 2.0;
+            let s = 0.5 / s;
+            return Self { w, v: Vec3::new((m[2][1] - m[1][2]) * s, (m[0][2] - m[2][0]) * s, (m[1][0] - m[0][1]) * s) };
+        }
+        let i = if m[1][1] > m[0][0] { if m[2][2] > m[1][1] { 2 } else { 1 } } else if m[2][2] > m[0][0] { 2 } else { 0 };
+        let j = (i + 1) % 3;
+        let k = (j + 1) % 3;
+        let s = (m[i][i] - (m[j][j] + m[k][k]) + 1.0).sqrt();
+        let mut q = [0.0; 3];
+        q[i] = s * 0.5;
+        let s = if s != 0.0 { 0.5 / s } else { s };
+        q[j] = (m[j][i] + m[i][j]) * s;
+        q[k] = (m[k][i] + m[i][k]) * s;
+        Self { w: (m[k][j] - m[j][k]) * s, v: Vec3::new(q[0], q[1], q[2]) }
+    }
+
+    pub fn dot(&self, other: &Quaternion) -> f64 {
+        self.w * other.w + self.v.inner_product(other.v)
+    }
+
+    pub fn normalize(self) -> Self {
+        let len = self.dot(&self).sqrt();
+        Self { w: self.w / len, v: self.v / len }
+    }
+
+    /// Spherical linear interpolation along the shorter arc.
+    pub fn slerp(&self, other: &Quaternion, t: f64) -> Self {
+        let mut cos = self.dot(other);
+        let mut other = *other;
+        if cos < 0.0 {
+            cos = -cos;
+            other = Self { w: -other.w, v: -other.v };
+        }
+        if cos > 0.9995 {
+            return Self { w: self.w + (other.w - self.w) * t, v: self.v + (other.v - self.v) * t }.normalize();
+        }
+        let theta = cos.clamp(-1.0, 1.0).acos();
+        let sin = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / sin;
+        let b = (t * theta).sin() / sin;
+        Self { w: self.w * a + other.w * b, v: self.v * a + other.v * b }
+    }
+
+    pub fn conjugate(self) -> Self {
+        Self { w: self.w, v: -self.v }
+    }
+
+    /// Rotates `v`; equivalent to applying [`Quaternion::to_matrix`].
+    pub fn rotate(&self, v: Vec3<f64>) -> Vec3<f64> {
+        let t = self.v.outer_product(v) * 2.0;
+        v + t * self.w + self.v.outer_product(t)
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        let (w, x, y, z) = (self.w, self.v.x, self.v.y, self.v.z);
+        Matrix4::new([
+            [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0],
+            [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0],
+            [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    /// `a * b` rotates by `b` first.
+    fn mul(self, rhs: Self) -> Self::Output {
+        Quaternion {
+            w: self.w * rhs.w - self.v.inner_product(rhs.v),
+            v: rhs.v * self.w + self.v * rhs.w + self.v.outer_product(rhs.v),
+        }
+    }
+}
+
+/// Transform interpolated between keyframes: translation and scale linearly, rotation by slerp.
+/// Before the first and after the last keyframe the transform is held constant. A scale passing through zero, as between
+/// mirrored keyframes, is kept a tiny fraction of its keyframe values away from it, so the object is flat but the
+/// transform stays invertible at that instant.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AnimatedTransform {
+    keyframes: Vec<(f64, Vec3<f64>, Quaternion, Vec3<f64>)>,
+}
+
+impl AnimatedTransform {
+    /// Keyframes must be sorted by time and free of shear.
+    pub fn new(keyframes: Vec<(f64, Transform)>) -> Self {
+        assert!(!keyframes.is_empty(), "at least one keyframe is required");
+        assert!(keyframes.windows(2).all(|w| w[0].0 <= w[1].0), "keyframes must be sorted by time");
+        let keyframes = keyframes.into_iter().map(|(time, transform)| {
+            let (translation, rotation, scale) = transform.decompose();
+            (time, translation, rotation, scale)
+        }).collect();
+        Self { keyframes }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.keyframes.len() > 1
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let index = self.keyframes.iter().position(|keyframe| time < keyframe.0).unwrap_or(self.keyframes.len());
+        let (translation, rotation, scale) = if index == 0 {
+            let (_, t, r, s) = self.keyframes[0];
+            (t, r, s)
+        } else if index == self.keyframes.len() {
+            let (_, t, r, s) = self.keyframes[index - 1];
+            (t, r, s)
+        } else {
+            let (t0, translation0, rotation0, scale0) = self.keyframes[index - 1];
+            let (t1, translation1, rotation1, scale1) = self.keyframes[index];
+            let w = (time - t0) / (t1 - t0);
+            let scale = |s0: f64, s1: f64| {
+                const MIN_SCALE: f64 = 1e-9;
+                let s = s0 + (s1 - s0) * w;
+                let min = MIN_SCALE * s0.abs().max(s1.abs());
+                if s.abs() >= min { s } else if s < 0.0 { -min } else { min }
+            };
+            let scale = Vec3::new(scale(scale0.x, scale1.x), scale(scale0.y, scale1.y), scale(scale0.z, scale1.z));
+            (translation0 + (translation1 - translation0) * w, rotation0.slerp(&rotation1, w), scale)
+        };
+        Transform::translate(translation) * Transform::from_rotation(rotation) * Transform::scale(scale)
+    }
+
+    /// Bounds of `aabb` swept through all keyframes, from sampled transforms padded by the largest step between samples.
+    pub fn motion_bounds(&self, aabb: &Aabb) -> Aabb {
+        const STEPS: usize = 64;
+        let first = self.keyframes[0].0;
+        let last = self.keyframes[self.keyframes.len() - 1].0;
+        if !self.is_animated() || aabb.is_empty() || first == last {
+            return self.at(first).apply_aabb(aabb);
+        }
+        let corners: Vec<_> = (0..8).map(|i| Vec3::new(
+            if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+            if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+            if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+        )).collect();
+        let mut bounds = Aabb::empty();
+        let mut previous: Option<Vec<Vec3<f64>>> = None;
+        let mut padding: f64 = 0.0;
+        for step in 0..=STEPS {
+            let transform = self.at(first + (last - first) * step as f64 / STEPS as f64);
+            let points: Vec<_> = corners.iter().map(|&corner| transform.apply_point(corner)).collect();
+            if let Some(previous) = &previous {
+                for (a, b) in previous.iter().zip(&points) {
+                    padding = padding.max((*a - *b).squared_len().sqrt());
+                }
+            }
+            bounds = points.iter().fold(bounds, |bounds, &p| bounds.union(&Aabb::new(p, p)));
+            previous = Some(points);
+        }
+        let padding = Vec3::new(padding, padding, padding);
+        Aabb::new(bounds.min - padding, bounds.max + padding)
+    }
+}
+
+/// Axis-aligned bounding box. Empty boxes have `min > max`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    min: Vec3<f64>,
+    max: Vec3<f64>,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3<f64>, max: Vec3<f64>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self::new(Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY))
+    }
+
+    pub fn infinite() -> Self {
+        Self::new(Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY), Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY))
+    }
+
+    pub fn min(&self) -> Vec3<f64> {
+        self.min
+    }
+
+    pub fn max(&self) -> Vec3<f64> {
+        self.max
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Whether the box is non-empty and bounded on every side.
+    pub fn is_finite(&self) -> bool {
+        !self.is_empty() && [self.min.x, self.min.y, self.min.z, self.max.x, self.max.y, self.max.z].iter().all(|c| c.is_finite())
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Vec3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            Vec3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        )
+    }
+
+    /// The region inside both boxes, empty if they are disjoint.
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Vec3::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y), self.min.z.max(other.min.z)),
+            Vec3::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y), self.max.z.min(other.max.z)),
+        )
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        !self.intersection(other).is_empty()
+    }
+
+    /// Whether `p` lies inside or on the boundary.
+    pub fn contains(&self, p: Point3<f64>) -> bool {
+        let p = p.vec();
+        (self.min.x..=self.max.x).contains(&p.x) && (self.min.y..=self.max.y).contains(&p.y) && (self.min.z..=self.max.z).contains(&p.z)
+    }
+
+    pub fn center(&self) -> Vec3<f64> {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Index of the longest axis.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.max - self.min;
+        if d.x >= d.y && d.x >= d.z { 0 } else if d.y >= d.z { 1 } else { 2 }
+    }
+
+    /// Slab test; returns the parametric range of `initial + direction * t` inside the box, clipped to `[t_min, t_max]`.
+    pub fn intersect(&self, initial: Vec3<f64>, inverse_direction: Vec3<f64>, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let (min, max, o, inv) = match axis {
+                0 => (self.min.x, self.max.x, initial.x, inverse_direction.x),
+                1 => (self.min.y, self.max.y, initial.y, inverse_direction.y),
+                _ => (self.min.z, self.max.z, initial.z, inverse_direction.z),
+            };
+            let a = (min - o) * inv;
+            let b = (max - o) * inv;
+            // NaN from 0 * inf means a parallel ray lies on the slab boundary, which counts as inside.
+            if a.is_nan() || b.is_nan() {
+                continue;
+            }
+            t0 = t0.max(a.min(b));
+            t1 = t1.min(a.max(b));
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
 impl<V: Add> Add for Vec3<V> {
     type Output = Vec3<V::Output>;
 
@@ -102,25 +975,29 @@ impl<V: Neg> Neg for Vec3<V> {
     }
 }
 
-impl<Rhs: Clone, V: Mul<Rhs>> Mul<Rhs> for Vec3<V> {
+impl<Rhs: Copy, V: Mul<Rhs>> Mul<Rhs> for Vec3<V> {
     type Output = Vec3<V::Output>;
 
     fn mul(self, rhs: Rhs) -> Self::Output {
-        Vec3::new(self.x * rhs.clone(), self.y * rhs.clone(), self.z * rhs)
+        Vec3::new(self.x * rhs, self.y * rhs, self.z * rhs)
     }
 }
 
-impl<Rhs: Clone, V: Div<Rhs>> Div<Rhs> for Vec3<V> {
+impl<Rhs: Copy, V: Div<Rhs>> Div<Rhs> for Vec3<V> {
     type Output = Vec3<V::Output>;
 
     fn div(self, rhs: Rhs) -> Self::Output {
-        Vec3::new(self.x / rhs.clone(), self.y / rhs.clone(), self.z / rhs)
+        Vec3::new(self.x / rhs, self.y / rhs, self.z / rhs)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::geometry::Vec3;
+    use std::f64::consts::PI;
+
+    use proptest::prelude::*;
+
+    use crate::geometry::{Aabb, AnimatedTransform, Frame, Matrix3, Matrix4, Normal3, Point3, Quaternion, Transform, Vec3};
 
     #[test]
     fn vec3_add_test() {
@@ -155,7 +1032,7 @@ mod tests {
     #[test]
     fn vec3_normalize_test() {
         let normalized: Vec3<_> = Vec3::new(1f64, 2f64, 3f64).normalize().into();
-        let len_expect = 3.7416573867739413855837487323165;
+        let len_expect = 3.741_657_386_773_941;
         let diff = normalized - Vec3::new(1f64 / len_expect, 2f64 / len_expect, 3f64 / len_expect);
         assert!(diff.x().abs() < 1e-3);
         assert!(diff.y().abs() < 1e-3);
@@ -168,4 +1045,355 @@ mod tests {
         assert_eq!(Vec3::new(1, 2, 3).y(), &2);
         assert_eq!(Vec3::new(1, 2, 3).z(), &3);
     }
+
+    fn assert_orthonormal(frame: &Frame) {
+        let t = frame.tangent();
+        let b = frame.bitangent();
+        let n = frame.normal();
+        assert!((t.squared_len() - 1.0).abs() < 1e-9);
+        assert!((b.squared_len() - 1.0).abs() < 1e-9);
+        assert!((n.squared_len() - 1.0).abs() < 1e-9);
+        assert!(t.inner_product(b).abs() < 1e-9);
+        assert!(t.inner_product(n).abs() < 1e-9);
+        assert!(b.inner_product(n).abs() < 1e-9);
+        // right-handed
+        let diff = t.outer_product(b) - n;
+        assert!(diff.squared_len() < 1e-9);
+    }
+
+    #[test]
+    fn frame_from_normal_test() {
+        for normal in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), Vec3::new(1.0, 2.0, 3.0), Vec3::new(-0.3, 0.1, -2.0)] {
+            let frame = Frame::from_normal(normal.normalize());
+            assert_orthonormal(&frame);
+            let v = Vec3::new(0.3, -0.4, 0.5);
+            let diff = frame.to_local(frame.to_world(v)) - v;
+            assert!(diff.squared_len() < 1e-18);
+            let n: Vec3<_> = normal.normalize().into();
+            assert!((frame.to_world(Vec3::new(0.0, 0.0, 1.0)) - n).squared_len() < 1e-18);
+        }
+    }
+
+    #[test]
+    fn frame_from_normal_tangent_test() {
+        let frame = Frame::from_normal_tangent(Vec3::new(0.0, 1.0, 0.0).normalize(), Vec3::new(2.0, 1.0, 0.0));
+        assert_orthonormal(&frame);
+        assert!((frame.tangent() - Vec3::new(1.0, 0.0, 0.0)).squared_len() < 1e-18);
+
+        let frame = Frame::from_normal_tangent(Vec3::new(0.0, 1.0, 0.0).normalize(), Vec3::new(0.0, 3.0, 0.0));
+        assert_orthonormal(&frame);
+    }
+
+    fn assert_close(a: Vec3<f64>, b: Vec3<f64>) {
+        assert!((a - b).squared_len() < 1e-18, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn matrix4_inverse_test() {
+        let m = Matrix4::new([
+            [2.0, 0.0, 1.0, 3.0],
+            [1.0, 3.0, 0.0, -1.0],
+            [0.0, 1.0, 4.0, 2.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let product = m * m.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((product.get(i, j) - expected).abs() < 1e-12);
+            }
+        }
+        let singular = Matrix4::new([[1.0, 2.0, 3.0, 4.0], [2.0, 4.0, 6.0, 8.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]);
+        assert!(singular.inverse().is_none());
+        let mut nan = Matrix4::identity();
+        nan.m[1][2] = f64::NAN;
+        assert!(nan.inverse().is_none());
+        assert_eq!(m.transpose().get(0, 3), 0.0);
+        assert_eq!(m.transpose().get(3, 0), 3.0);
+    }
+
+    #[test]
+    fn transform_test() {
+        let transform = Transform::translate(Vec3::new(1.0, 2.0, 3.0)) * Transform::rotate(Vec3::new(0.0, 0.0, 1.0).normalize(), PI / 2.0) * Transform::scale(Vec3::new(2.0, 2.0, 2.0));
+        assert_close(transform.apply_point(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(1.0, 4.0, 3.0));
+        assert_close(transform.apply_vector(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 2.0, 0.0));
+        assert_close(transform.inverse().apply_point(Vec3::new(1.0, 4.0, 3.0)), Vec3::new(1.0, 0.0, 0.0));
+        let general = Transform::new(*transform.matrix()).unwrap();
+        assert_close(general.inverse().apply_point(Vec3::new(1.0, 4.0, 3.0)), Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn transform_error_test() {
+        let transform = Transform::translate(Vec3::new(1e4, 0.0, 0.0)) * Transform::scale(Vec3::new(2.0, 2.0, 2.0));
+        let p = Vec3::new(0.1, 0.2, 0.3);
+        let error = transform.apply_error(p, Vec3::new(1e-12, 0.0, 0.0));
+        assert!(*error.x() >= 2e-12 && *error.x() < 1e-11);
+        // Rounding the translated coordinate alone exceeds any error carried in from the input.
+        assert!(*error.x() > *error.y());
+        assert!(*error.y() > 0.0 && *error.y() < 1e-15);
+    }
+
+    #[test]
+    fn transform_normal_test() {
+        // a plane with normal (1, 1, 0) squashed along x keeps its normal perpendicular to the transformed tangent
+        let transform = Transform::scale(Vec3::new(0.5, 1.0, 1.0));
+        let tangent = transform.apply_vector(Vec3::new(1.0, -1.0, 0.0));
+        let normal = transform.apply_normal(Vec3::new(1.0, 1.0, 0.0));
+        assert!(tangent.inner_product(normal).abs() < 1e-12);
+    }
+
+    #[test]
+    fn aabb_test() {
+        let aabb = Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let hit = aabb.intersect(Vec3::new(-3.0, 0.0, 0.0), Vec3::new(1.0, f64::INFINITY, f64::INFINITY), 0.0, f64::INFINITY);
+        assert_eq!(hit, Some((2.0, 4.0)));
+        assert!(aabb.intersect(Vec3::new(-3.0, 2.0, 0.0), Vec3::new(1.0, f64::INFINITY, f64::INFINITY), 0.0, f64::INFINITY).is_none());
+        assert!(aabb.intersect(Vec3::new(-3.0, 0.0, 0.0), Vec3::new(1.0, f64::INFINITY, f64::INFINITY), 0.0, 1.0).is_none());
+        // on the boundary
+        assert!(aabb.intersect(Vec3::new(-3.0, 1.0, 0.0), Vec3::new(1.0, f64::INFINITY, f64::INFINITY), 0.0, f64::INFINITY).is_some());
+
+        let union = aabb.union(&Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(3.0, 1.0, 1.0)));
+        assert_eq!(union.max(), Vec3::new(3.0, 1.0, 1.0));
+        assert_eq!(union.longest_axis(), 0);
+        assert!(Aabb::empty().is_empty());
+        assert_eq!(Aabb::empty().union(&aabb), aabb);
+
+        let rotated = Transform::rotate(Vec3::new(0.0, 0.0, 1.0).normalize(), PI / 4.0).apply_aabb(&aabb);
+        assert!((*rotated.max().x() - 2f64.sqrt()).abs() < 1e-12);
+        // Infinite corners would turn into NaN.
+        assert!(aabb.is_finite() && !Aabb::empty().is_finite() && !Aabb::infinite().is_finite());
+        assert_eq!(Transform::rotate(Vec3::new(0.0, 0.0, 1.0).normalize(), PI / 4.0).apply_aabb(&Aabb::infinite()), Aabb::infinite());
+    }
+
+    #[test]
+    fn quaternion_test() {
+        let axis = Vec3::new(1.0, 2.0, -1.0).normalize();
+        let q = Quaternion::from_axis_angle(axis, 0.7);
+        let rotation = Transform::from_rotation(q);
+        let expected = Transform::rotate(axis, 0.7);
+        let p = Vec3::new(0.3, -2.0, 1.5);
+        assert_close(rotation.apply_point(p), expected.apply_point(p));
+
+        let m = q.to_matrix();
+        let back = Quaternion::from_matrix([
+            [m.get(0, 0), m.get(0, 1), m.get(0, 2)],
+            [m.get(1, 0), m.get(1, 1), m.get(1, 2)],
+            [m.get(2, 0), m.get(2, 1), m.get(2, 2)],
+        ]);
+        assert!((back.dot(&q).abs() - 1.0).abs() < 1e-12);
+
+        let half = Quaternion::identity().slerp(&Quaternion::from_axis_angle(axis, 1.0), 0.5);
+        assert!((half.dot(&Quaternion::from_axis_angle(axis, 0.5)) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn transform_decompose_test() {
+        let rotation = Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 1.0).normalize(), 2.5);
+        let transform = Transform::translate(Vec3::new(1.0, -2.0, 3.0)) * Transform::from_rotation(rotation) * Transform::scale(Vec3::new(2.0, 0.5, 3.0));
+        let (translation, decomposed, scale) = transform.decompose();
+        assert_close(translation, Vec3::new(1.0, -2.0, 3.0));
+        assert_close(scale, Vec3::new(2.0, 0.5, 3.0));
+        assert!((decomposed.dot(&rotation).abs() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn animated_transform_test() {
+        let animated = AnimatedTransform::new(vec![
+            (0.0, Transform::identity()),
+            (1.0, Transform::translate(Vec3::new(2.0, 0.0, 0.0)) * Transform::rotate(Vec3::new(0.0, 0.0, 1.0).normalize(), PI / 2.0)),
+        ]);
+        assert_close(animated.at(-1.0).apply_point(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(1.0, 0.0, 0.0));
+        assert_close(animated.at(2.0).apply_point(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(2.0, 1.0, 0.0));
+        let half = (PI / 4.0).cos();
+        assert_close(animated.at(0.5).apply_point(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(1.0 + half, half, 0.0));
+
+        let aabb = Aabb::new(Vec3::new(-0.1, -0.1, -0.1), Vec3::new(1.0, 0.1, 0.1));
+        let bounds = animated.motion_bounds(&aabb);
+        for i in 0..=100 {
+            let p = animated.at(i as f64 / 100.0).apply_point(Vec3::new(1.0, 0.0, 0.0));
+            assert!(p.x >= bounds.min.x && p.x <= bounds.max.x && p.y >= bounds.min.y && p.y <= bounds.max.y);
+        }
+    }
+
+    #[test]
+    fn animated_transform_mirrored_test() {
+        // The x scale goes from 2 to -2 and passes through zero halfway.
+        let animated = AnimatedTransform::new(vec![
+            (0.0, Transform::scale(Vec3::new(2.0, 1.0, 1.0))),
+            (1.0, Transform::scale(Vec3::new(-2.0, 1.0, 1.0))),
+        ]);
+        assert_close(animated.at(0.25).apply_point(Vec3::new(1.0, 1.0, 0.0)), Vec3::new(1.0, 1.0, 0.0));
+        let flat = animated.at(0.5);
+        assert!(flat.apply_point(Vec3::new(1.0, 1.0, 0.0)).x.abs() < 1e-6);
+        assert!(flat.inverse().apply_point(Vec3::new(0.0, 1.0, 0.0)).x.is_finite());
+        let bounds = animated.motion_bounds(&Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)));
+        assert!(bounds.min.x <= -2.0 && bounds.max.x >= 2.0);
+    }
+
+    fn vec3() -> impl Strategy<Value=Vec3<f64>> {
+        (-10.0..10.0, -10.0..10.0, -10.0..10.0).prop_map(|(x, y, z)| Vec3::new(x, y, z))
+    }
+
+    fn rotation() -> impl Strategy<Value=Quaternion> {
+        (vec3(), -PI..PI).prop_filter_map("axis too short", |(axis, angle)| {
+            (axis.squared_len() > 1e-2).then(|| Quaternion::from_axis_angle(axis.normalize(), angle))
+        })
+    }
+
+    /// Translation, rotation and a scale bounded away from zero and from extreme anisotropy.
+    fn transform() -> impl Strategy<Value=Transform> {
+        let factor = || prop_oneof![0.2..5.0, -5.0..-0.2];
+        (vec3(), rotation(), factor(), factor(), factor()).prop_map(|(translation, rotation, x, y, z)| {
+            Transform::translate(translation) * Transform::from_rotation(rotation) * Transform::scale(Vec3::new(x, y, z))
+        })
+    }
+
+    fn matrix3() -> impl Strategy<Value=Matrix3> {
+        (vec3(), vec3(), vec3()).prop_map(|(x, y, z)| Matrix3::from_columns(x, y, z))
+    }
+
+    fn matrix4() -> impl Strategy<Value=Matrix4> {
+        prop::array::uniform4(prop::array::uniform4(-10.0..10.0)).prop_map(Matrix4::new)
+    }
+
+    fn assert_near(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance * (1.0 + a.abs().max(b.abs())), "{} != {}", a, b);
+    }
+
+    fn assert_near_vec(a: Vec3<f64>, b: Vec3<f64>, tolerance: f64) {
+        assert_near(a.x, b.x, tolerance);
+        assert_near(a.y, b.y, tolerance);
+        assert_near(a.z, b.z, tolerance);
+    }
+
+    #[test]
+    fn matrix3_test() {
+        let m = Matrix3::new([[2.0, 0.0, 1.0], [1.0, 3.0, 0.0], [0.0, 1.0, 4.0]]);
+        assert_eq!(m.determinant(), 25.0);
+        assert_eq!(m * Matrix3::identity(), m);
+        assert_eq!(m.transpose().get(0, 1), 1.0);
+        assert_eq!(m * Vec3::new(1.0, 1.0, 1.0), Vec3::new(3.0, 4.0, 5.0));
+        assert!(Matrix3::from_columns(Vec3::new(1.0, 2.0, 3.0), Vec3::new(2.0, 4.0, 6.0), Vec3::new(0.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn point_normal_test() {
+        let transform = Transform::translate(Vec3::new(1.0, 2.0, 3.0)) * Transform::scale(Vec3::new(2.0, 1.0, 1.0));
+        assert_eq!(transform.apply(Point3::new(1.0, 0.0, 0.0)), Point3::new(3.0, 2.0, 3.0));
+        assert_eq!(transform.apply(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(2.0, 0.0, 0.0));
+        assert_eq!(transform.apply(Normal3::new(1.0, 0.0, 0.0)), Normal3::new(0.5, 0.0, 0.0));
+        assert_eq!(Point3::new(3.0, 2.0, 3.0) - Point3::new(1.0, 1.0, 1.0), Vec3::new(2.0, 1.0, 2.0));
+        assert_eq!(Point3::new(1.0, 1.0, 1.0) + Vec3::new(2.0, 1.0, 2.0), Point3::new(3.0, 2.0, 3.0));
+    }
+
+    proptest! {
+        #[test]
+        fn matrix3_inverse_property(m in matrix3()) {
+            prop_assume!(m.determinant().abs() > 1.0);
+            let product = m * m.inverse().unwrap();
+            for i in 0..3 {
+                for j in 0..3 {
+                    assert_near(product.get(i, j), if i == j { 1.0 } else { 0.0 }, 1e-9);
+                }
+            }
+            prop_assert_eq!(m.transpose().transpose(), m);
+            assert_near(m.transpose().determinant(), m.determinant(), 1e-12);
+            assert_near(m.inverse().unwrap().determinant() * m.determinant(), 1.0, 1e-9);
+        }
+
+        #[test]
+        fn matrix3_determinant_property(a in matrix3(), b in matrix3()) {
+            assert_near((a * b).determinant(), a.determinant() * b.determinant(), 1e-9);
+        }
+
+        #[test]
+        fn matrix4_inverse_property(m in matrix4()) {
+            prop_assume!(m.determinant().abs() > 1.0);
+            let inverse = m.inverse().unwrap();
+            let product = m * inverse;
+            for i in 0..4 {
+                for j in 0..4 {
+                    assert_near(product.get(i, j), if i == j { 1.0 } else { 0.0 }, 1e-8);
+                }
+            }
+            assert_near(m.transpose().determinant(), m.determinant(), 1e-9);
+            assert_near(inverse.determinant() * m.determinant(), 1.0, 1e-8);
+        }
+
+        #[test]
+        fn matrix4_determinant_property(a in matrix4(), b in matrix4(), transform in transform()) {
+            assert_near((a * b).determinant(), a.determinant() * b.determinant(), 1e-9);
+            // An affine transform scales volumes by its linear part alone.
+            assert_near(transform.matrix().determinant(), transform.matrix().linear().determinant(), 1e-12);
+        }
+
+        #[test]
+        fn quaternion_property(a in rotation(), b in rotation(), v in vec3(), t in 0.0..1.0) {
+            assert_near_vec(a.rotate(v), Transform::from_rotation(a).apply(v), 1e-12);
+            assert_near_vec((a * b).rotate(v), a.rotate(b.rotate(v)), 1e-12);
+            assert_near_vec(a.conjugate().rotate(a.rotate(v)), v, 1e-12);
+            let slerp = a.slerp(&b, t);
+            assert_near(slerp.dot(&slerp), 1.0, 1e-12);
+            assert_near(a.slerp(&b, 0.0).dot(&a).abs(), 1.0, 1e-12);
+            assert_near(a.slerp(&b, 1.0).dot(&b).abs(), 1.0, 1e-12);
+            // Rotation preserves lengths.
+            assert_near(slerp.rotate(v).squared_len(), v.squared_len(), 1e-12);
+        }
+
+        #[test]
+        fn transform_property(transform in transform(), p in vec3(), v in vec3(), w in vec3()) {
+            let (point, inverse) = (Point3::from(p), transform.inverse());
+            assert_near_vec(inverse.apply(transform.apply(point)).vec(), p, 1e-9);
+            // Points move with the translation, vectors only with the linear part.
+            assert_near_vec(transform.apply(point + v) - transform.apply(point), transform.apply(v), 1e-9);
+            // A normal perpendicular to two tangents stays perpendicular to them.
+            let normal = Normal3::from(v.outer_product(w));
+            let n = transform.apply(normal).vec();
+            assert!(n.inner_product(transform.apply(v)).abs() <= 1e-9 * (1.0 + n.squared_len().sqrt() * v.squared_len().sqrt() * 25.0));
+            assert!(n.inner_product(transform.apply(w)).abs() <= 1e-9 * (1.0 + n.squared_len().sqrt() * w.squared_len().sqrt() * 25.0));
+        }
+
+        #[test]
+        fn aabb_property(a in vec3(), b in vec3(), c in vec3(), d in vec3(), p in vec3(), transform in transform()) {
+            let bounds = |a: Vec3<f64>, b: Vec3<f64>| Aabb::new(
+                Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+                Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+            );
+            let (first, second) = (bounds(a, b), bounds(c, d));
+            let (union, intersection) = (first.union(&second), first.intersection(&second));
+            let point = Point3::from(p);
+            if first.contains(point) || second.contains(point) {
+                prop_assert!(union.contains(point));
+            }
+            prop_assert_eq!(intersection.contains(point), first.contains(point) && second.contains(point));
+            prop_assert_eq!(first.overlaps(&second), second.overlaps(&first));
+            prop_assert!(Aabb::empty().intersection(&first).is_empty());
+            if first.contains(point) {
+                let transformed = transform.apply(first);
+                let q = transform.apply(point).vec();
+                let slack = Vec3::new(1e-9, 1e-9, 1e-9) * (1.0 + q.abs().x.max(q.abs().y).max(q.abs().z));
+                prop_assert!(Aabb::new(transformed.min() - slack, transformed.max() + slack).contains(Point3::from(q)));
+            }
+        }
+
+        #[test]
+        fn aabb_slab_property(a in vec3(), b in vec3(), p in vec3(), s in (0.0..1.0, 0.0..1.0, 0.0..1.0)) {
+            let aabb = Aabb::new(
+                Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+                Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+            );
+            // A ray aimed at a point inside the box enters before reaching it and exits after.
+            let size = aabb.max() - aabb.min();
+            let target = aabb.min() + Vec3::new(size.x * s.0, size.y * s.1, size.z * s.2);
+            let direction = target - p;
+            prop_assume!(direction.squared_len() > 1e-6);
+            let inverse_direction = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+            let (t0, t1) = aabb.intersect(p, inverse_direction, f64::NEG_INFINITY, f64::INFINITY).unwrap();
+            prop_assert!(t0 <= 1.0 + 1e-9 && 1.0 - 1e-9 <= t1);
+            let (enter, exit) = (p + direction * t0, p + direction * t1);
+            let slack = Vec3::new(1e-6, 1e-6, 1e-6);
+            let padded = Aabb::new(aabb.min() - slack, aabb.max() + slack);
+            prop_assert!(padded.contains(Point3::from(enter)) && padded.contains(Point3::from(exit)));
+        }
+    }
 }
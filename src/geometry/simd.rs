@@ -0,0 +1,185 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::geometry::Vec3;
+
+/// Single-precision vector padded to 16 bytes, so each operation is one 4-lane vector instruction.
+/// The fourth lane is padding that is computed along but never read.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, align(16))]
+pub struct Vec3A([f32; 4]);
+
+impl Vec3A {
+    #[inline]
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Self([x, y, z, 0.0])
+    }
+
+    #[inline]
+    pub fn splat(value: f32) -> Self {
+        Self::new(value, value, value)
+    }
+
+    #[inline]
+    pub fn x(self) -> f32 {
+        self.0[0]
+    }
+
+    #[inline]
+    pub fn y(self) -> f32 {
+        self.0[1]
+    }
+
+    #[inline]
+    pub fn z(self) -> f32 {
+        self.0[2]
+    }
+
+    #[inline]
+    fn map(self, f: impl Fn(f32) -> f32) -> Self {
+        Self([f(self.0[0]), f(self.0[1]), f(self.0[2]), f(self.0[3])])
+    }
+
+    #[inline]
+    fn zip(self, rhs: Self, f: impl Fn(f32, f32) -> f32) -> Self {
+        Self([f(self.0[0], rhs.0[0]), f(self.0[1], rhs.0[1]), f(self.0[2], rhs.0[2]), f(self.0[3], rhs.0[3])])
+    }
+
+    #[inline]
+    pub fn dot(self, rhs: Self) -> f32 {
+        let p = self.zip(rhs, |a, b| a * b);
+        p.0[0] + p.0[1] + p.0[2]
+    }
+
+    #[inline]
+    pub fn cross(self, rhs: Self) -> Self {
+        let [ax, ay, az, _] = self.0;
+        let [bx, by, bz, _] = rhs.0;
+        Self::new(ay * bz - az * by, az * bx - ax * bz, ax * by - ay * bx)
+    }
+
+    #[inline]
+    pub fn length_squared(self) -> f32 {
+        self.dot(self)
+    }
+
+    #[inline]
+    pub fn length(self) -> f32 {
+        self.length_squared().sqrt()
+    }
+
+    #[inline]
+    pub fn normalize(self) -> Self {
+        self * (1.0 / self.length())
+    }
+
+    #[inline]
+    pub fn abs(self) -> Self {
+        self.map(f32::abs)
+    }
+
+    #[inline]
+    pub fn min(self, rhs: Self) -> Self {
+        self.zip(rhs, f32::min)
+    }
+
+    #[inline]
+    pub fn max(self, rhs: Self) -> Self {
+        self.zip(rhs, f32::max)
+    }
+
+    /// Largest component.
+    #[inline]
+    pub fn max_element(self) -> f32 {
+        self.x().max(self.y()).max(self.z())
+    }
+}
+
+impl PartialEq for Vec3A {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.0[..3] == other.0[..3]
+    }
+}
+
+impl Add for Vec3A {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: Self) -> Self {
+        self.zip(rhs, |a, b| a + b)
+    }
+}
+
+impl Sub for Vec3A {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: Self) -> Self {
+        self.zip(rhs, |a, b| a - b)
+    }
+}
+
+impl Mul<f32> for Vec3A {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, rhs: f32) -> Self {
+        self.map(|a| a * rhs)
+    }
+}
+
+impl Neg for Vec3A {
+    type Output = Self;
+
+    #[inline]
+    fn neg(self) -> Self {
+        self.map(|a| -a)
+    }
+}
+
+impl From<Vec3<f64>> for Vec3A {
+    #[inline]
+    fn from(value: Vec3<f64>) -> Self {
+        Self::new(*value.x() as f32, *value.y() as f32, *value.z() as f32)
+    }
+}
+
+impl From<Vec3<f32>> for Vec3A {
+    #[inline]
+    fn from(value: Vec3<f32>) -> Self {
+        Self::new(*value.x(), *value.y(), *value.z())
+    }
+}
+
+impl From<Vec3A> for Vec3<f32> {
+    #[inline]
+    fn from(value: Vec3A) -> Self {
+        Vec3::new(value.x(), value.y(), value.z())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::simd::Vec3A;
+    use crate::geometry::Vec3;
+
+    #[test]
+    fn vec3a_matches_vec3_test() {
+        let (a, b) = (Vec3::new(1.5f32, -2.0, 0.25), Vec3::new(-0.5f32, 3.0, 4.0));
+        let (wa, wb) = (Vec3A::from(a), Vec3A::from(b));
+        assert_eq!(std::mem::align_of::<Vec3A>(), 16);
+        assert_eq!(Vec3::from(wa + wb), a + b);
+        assert_eq!(Vec3::from(wa - wb), a - b);
+        assert_eq!(Vec3::from(wa * 2.0), a * 2.0);
+        assert_eq!(Vec3::from(wa.cross(wb)), a.outer_product(b));
+        assert_eq!(wa.dot(wb), a.inner_product(b));
+        assert!((wa.normalize().length() - 1.0).abs() < 1e-6);
+        assert_eq!(Vec3::from(wa.min(wb)), Vec3::new(-0.5, -2.0, 0.25));
+        assert_eq!(wb.abs().max_element(), 4.0);
+        // Padding turned NaN by the multiplication does not leak into results.
+        let scaled = Vec3A::new(1.0, 2.0, 3.0) * f32::INFINITY;
+        assert_eq!(scaled, Vec3A::splat(f32::INFINITY));
+        assert_eq!(scaled.dot(Vec3A::splat(1.0)), f32::INFINITY);
+        assert_eq!(Vec3A::from(Vec3::new(0.1f64, 0.2, 0.3)), Vec3A::from(Vec3::new(0.1f64, 0.2, 0.3).cast::<f32>()));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod ray_tracing;
+pub mod geometry;
@@ -1,9 +1,15 @@
-use image::ColorType;
+use std::time::Instant;
 
-use crate::ray_tracing::draw;
+use image::ColorType;
 
-pub mod ray_tracing;
-pub mod geometry;
+use ray_tracing::ray_tracing::{draw, Integrator, RenderOptions};
+use ray_tracing::ray_tracing::aov::{exr, Aov, Layer};
+use ray_tracing::ray_tracing::color_space::{ColorSpace, OutputTransform};
+use ray_tracing::ray_tracing::denoise::Denoiser;
+use ray_tracing::ray_tracing::scene::material::Color;
+use ray_tracing::ray_tracing::scene::medium::Medium;
+use ray_tracing::ray_tracing::sppm::PhotonMapping;
+use ray_tracing::ray_tracing::statistics::Statistics;
 
 fn main() {
     println!("Hello, world!");
@@ -13,14 +19,109 @@ fn main() {
     } else {
         (1920, 1080)
     };
-    let mut buffer = vec![0; (width * height * 4) as usize];
+    let arguments = parse_arguments(std::env::args().skip(1));
+    let mut buffer = vec![0; width * height * 4];
+    let mut statistics = Statistics::new();
 
-    draw(&mut buffer, width, height);
+    let layers = draw(&mut buffer, width, height, &arguments.options, &mut statistics);
 
+    let start = Instant::now();
     write_image(&buffer, width as u32, height as u32);
+    write_layers(&layers, width, height, arguments.exr_path.as_deref());
+    statistics.timing.output = start.elapsed();
+
+    eprintln!("{}", statistics.summary());
+    if let Some(path) = arguments.stats_path {
+        std::fs::write(&path, statistics.to_json()).expect("failed to write statistics");
+    }
+}
+
+struct Arguments {
+    options: RenderOptions,
+    stats_path: Option<String>,
+    exr_path: Option<String>,
+}
+
+fn parse_arguments(mut args: impl Iterator<Item=String>) -> Arguments {
+    let mut arguments = Arguments { options: RenderOptions { progress: true, ..RenderOptions::default() }, stats_path: None, exr_path: None };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("{} requires a value", arg));
+        match arg.as_str() {
+            "--stats" => arguments.stats_path = Some(value()),
+            "--spp" => {
+                let samples_per_pixel = value().parse().expect("invalid --spp");
+                assert!(samples_per_pixel > 0, "--spp must be at least 1");
+                arguments.options.samples_per_pixel = samples_per_pixel;
+            }
+            "--min-depth" => arguments.options.min_depth = value().parse().expect("invalid --min-depth"),
+            "--max-depth" => arguments.options.max_depth = value().parse().expect("invalid --max-depth"),
+            "--shutter" => {
+                let value = value();
+                let (open, close) = value.split_once(',').expect("--shutter expects open,close");
+                arguments.options.shutter = (open.parse().expect("invalid --shutter"), close.parse().expect("invalid --shutter"));
+            }
+            "--spectral" => arguments.options.spectral = true,
+            "--working-space" => arguments.options.working_space = match value().as_str() {
+                "srgb" | "rec709" => ColorSpace::LinearSrgb,
+                "acescg" => ColorSpace::AcesCg,
+                "rec2020" => ColorSpace::Rec2020,
+                "p3" => ColorSpace::DisplayP3,
+                other => panic!("unknown --working-space: {}", other),
+            },
+            "--output" => arguments.options.output = match value().as_str() {
+                "srgb" => OutputTransform::Srgb,
+                "p3" => OutputTransform::DisplayP3,
+                "rec2020-pq" => OutputTransform::Rec2020Pq,
+                "rec2020-hlg" => OutputTransform::Rec2020Hlg,
+                other => panic!("unknown --output: {}", other),
+            },
+            "--aov" => arguments.options.aovs.push(match value().as_str() {
+                "beauty" => Aov::Beauty,
+                "albedo" => Aov::Albedo,
+                "normal" => Aov::Normal,
+                "depth" => Aov::Depth,
+                "position" => Aov::Position,
+                "object-id" => Aov::ObjectId,
+                "material-id" => Aov::MaterialId,
+                "direct" => Aov::Direct,
+                "indirect" => Aov::Indirect,
+                "lights" => Aov::Lights,
+                other => panic!("unknown --aov: {}", other),
+            }),
+            "--integrator" => arguments.options.integrator = match value().as_str() {
+                "path" => Integrator::Path,
+                "bdpt" => Integrator::Bidirectional,
+                "sppm" => Integrator::PhotonMapping(PhotonMapping::default()),
+                other => panic!("unknown --integrator: {}", other),
+            },
+            "--denoise" => arguments.options.denoise = Some(Denoiser::default()),
+            "--exr" => arguments.exr_path = Some(value()),
+            "--fog" => {
+                let density: f64 = value().parse().expect("invalid --fog");
+                let scattering = Color { r: density, g: density, b: density };
+                arguments.options.atmosphere = Some(Medium::homogeneous(Color::zero(), scattering, 0.0));
+            }
+            _ => panic!("unknown argument: {}", arg),
+        }
+    }
+    assert!(arguments.options.atmosphere.is_none() || arguments.options.integrator == Integrator::Path,
+            "--fog requires --integrator path; bdpt and sppm do not trace media");
+    arguments
 }
 
 fn write_image(data: &[u8], width: u32, height: u32) {
     assert_eq!(data.len(), (width * height * 4) as usize);
     image::save_buffer("./img.png", data, width, height, ColorType::Rgba8).expect("failed to write image");
 }
+
+/// Writes all layers to one multi-layer EXR at `path`, or each to its own `./img.<layer>.exr` without one.
+fn write_layers(layers: &[Layer], width: usize, height: usize, path: Option<&str>) {
+    match path {
+        Some(path) => exr::save(path, width, height, layers).expect("failed to write layers"),
+        None => for layer in layers {
+            let path = format!("./img.{}.exr", layer.name);
+            let layer = Layer { name: String::new(), ..layer.clone() };
+            exr::save(path, width, height, &[layer]).expect("failed to write layer");
+        },
+    }
+}
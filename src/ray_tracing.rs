@@ -5,121 +5,1012 @@ use rand::{Rng, thread_rng};
 use rayon::iter::{IndexedParallelIterator, IntoParallelIterator};
 use rayon::iter::ParallelIterator;
 
-use crate::geometry::{NormalizedVec3, Vec3};
+use crate::geometry::{Frame, NormalizedVec3, Vec3};
+use crate::ray_tracing::aov::{layers, Aov, FirstHit, Layer, PathRecord, PixelAovs};
+use crate::ray_tracing::bdpt::{Scene, SplatFilm};
+use crate::ray_tracing::color_space::{ColorSpace, OutputTransform};
+use crate::ray_tracing::denoise::{Denoiser, Features};
+use crate::ray_tracing::packet::PACKET_WIDTH;
+#[cfg(feature = "packets")]
+use crate::ray_tracing::packet::RayPacket;
 use crate::ray_tracing::scene::camera::Camera;
-use crate::ray_tracing::scene::Collision;
-use crate::ray_tracing::scene::material::{Color, Material};
+use crate::ray_tracing::scene::{offset_ray_origin, Collision, SurfaceInteraction, SurfaceDifferentials};
+use crate::ray_tracing::scene::material::{fresnel_conductor, Color, Material, ShadingGeometry};
+use crate::ray_tracing::scene::medium::{Medium, MediumSample};
+use crate::ray_tracing::scene::texture::TextureCoordinate;
+use crate::ray_tracing::scene::object::plane::{Disk, Plane};
 use crate::ray_tracing::scene::object::Sphere;
+use crate::ray_tracing::spectrum::{Radiance, SampledSpectrum, SampledWavelengths, RGB_WAVELENGTHS};
+use crate::ray_tracing::sppm::PhotonMapping;
+use crate::ray_tracing::statistics::{PathCounters, Progress, Statistics};
 
+pub mod aov;
+pub mod bdpt;
+pub mod color_space;
+pub mod denoise;
+pub mod packet;
 pub mod scene;
+pub mod spectrum;
+pub mod sppm;
+pub mod statistics;
+#[cfg(test)]
+pub mod test_util;
 
+#[derive(Debug, Clone)]
 pub struct Ray {
     initial: Vec3<f64>,
     direction: NormalizedVec3<f64>,
+    /// Rays through the neighbouring pixels, used to estimate texture footprints.
+    differential: Option<RayDifferential>,
+    /// Instant within the camera shutter interval at which the ray samples the scene.
+    time: f64,
 }
 
-pub fn draw(buffer: &mut [u8], width: usize, height: usize) {
+impl Ray {
+    pub fn new(initial: Vec3<f64>, direction: NormalizedVec3<f64>, time: f64) -> Self {
+        Self { initial, direction, differential: None, time }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferential {
+    x_initial: Vec3<f64>,
+    x_direction: NormalizedVec3<f64>,
+    y_initial: Vec3<f64>,
+    y_direction: NormalizedVec3<f64>,
+}
+
+impl RayDifferential {
+    /// Angle by which a diffuse bounce spreads the footprint, standing in for the width of the lobe.
+    const DIFFUSE_SPREAD: f64 = 1.0 / 16.0;
+
+    /// Differentials of the mirror reflection of `ray` into `reflected`, following pbrt's specular reflection.
+    pub fn reflect(ray: &Ray, initial: Vec3<f64>, normal: Vec3<f64>, dndu: Vec3<f64>, dndv: Vec3<f64>, differentials: &SurfaceDifferentials, reflected: Vec3<f64>) -> Option<Self> {
+        let differential = ray.differential?;
+        let wo = -ray.direction.vec();
+        let dndx = dndu * differentials.duv_dx.0 + dndv * differentials.duv_dx.1;
+        let dndy = dndu * differentials.duv_dy.0 + dndv * differentials.duv_dy.1;
+        let direction = |offset_direction: NormalizedVec3<f64>, dndx: Vec3<f64>| {
+            let dwodx = -offset_direction.vec() - wo;
+            let ddndx = dwodx.inner_product(normal) + wo.inner_product(dndx);
+            (reflected - dwodx + (dndx * wo.inner_product(normal) + normal * ddndx) * 2.0).normalize()
+        };
+        Some(Self {
+            x_initial: initial + differentials.dpdx,
+            x_direction: direction(differential.x_direction, dndx),
+            y_initial: initial + differentials.dpdy,
+            y_direction: direction(differential.y_direction, dndy),
+        })
+    }
+
+    fn diffuse(ray: &Ray, initial: Vec3<f64>, differentials: &SurfaceDifferentials, direction: NormalizedVec3<f64>) -> Option<Self> {
+        ray.differential?;
+        let frame = Frame::from_normal(direction);
+        Some(Self {
+            x_initial: initial + differentials.dpdx,
+            x_direction: (direction.vec() + frame.tangent() * Self::DIFFUSE_SPREAD).normalize(),
+            y_initial: initial + differentials.dpdy,
+            y_direction: (direction.vec() + frame.bitangent() * Self::DIFFUSE_SPREAD).normalize(),
+        })
+    }
+}
+
+/// Bends a perturbed shading normal towards the geometric normal until the outgoing direction `wo`
+/// is in front of it; otherwise normal maps viewed at grazing angles shade the back of the surface.
+fn adapt_shading_normal(shading_normal: Vec3<f64>, normal: Vec3<f64>, wo: Vec3<f64>) -> Vec3<f64> {
+    const MIN_COSINE: f64 = 1e-3;
+    let cosine = wo.inner_product(shading_normal);
+    if cosine >= MIN_COSINE {
+        return shading_normal;
+    }
+    let t = (MIN_COSINE - cosine) / wo.inner_product(normal).max(MIN_COSINE);
+    (shading_normal + normal * t).normalize().vec()
+}
+
+/// Cosine-weighted direction around `normal`, with its cosine.
+fn sample_cosine(normal: Vec3<f64>, tangent: Vec3<f64>, rng: &mut impl Rng) -> (Vec3<f64>, f64) {
+    let frame = Frame::from_normal_tangent(normal.normalize(), tangent);
+    let r: f64 = rng.gen_range(0.0..1.0);
+    let phi = rng.gen_range(0.0..PI * 2.0);
+    let cosine = (1.0 - r).sqrt();
+    (frame.to_world(Vec3::new(r.sqrt() * phi.cos(), r.sqrt() * phi.sin(), cosine)), cosine)
+}
+
+/// Whether `direction` leaves a surface with geometric normal `normal` on the wrong side for a reflection or, if
+/// `transmitted`, a refraction. Following it would leak light through the surface.
+fn leaks(direction: Vec3<f64>, normal: Vec3<f64>, transmitted: bool) -> bool {
+    (direction.inner_product(normal) > 0.0) == transmitted
+}
+
+/// Material and shading frame at a surface hit, resolved alike by every integrator.
+struct SurfacePoint<'a> {
+    differentials: SurfaceDifferentials,
+    coordinate: TextureCoordinate,
+    material: &'a Material,
+    /// Geometric frame with the normal facing the incoming ray.
+    geometry: ShadingGeometry,
+    /// Shading normal, bent where needed to face the incoming ray as well.
+    shading_normal: Vec3<f64>,
+}
+
+impl<'a> SurfacePoint<'a> {
+    fn new(interaction: &mut SurfaceInteraction<'a>, ray: &Ray) -> Self {
+        let differentials = interaction.differentials(ray);
+        let coordinate = interaction.texture_coordinate(&differentials);
+        let material = interaction.resolve_material(&coordinate);
+        let geometry = interaction.geometry();
+        let shading_normal = adapt_shading_normal(interaction.shading().normal, geometry.normal, -ray.direction.vec());
+        Self { differentials, coordinate, material, geometry, shading_normal }
+    }
+
+    /// Describes this point as the first surface seen by a camera path, `distance` along it, among `objects`.
+    fn first_hit<T: Collision>(&self, objects: &[T], interaction: &SurfaceInteraction, distance: f64) -> FirstHit {
+        let material = objects.iter().flat_map(|object| object.materials())
+            .position(|material| std::ptr::eq(material, interaction.material))
+            .expect("hits borrow the materials of the scene");
+        FirstHit {
+            albedo: self.material.albedo(&self.coordinate),
+            normal: self.shading_normal,
+            distance,
+            position: interaction.position,
+            object_id: interaction.primitive_id,
+            material,
+        }
+    }
+}
+
+/// Direction leaving a mirror-like or refractive surface, not normalized, with the factor it multiplies the throughput by.
+struct DeltaScatter<S> {
+    direction: Vec3<f64>,
+    weight: S,
+    transmitted: bool,
+}
+
+/// Samples how a [`Material::Conductor`] or [`Material::Dielectric`] scatters light arriving along `direction`.
+/// Dispersive dielectrics pick one wavelength, or one channel, of `throughput` to follow.
+#[allow(clippy::too_many_arguments)]
+fn scatter_delta<S: Radiance>(material: &Material, direction: Vec3<f64>, shading_normal: Vec3<f64>, front_face: bool, coordinate: &TextureCoordinate,
+                              throughput: &S, wavelengths: &mut S::Wavelengths, rng: &mut impl Rng) -> DeltaScatter<S> {
+    let cosine = -direction.inner_product(shading_normal);
+    let (weight, roughness) = match material {
+        Material::Conductor { eta, k, roughness } => {
+            let (eta, k) = (S::spectrum(eta, wavelengths), S::spectrum(k, wavelengths));
+            (S::from_fn(|i| fresnel_conductor(cosine, eta.channel(i), k.channel(i))), roughness)
+        }
+        Material::Dielectric { ior } => {
+            let (index, weight) = if ior.is_dispersive() {
+                let (lambda, weight) = S::select_channel(throughput, wavelengths, rng.gen_range(0.0..1.0));
+                (ior.evaluate(lambda), weight)
+            } else {
+                (ior.evaluate(RGB_WAVELENGTHS[1]), S::splat(1.0))
+            };
+            // Index of the far side relative to this one. Radiance is not scaled by its square on transmission:
+            // the factors cancel for lights outside every dielectric.
+            let eta = if front_face { index } else { 1.0 / index };
+            let sin2_t = (1.0 - cosine * cosine) / (eta * eta);
+            let reflect = sin2_t >= 1.0 || rng.gen_range(0.0..1.0) < fresnel_conductor(cosine, eta, 0.0);
+            let direction = if reflect {
+                direction + shading_normal * (2.0 * cosine)
+            } else {
+                direction / eta + shading_normal * (cosine / eta - (1.0 - sin2_t).sqrt())
+            };
+            return DeltaScatter { direction, weight, transmitted: !reflect };
+        }
+        _ => unreachable!("only conductors and dielectrics scatter deterministically"),
+    };
+    let reflected = direction + shading_normal * (2.0 * cosine);
+    let fuzz = loop {
+        let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        if p.squared_len() <= 1.0 { break p; }
+    };
+    DeltaScatter { direction: reflected + fuzz * roughness.evaluate_scalar(coordinate), weight, transmitted: false }
+}
+
+/// Light transport algorithm used by [`draw`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    /// Unidirectional path tracing from the camera.
+    Path,
+    /// Bidirectional path tracing (see [`bdpt::trace`]); suits light that arrives through small openings or caustics.
+    /// Participating media are ignored.
+    Bidirectional,
+    /// Stochastic progressive photon mapping; converges on caustics seen through mirrors and glass. Participating
+    /// media are ignored.
+    PhotonMapping(PhotonMapping),
+}
+
+pub struct RenderOptions {
+    pub samples_per_pixel: usize,
+    /// Number of bounces before Russian roulette may terminate a path.
+    pub min_depth: usize,
+    /// Hard limit on the number of bounces, as a safety net for nearly lossless scenes.
+    pub max_depth: usize,
+    /// Open and close times of the camera shutter; rays are spread uniformly over this interval.
+    pub shutter: (f64, f64),
+    /// Medium filling the scene outside every [`Material::Filled`] object, such as fog.
+    pub atmosphere: Option<Medium>,
+    /// Traces a few wavelengths per path instead of RGB, uplifting colours to spectra and converting through CIE XYZ.
+    pub spectral: bool,
+    /// Space of every colour in the scene and of the rendered radiance.
+    pub working_space: ColorSpace,
+    /// Encoding of the written image.
+    pub output: OutputTransform,
+    /// Auxiliary images that [`draw`] returns besides the beauty image.
+    pub aovs: Vec<Aov>,
+    /// Filter applied to the beauty image after rendering, guided by the first visible surfaces.
+    pub denoise: Option<Denoiser>,
+    pub integrator: Integrator,
+    /// Draws a progress bar on stderr while rendering.
+    pub progress: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            samples_per_pixel: 100,
+            min_depth: 3,
+            max_depth: 64,
+            shutter: (0.0, 0.0),
+            atmosphere: None,
+            spectral: false,
+            working_space: ColorSpace::LinearSrgb,
+            output: OutputTransform::Srgb,
+            aovs: Vec::new(),
+            denoise: None,
+            integrator: Integrator::Path,
+            progress: false,
+        }
+    }
+}
+
+/// Renders the scene into `buffer` as encoded RGBA, returning the layers of the requested [`RenderOptions::aovs`].
+pub fn draw(buffer: &mut [u8], width: usize, height: usize, options: &RenderOptions, statistics: &mut Statistics) -> Vec<Layer> {
+    let start = Instant::now();
     let camera = Camera::new(Vec3::new(0.0, 0.0, 4.0),
                              Vec3::new(0.0, 0.0, -1.0).normalize(),
                              Vec3::new(0.0, -1.0, 0.0).normalize(),
                              Vec3::new(1.0, 0.0, 0.0).normalize(),
-                             width, height, PI / 4.0);
-    let objects = [
-        Sphere::new(Vec3::new(1e6 + 1f64, 0.0, 0.0), 1e6, Material::Solid { color: Color { r: 0.0, g: 1.0, b: 0.0 }, illuminate: Color::zero() }),
-        Sphere::new(Vec3::new(-1e6 - 1f64, 0.0, 0.0), 1e6, Material::Solid { color: Color { r: 1.0, g: 0.0, b: 0.0 }, illuminate: Color::zero() }),
-        Sphere::new(Vec3::new(0.0, -1e6 - 1f64, 0.0), 1e6, Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }, illuminate: Color::zero() }),
-        Sphere::new(Vec3::new(0.0, 1e6 + 1f64, 0.0), 1e6, Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }, illuminate: Color::zero() }),
-        Sphere::new(Vec3::new(0.0, 0.0, -1e6 - 1f64), 1e6, Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }, illuminate: Color::zero() }),
-        Sphere::new(Vec3::new(0.5, -0.75, 0.5), 0.25, Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }, illuminate: Color::zero() }),
-        Sphere::new(Vec3::new(-0.5, -0.75, -0.5), 0.25, Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }, illuminate: Color::zero() }),
-        Sphere::new(Vec3::new(0.0, 1e3 + 0.9999, 0.0), 1e3, Material::Solid { color: Color::zero(), illuminate: Color { r: 1.0, g: 1.0, b: 1.0 } }),
+                             width, height, PI / 4.0)
+        .with_shutter(options.shutter.0, options.shutter.1);
+    let objects: Vec<Box<dyn Collision>> = vec![
+        Box::new(Plane::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0).normalize(), Material::Solid { color: Color { r: 0.0, g: 1.0, b: 0.0 }.into(), illuminate: Color::zero().into() })),
+        Box::new(Plane::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0).normalize(), Material::Solid { color: Color { r: 1.0, g: 0.0, b: 0.0 }.into(), illuminate: Color::zero().into() })),
+        Box::new(Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0).normalize(), Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }.into(), illuminate: Color::zero().into() })),
+        Box::new(Plane::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0).normalize(), Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }.into(), illuminate: Color::zero().into() })),
+        Box::new(Plane::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0).normalize(), Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }.into(), illuminate: Color::zero().into() })),
+        Box::new(Sphere::new(Vec3::new(0.5, -0.75, 0.5), 0.25, Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }.into(), illuminate: Color::zero().into() })),
+        Box::new(Sphere::new(Vec3::new(-0.5, -0.75, -0.5), 0.25, Material::Solid { color: Color { r: 1.0, g: 1.0, b: 1.0 }.into(), illuminate: Color::zero().into() })),
+        Box::new(Disk::new(Vec3::new(0.0, 0.9999, 0.0), Vec3::new(0.0, -1.0, 0.0).normalize(), 0.45, Material::Solid { color: Color::zero().into(), illuminate: Color { r: 1.0, g: 1.0, b: 1.0 }.into() })),
     ];
-    let mut result = Vec::with_capacity(width * height);
+    statistics.timing.scene_build = start.elapsed();
     let start = Instant::now();
-    (0..height).map(|y| (0..width).map(move |x| (x, y))).flatten().collect::<Vec<_>>().into_par_iter().map(|(x, y)| {
-        let mut rng = thread_rng();
-        let mut color_sum = Color::zero();
-        const COUNT: usize = 100;
-        let x = x as f64;
-        let y = y as f64;
-        for _ in 0..COUNT {
-            let mut ray = camera.create_ray(rng.gen_range(x..x + 1.0), rng.gen_range(y..y + 1.0));
-            let mut throughput = Color { r: 1.0, g: 1.0, b: 1.0 };
-            let mut light = Color { r: 0.0, g: 0.0, b: 0.0 };
-            for _ in 0..10 {
-                let mut collision: Option<(f64, NormalizedVec3<f64>, Material)> = None;
-                for object in &objects {
-                    let current = object.collision(&ray);
-                    match (&mut collision, &current) {
-                        (Some(collision), Some(current)) => {
-                            if current.0 < collision.0 {
-                                *collision = current.clone();
+    let counters = &statistics.counters;
+    let record = !options.aovs.is_empty() || options.denoise.is_some();
+    let scene = Scene::new(&objects, &camera);
+    let film = SplatFilm::new(width, height);
+    let result = if let Integrator::PhotonMapping(photon_mapping) = options.integrator {
+        photon_mapping.render(&scene, width, height, options, counters)
+    } else {
+        let mut result = Vec::with_capacity(width * height);
+        let progress = Progress::new(width * height, options.progress);
+        (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).collect::<Vec<_>>().into_par_iter().map(|(x, y)| {
+            let mut rng = thread_rng();
+            let mut path_counters = PathCounters::default();
+            let mut color_sum = Color::zero();
+            let mut luminance_squares = 0.0;
+            let mut aovs = PixelAovs::default();
+            let x = x as f64;
+            let y = y as f64;
+            for first in (0..options.samples_per_pixel).step_by(PACKET_WIDTH) {
+                let rays: Vec<Ray> = (first..options.samples_per_pixel.min(first + PACKET_WIDTH)).map(|_| {
+                    let time = camera.sample_time(rng.gen_range(0.0..1.0));
+                    camera.create_ray(rng.gen_range(x..x + 1.0), rng.gen_range(y..y + 1.0), time)
+                }).collect();
+                // Path tracing finds the first hits of a pixel's camera rays together, so they can share packets.
+                let mut hits = if options.integrator == Integrator::Path { camera_hits(&objects, &rays, &mut path_counters) } else { Vec::new() }.into_iter();
+                for ray in rays {
+                    let hit = hits.next().flatten();
+                    let color = if options.spectral {
+                        let mut wavelengths = SampledWavelengths::sample_visible(rng.gen_range(0.0..1.0)).with_working_space(options.working_space);
+                        let mut path = PathRecord::default();
+                        let spectrum: SampledSpectrum = match options.integrator {
+                            Integrator::Path => trace_path_from(&objects, ray, hit, options, &mut wavelengths, &mut rng, &mut path_counters, record.then_some(&mut path)),
+                            Integrator::Bidirectional => {
+                                let (spectrum, splats) = bdpt::trace(&scene, ray, options, &mut wavelengths, &mut rng, &mut path_counters, record.then_some(&mut path));
+                                for (raster, splat) in splats {
+                                    film.add(raster, &wavelengths.to_rgb(&splat));
+                                }
+                                spectrum
                             }
+                            Integrator::PhotonMapping(_) => unreachable!("photon mapping renders every pixel at once"),
+                        };
+                        aovs.add(path, |spectrum| wavelengths.to_rgb(spectrum));
+                        wavelengths.to_rgb(&spectrum)
+                    } else {
+                        let mut path = PathRecord::default();
+                        let color = match options.integrator {
+                            Integrator::Path => trace_path_from(&objects, ray, hit, options, &mut (), &mut rng, &mut path_counters, record.then_some(&mut path)),
+                            Integrator::Bidirectional => {
+                                let (color, splats) = bdpt::trace(&scene, ray, options, &mut (), &mut rng, &mut path_counters, record.then_some(&mut path));
+                                for (raster, splat) in splats {
+                                    film.add(raster, &splat);
+                                }
+                                color
+                            }
+                            Integrator::PhotonMapping(_) => unreachable!("photon mapping renders every pixel at once"),
+                        };
+                        aovs.add(path, Color::clone);
+                        color
+                    };
+                    luminance_squares += options.working_space.luminance(&color).powi(2);
+                    color_sum = color_sum + color;
+                }
+            }
+            counters.record(&path_counters);
+            progress.advance(1);
+            let count = options.samples_per_pixel as f64;
+            let color = Color { r: color_sum.r / count, g: color_sum.g / count, b: color_sum.b / count };
+            // Variance of the pixel's mean luminance; a single sample is its own crude estimate.
+            let luminance = options.working_space.luminance(&color);
+            let variance = if count > 1.0 { (luminance_squares / count - luminance * luminance).max(0.0) / (count - 1.0) } else { luminance_squares };
+            (color, variance, aovs)
+        }).collect_into_vec(&mut result);
+        progress.finish();
+        result
+    };
+    statistics.timing.render = start.elapsed();
+    let (mut beauty, mut variance, mut aovs) = (Vec::with_capacity(result.len()), Vec::with_capacity(result.len()), Vec::with_capacity(result.len()));
+    for (color, pixel_variance, pixel) in result {
+        beauty.push(color);
+        variance.push(pixel_variance);
+        aovs.push(pixel);
+    }
+    // Light tracing reaches pixels at random, so its image only averages out over the samples of the whole frame.
+    if options.integrator == Integrator::Bidirectional {
+        for (color, splat) in beauty.iter_mut().zip(film.colors(1.0 / options.samples_per_pixel as f64)) {
+            *color = color.clone() + splat;
+        }
+    }
+    if let Some(denoiser) = &options.denoise {
+        let start = Instant::now();
+        let features: Vec<_> = aovs.iter().map(|pixel| Features { albedo: pixel.albedo(), normal: pixel.normal(), depth: pixel.depth() }).collect();
+        beauty = denoiser.denoise(&beauty, &variance, &features, width, height, options.working_space);
+        statistics.timing.denoise = start.elapsed();
+    }
+    for (pixel, color) in buffer.chunks_exact_mut(4).zip(&beauty) {
+        let signal = options.output.encode(color, options.working_space);
+        pixel.copy_from_slice(&[signal.r, signal.g, signal.b, 1.0].map(|c| (c * 255.0).round() as u8));
+    }
+    layers(&options.aovs, &beauty, &aovs)
+}
+
+/// Nearest hit of `ray` among `objects` closer than `t_max`, labelled with the index of the object hit.
+fn nearest_hit<'a, T: Collision>(objects: &'a [T], ray: &Ray, t_max: f64, path_counters: &mut PathCounters) -> Option<SurfaceInteraction<'a>> {
+    path_counters.rays_traced += 1;
+    path_counters.intersection_tests += objects.len() as u64;
+    let mut collision: Option<SurfaceInteraction> = None;
+    for (id, object) in objects.iter().enumerate() {
+        // Only hits closer than the nearest so far can replace it.
+        let t_max = collision.as_ref().map_or(t_max, |hit| hit.distance);
+        if let Some(current) = object.collision(ray, 0.0, t_max) {
+            collision = Some(SurfaceInteraction { primitive_id: id, ..current });
+        }
+    }
+    collision
+}
+
+/// Whether anything but interfaces lies between `from` and `to`, tested with [`Collision::occluded`] shadow rays.
+fn occluded<T: Collision>(objects: &[T], from: Vec3<f64>, to: Vec3<f64>, time: f64, path_counters: &mut PathCounters) -> bool {
+    let offset = to - from;
+    let distance = offset.squared_len().sqrt();
+    if distance == 0.0 {
+        return false;
+    }
+    path_counters.rays_traced += 1;
+    path_counters.intersection_tests += objects.len() as u64;
+    let ray = Ray::new(from, offset.normalize(), time);
+    objects.iter().any(|object| object.occluded(&ray, distance))
+}
+
+/// Nearest hits of camera rays, found one at a time by [`nearest_hit`].
+#[cfg(not(feature = "packets"))]
+fn camera_hits<'a, T: Collision>(objects: &'a [T], rays: &[Ray], path_counters: &mut PathCounters) -> Vec<Option<SurfaceInteraction<'a>>> {
+    rays.iter().map(|ray| nearest_hit(objects, ray, f64::INFINITY, path_counters)).collect()
+}
+
+/// Nearest hits of camera rays, traced [`PACKET_WIDTH`] at a time with [`Collision::collision_packet`]. Packets may solve
+/// in single precision, which camera rays tolerate as they start away from every surface.
+#[cfg(feature = "packets")]
+fn camera_hits<'a, T: Collision>(objects: &'a [T], rays: &[Ray], path_counters: &mut PathCounters) -> Vec<Option<SurfaceInteraction<'a>>> {
+    let mut hits = Vec::with_capacity(rays.len());
+    for chunk in rays.chunks(PACKET_WIDTH) {
+        // Lanes past the end of the last chunk repeat its final ray and stay inactive.
+        let packet = RayPacket::new(std::array::from_fn(|i| chunk[i.min(chunk.len() - 1)].clone()));
+        let active = std::array::from_fn(|i| i < chunk.len());
+        let mut t_max = [f64::INFINITY; PACKET_WIDTH];
+        let mut nearest = std::array::from_fn(|_| None);
+        for (id, object) in objects.iter().enumerate() {
+            let before = t_max;
+            object.collision_packet(&packet, &active, 0.0, &mut t_max, &mut nearest);
+            for ((hit, before), after) in nearest.iter_mut().zip(before.iter()).zip(t_max.iter()) {
+                if after < before {
+                    if let Some(hit) = hit {
+                        hit.primitive_id = id;
+                    }
+                }
+            }
+        }
+        path_counters.rays_traced += chunk.len() as u64;
+        path_counters.intersection_tests += (chunk.len() * objects.len()) as u64;
+        hits.extend(IntoIterator::into_iter(nearest).take(chunk.len()));
+    }
+    hits
+}
+
+/// Emitting objects of a scene, chosen uniformly when sampling lights.
+pub struct Lights {
+    /// Index of each light in the scene and its surface area.
+    lights: Vec<(usize, f64)>,
+}
+
+impl Lights {
+    pub fn new<T: Collision>(objects: &[T]) -> Self {
+        let lights = objects.iter().enumerate()
+            .filter_map(|(id, object)| {
+                let (point, area) = object.sample_area((0.5, 0.5), 0.0)?;
+                point.material.emits().then_some((id, area))
+            })
+            .collect();
+        Self { lights }
+    }
+
+    /// Index of a light chosen uniformly with `u` in `[0, 1)`, or `None` without lights.
+    pub fn choose(&self, u: f64) -> Option<usize> {
+        let index = ((u * self.lights.len() as f64) as usize).min(self.lights.len().checked_sub(1)?);
+        Some(self.lights[index].0)
+    }
+
+    /// Density per unit area of choosing a point on object `id`; zero if it is not a light.
+    pub fn pdf(&self, id: usize) -> f64 {
+        self.lights.iter().find(|(light, _)| *light == id).map_or(0.0, |(_, area)| 1.0 / (self.lights.len() as f64 * area))
+    }
+}
+
+/// Light arriving at `position` inside `medium` from a point sampled by area on a uniformly chosen light, with the direction
+/// towards it, the density of that direction per unit solid angle and the light's index. The shadow ray passes through
+/// interfaces, estimating the transmittance of every medium it crosses by ratio tracking.
+#[allow(clippy::too_many_arguments)]
+fn sample_light<'a, T: Collision, S: Radiance>(objects: &'a [T], lights: &Lights, position: Vec3<f64>, mut medium: Option<&'a Medium>, options: &'a RenderOptions,
+                                               time: f64, wavelengths: &S::Wavelengths, rng: &mut impl Rng, path_counters: &mut PathCounters) -> Option<(NormalizedVec3<f64>, S, f64, usize)> {
+    let id = lights.choose(rng.gen_range(0.0..1.0))?;
+    let (mut point, _) = objects[id].sample_area((rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)), time)?;
+    let offset = point.position - position;
+    if offset.squared_len() == 0.0 {
+        return None;
+    }
+    let direction = offset.normalize();
+    let cosine = point.normal.vec().inner_product(direction.vec()).abs();
+    if cosine == 0.0 {
+        return None;
+    }
+    let pdf = lights.pdf(id) * offset.squared_len() / cosine;
+    let coordinate = point.texture_coordinate(&SurfaceDifferentials::zero());
+    let mut radiance = match point.resolve_material(&coordinate) {
+        Material::Solid { illuminate, .. } => S::illuminant(&illuminate.evaluate(&coordinate), wavelengths),
+        _ => return None,
+    };
+    if radiance.max_value() <= 0.0 {
+        return None;
+    }
+    let target = offset_ray_origin(point.position, point.error, point.normal.vec(), -offset);
+    if occluded(objects, position, target, time, path_counters) {
+        return None;
+    }
+    // Only interfaces remain on the way, each of which may lead into another medium.
+    let mut from = position;
+    loop {
+        let segment = target - from;
+        let length = segment.squared_len().sqrt();
+        if length == 0.0 {
+            return Some((direction, radiance, pdf, id));
+        }
+        let ray = Ray::new(from, segment.normalize(), time);
+        let hit = nearest_hit(objects, &ray, length, path_counters);
+        if let Some(medium) = medium {
+            let t_max = hit.as_ref().map_or(length, |hit| hit.distance);
+            radiance = radiance * medium.transmittance(&ray, t_max, wavelengths, rng);
+        }
+        match hit {
+            None => return Some((direction, radiance, pdf, id)),
+            Some(hit) if hit.material.is_interface() => {
+                if let Some(inside) = hit.material.medium() {
+                    medium = if hit.front_face { Some(inside) } else { options.atmosphere.as_ref() };
+                }
+                from = offset_ray_origin(hit.position, hit.error, hit.normal.vec(), segment);
+            }
+            Some(_) => return None,
+        }
+    }
+}
+
+/// Traces one camera path from scratch, for tests that start paths by hand.
+#[cfg(test)]
+fn trace_path<T: Collision, S: Radiance>(objects: &[T], ray: Ray, options: &RenderOptions, wavelengths: &mut S::Wavelengths, rng: &mut impl Rng, path_counters: &mut PathCounters, record: Option<&mut PathRecord<S>>) -> S {
+    let collision = nearest_hit(objects, &ray, f64::INFINITY, path_counters);
+    trace_path_from(objects, ray, collision, options, wavelengths, rng, path_counters, record)
+}
+
+/// Traces one camera path whose first hit `collision` was found beforehand, also filling `record` with what the path saw if given.
+#[allow(clippy::too_many_arguments)]
+fn trace_path_from<'a, T: Collision, S: Radiance>(objects: &'a [T], mut ray: Ray, mut collision: Option<SurfaceInteraction<'a>>, options: &RenderOptions, wavelengths: &mut S::Wavelengths,
+                                                  rng: &mut impl Rng, path_counters: &mut PathCounters, mut record: Option<&mut PathRecord<S>>) -> S {
+    let mut throughput = S::splat(1.0);
+    let mut light = S::zero();
+    path_counters.samples += 1;
+    // Filled objects may not overlap, so leaving one always returns the ray to the atmosphere.
+    let mut medium = options.atmosphere.as_ref();
+    // Interfaces crossed and distance travelled through them, so AOVs see past invisible surfaces.
+    let mut crossings = 0;
+    let mut travelled = 0.0;
+    // Built at the first scattering in a medium, the only vertices that sample lights.
+    let mut lights: Option<Lights> = None;
+    // Where the current ray last scattered in a medium and the density of its direction there. Lights were sampled at
+    // that point too, so the light this ray finds is weighted against that strategy by the balance heuristic.
+    let mut scattered: Option<(Vec3<f64>, f64)> = None;
+    for depth in 0..options.max_depth {
+        let bounces = depth - crossings;
+        if depth > 0 {
+            collision = nearest_hit(objects, &ray, f64::INFINITY, path_counters);
+        }
+        let t_max = collision.as_ref().map_or(f64::INFINITY, |hit| hit.distance);
+        let sample = medium.map(|medium| {
+            let (sample, emitted) = medium.sample(&ray, t_max, wavelengths, rng);
+            let emitted = throughput.clone() * emitted;
+            if let Some(record) = record.as_deref_mut() {
+                record.add(&emitted, bounces, None);
+            }
+            light = light.clone() + emitted;
+            sample
+        });
+        match &sample {
+            Some(MediumSample::Passed { weight }) => throughput = throughput * weight.clone(),
+            Some(MediumSample::Absorbed) => return light,
+            _ => {}
+        }
+        if let (Some(current), Some(MediumSample::Scattered { distance, weight })) = (medium, sample) {
+            path_counters.path_length_total += 1;
+            let position = ray.initial + ray.direction.vec() * distance;
+            throughput = throughput * weight;
+            let lights = lights.get_or_insert_with(|| Lights::new(objects));
+            if let Some((direction, radiance, pdf, id)) = sample_light::<_, S>(objects, lights, position, medium, options, ray.time, wavelengths, rng, path_counters) {
+                // Phase sampling is exact, so the phase function is also the density of scattering towards the light.
+                let phase = current.phase().evaluate(ray.direction.vec().inner_product(direction.vec()));
+                let contribution = throughput.clone() * radiance.map(|c| c * phase / (pdf + phase));
+                if let Some(record) = record.as_deref_mut() {
+                    record.add(&contribution, bounces + 1, Some(id));
+                }
+                light = light + contribution;
+            }
+            let direction = current.phase().sample(ray.direction, (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)));
+            scattered = Some((position, current.phase().evaluate(ray.direction.vec().inner_product(direction.vec()))));
+            ray = Ray::new(position, direction, ray.time);
+        } else if let Some(mut interaction) = collision.take() {
+            let point = SurfacePoint::new(&mut interaction, &ray);
+            path_counters.path_length_total += 1;
+            if let Some(record) = record.as_deref_mut().filter(|record| bounces == 0 && record.first_hit.is_none()) {
+                if !matches!(point.material, Material::Interface) {
+                    record.first_hit = Some(point.first_hit(objects, &interaction, travelled + interaction.distance));
+                }
+            }
+            let SurfacePoint { differentials, coordinate, material, geometry, shading_normal } = point;
+            let ShadingGeometry { normal, dndu, dndv, .. } = geometry;
+            let SurfaceInteraction { position, error, dpdu, .. } = interaction;
+            let direction: Vec3<_> = ray.direction.into();
+            match material {
+                Material::Solid { color, illuminate } => {
+                    let weight = match (scattered.take(), &lights) {
+                        (Some((origin, phase)), Some(lights)) => {
+                            let cosine = interaction.normal.vec().inner_product(direction).abs();
+                            let pdf = lights.pdf(interaction.primitive_id) * (position - origin).squared_len() / cosine;
+                            phase / (phase + pdf)
                         }
-                        (ref mut collision, Some(current)) => {
-                            collision.replace(current.clone());
-                        }
-                        _ => {}
+                        _ => 1.0,
+                    };
+                    let emitted = throughput.clone() * S::illuminant(&illuminate.evaluate(&coordinate), wavelengths).map(|c| c * weight);
+                    if let Some(record) = record.as_deref_mut() {
+                        record.add(&emitted, bounces, Some(interaction.primitive_id));
                     }
+                    light = light + emitted;
+                    let (direction, _) = sample_cosine(shading_normal, dpdu, rng);
+                    if leaks(direction, normal, false) {
+                        return light;
+                    }
+                    let direction = direction.normalize();
+                    let initial = offset_ray_origin(position, error, normal, direction.vec());
+                    ray = Ray {
+                        initial,
+                        direction,
+                        differential: RayDifferential::diffuse(&ray, initial, &differentials, direction),
+                        time: ray.time,
+                    };
+                    throughput = throughput * S::albedo(&color.evaluate(&coordinate), wavelengths);
                 }
-                if let Some((d, normal, material)) = collision {
-                    let normal: Vec3<f64> = normal.into();
-                    match material {
-                        Material::Solid { color, illuminate } => {
-                            ray = {
-                                let x_inner = normal.inner_product(Vec3::new(1.0, 0.0, 0.0)).abs();
-                                let y_inner = normal.inner_product(Vec3::new(0.0, 1.0, 0.0)).abs();
-                                let u = if x_inner < y_inner {
-                                    normal.outer_product(Vec3::new(1.0, 0.0, 0.0))
-                                } else {
-                                    normal.outer_product(Vec3::new(0.0, 1.0, 0.0))
-                                };
-                                let v = u.clone().outer_product(normal).normalize();
-                                let u = u.normalize();
-
-                                if false {
-                                    let theta = rng.gen_range(0.0..PI / 2.0);
-                                    let phi = rng.gen_range(0.0..PI * 2.0);
-
-                                    let direction: Vec3<_> = ray.direction.into();
-                                    let initial = ray.initial + direction * d + normal * 1e-4;
-                                    let direction = u.vec() * phi.cos() * theta.sin() + v.vec() * phi.sin() * theta.sin() + normal * theta.sin();
-                                    Ray {
-                                        initial,
-                                        direction: direction.normalize(),
-                                    }
-                                } else {
-                                    let r: f64 = rng.gen_range(0.0..1.0);
-                                    let r = r.sqrt();
-                                    let phi = rng.gen_range(0.0..PI * 2.0);
-
-                                    let direction: Vec3<_> = ray.direction.into();
-                                    let initial = ray.initial + direction * d + normal * 1e-4;
-                                    let direction = u.vec() * phi.cos() * r + v.vec() * phi.sin() * r + normal * (1.0 - r * r);
-                                    Ray {
-                                        initial,
-                                        direction: direction.normalize(),
-                                    }
-                                }
-                            };
-                            light = light + throughput.clone() * illuminate;
-                            throughput = throughput * color;
+                Material::Conductor { .. } | Material::Dielectric { .. } => {
+                    scattered = None;
+                    let scatter = scatter_delta(material, direction, shading_normal, interaction.front_face, &coordinate, &throughput, wavelengths, rng);
+                    if leaks(scatter.direction, normal, scatter.transmitted) {
+                        return light;
+                    }
+                    if scatter.transmitted {
+                        if let Some(inside) = interaction.material.medium() {
+                            medium = if interaction.front_face { Some(inside) } else { options.atmosphere.as_ref() };
                         }
                     }
-                } else {
-                    break;
+                    let scattered = scatter.direction.normalize();
+                    let initial = offset_ray_origin(position, error, normal, scattered.vec());
+                    let differential = if scatter.transmitted {
+                        None
+                    } else {
+                        RayDifferential::reflect(&ray, initial, shading_normal, dndu, dndv, &differentials, scattered.vec())
+                    };
+                    ray = Ray { initial, direction: scattered, differential, time: ray.time };
+                    throughput = throughput * scatter.weight;
+                }
+                Material::Interface => {
+                    if let Some(inside) = interaction.material.medium() {
+                        medium = if interaction.front_face { Some(inside) } else { options.atmosphere.as_ref() };
+                    }
+                    let initial = offset_ray_origin(position, error, normal, direction);
+                    ray = Ray { initial, ..ray };
+                    crossings += 1;
+                    travelled += interaction.distance;
                 }
-                if throughput.r <= 1e-4 && throughput.g <= 1e-4 && throughput.b <= 1e-4 { break; }
+                Material::Bumped { .. } | Material::Filled { .. } => unreachable!("resolve strips bump and fill layers"),
+            }
+        } else {
+            path_counters.escaped += 1;
+            return light;
+        }
+        if depth + 1 >= options.min_depth {
+            let survival = throughput.max_value().min(1.0);
+            if survival <= 0.0 || rng.gen_range(0.0..1.0) >= survival {
+                path_counters.terminated_by_roulette += 1;
+                return light;
             }
-            color_sum = color_sum + light;
-        }
-        Color { r: color_sum.r / COUNT as f64, g: color_sum.g / COUNT as f64, b: color_sum.b / COUNT as f64 }
-    }).collect_into_vec(&mut result);
-    println!("Time: {}ms", start.elapsed().as_millis());
-    for i in 0..width * height {
-        buffer[i * 4] = (result[i].r.powf(1.0 / 2.5) * 256.0) as u8;
-        buffer[i * 4 + 1] = (result[i].g.powf(1.0 / 2.5) * 256.0) as u8;
-        buffer[i * 4 + 2] = (result[i].b.powf(1.0 / 2.5) * 256.0) as u8;
-        buffer[i * 4 + 3] = 255;
+            throughput = throughput.map(|c| c / survival);
+        }
+    }
+    path_counters.terminated_by_depth += 1;
+    light
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::{Aabb, Vec3};
+    use crate::ray_tracing::{adapt_shading_normal, camera_hits, draw, nearest_hit, RenderOptions, Ray, RayDifferential, trace_path};
+    use crate::ray_tracing::aov::{Aov, PathRecord};
+    use crate::ray_tracing::denoise::Denoiser;
+    use crate::ray_tracing::scene::Collision;
+    use crate::ray_tracing::scene::material::{Color, Ior, Material};
+    use crate::ray_tracing::scene::medium::grid::VoxelGrid;
+    use crate::ray_tracing::scene::medium::Medium;
+    use crate::ray_tracing::scene::object::plane::Plane;
+    use crate::ray_tracing::scene::object::Sphere;
+    use crate::ray_tracing::spectrum::{reference_white, SampledSpectrum, SampledWavelengths, Spectrum, RGB_WAVELENGTHS};
+    use crate::ray_tracing::statistics::{PathCounters, Statistics};
+    use crate::ray_tracing::test_util::Counting;
+
+    #[test]
+    fn white_furnace_test() {
+        // Closed sphere with albedo a and emission e seen from inside: L = e / (1 - a).
+        let albedo = 0.8;
+        let emission = 0.5;
+        let objects = [Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Solid {
+            color: Color { r: albedo, g: albedo, b: albedo }.into(),
+            illuminate: Color { r: emission, g: emission, b: emission }.into(),
+        })];
+        let options = RenderOptions { samples_per_pixel: 1, min_depth: 1, max_depth: 1000, ..RenderOptions::default() };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut path_counters = PathCounters::default();
+        const COUNT: usize = 20000;
+        let mut sum = 0.0;
+        for _ in 0..COUNT {
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let ray = Ray { initial: Vec3::new(0.0, 0.0, 0.0), direction: direction.normalize(), differential: None, time: 0.0 };
+            sum += trace_path::<_, Color>(&objects, ray, &options, &mut (), &mut rng, &mut path_counters, None).g;
+        }
+        let expected = emission / (1.0 - albedo);
+        let estimate = sum / COUNT as f64;
+        assert!((estimate - expected).abs() < expected * 0.02, "estimate {} expected {}", estimate, expected);
+        assert_eq!(path_counters.terminated_by_depth, 0);
+        assert!(path_counters.terminated_by_roulette > 0);
+    }
+
+    #[test]
+    fn spectral_white_furnace_test() {
+        // Uplifted greys are flat, so the spectral estimate matches the RGB one after conversion.
+        let objects = [Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Solid {
+            color: Color { r: 0.8, g: 0.8, b: 0.8 }.into(),
+            illuminate: Color { r: 0.5, g: 0.5, b: 0.5 }.into(),
+        })];
+        let options = RenderOptions { samples_per_pixel: 1, min_depth: 1, max_depth: 1000, spectral: true, ..RenderOptions::default() };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut path_counters = PathCounters::default();
+        const COUNT: usize = 20000;
+        let mut sum = Color::zero();
+        for _ in 0..COUNT {
+            let mut wavelengths = SampledWavelengths::sample_visible(rng.gen_range(0.0..1.0));
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0).normalize(), 0.0);
+            let spectrum: SampledSpectrum = trace_path(&objects, ray, &options, &mut wavelengths, &mut rng, &mut path_counters, None);
+            sum = sum + wavelengths.to_rgb(&spectrum);
+        }
+        for channel in [sum.r, sum.g, sum.b] {
+            assert!((channel / COUNT as f64 - 2.5).abs() < 2.5 * 0.03, "{:?}", sum);
+        }
+    }
+
+    #[test]
+    fn conductor_test() {
+        // A smooth conductor inside a white emitter reflects its Fresnel reflectance at normal incidence.
+        let (eta, k) = (Spectrum::sampled(vec![(400.0, 1.5), (700.0, 0.2)]), Spectrum::sampled(vec![(400.0, 1.9), (700.0, 3.6)]));
+        let conductor = Material::Conductor { eta: eta.clone(), k: k.clone(), roughness: Color::zero().into() };
+        let objects: Vec<Box<dyn Collision>> = vec![Box::new(emitter(10.0)), Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, conductor))];
+        let options = RenderOptions { samples_per_pixel: 1, min_depth: 10, ..RenderOptions::default() };
+        let mut rng = StdRng::seed_from_u64(0);
+        let ray = || Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0).normalize(), 0.0);
+        let color = trace_path::<_, Color>(&objects, ray(), &options, &mut (), &mut rng, &mut PathCounters::default(), None);
+        let reflectance = |lambda: f64| {
+            let (n, k) = (eta.evaluate(lambda), k.evaluate(lambda));
+            ((n - 1.0).powi(2) + k * k) / ((n + 1.0).powi(2) + k * k)
+        };
+        assert!((color.r - reflectance(RGB_WAVELENGTHS[0])).abs() < 1e-9, "{:?}", color);
+        assert!((color.b - reflectance(RGB_WAVELENGTHS[2])).abs() < 1e-9, "{:?}", color);
+
+        let mut wavelengths = SampledWavelengths::sample_visible(0.7);
+        let spectrum: SampledSpectrum = trace_path(&objects, ray(), &options, &mut wavelengths, &mut rng, &mut PathCounters::default(), None);
+        for (i, &lambda) in wavelengths.lambda().iter().enumerate() {
+            let expected = reflectance(lambda) * reference_white(lambda);
+            assert!((spectrum.0[i] - expected).abs() < 1e-9, "{:?}", spectrum);
+        }
+    }
+
+    #[test]
+    fn dielectric_test() {
+        // Clear glass neither absorbs nor emits, so every path reaches the surrounding emitter in every channel,
+        // whether or not dispersion restricts it to one.
+        let mut rng = StdRng::seed_from_u64(3);
+        let options = RenderOptions { samples_per_pixel: 1, min_depth: 10, ..RenderOptions::default() };
+        const COUNT: usize = 4000;
+        for ior in [Ior::Constant(1.5), Ior::diamond()] {
+            let glass = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Dielectric { ior: ior.clone() });
+            let objects: Vec<Box<dyn Collision>> = vec![Box::new(emitter(10.0)), Box::new(glass)];
+            let (mut rgb, mut spectral) = (Color::zero(), Color::zero());
+            let mut terminated = 0;
+            for _ in 0..COUNT {
+                let ray = Ray::new(Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), -5.0), Vec3::new(0.0, 0.0, 1.0).normalize(), 0.0);
+                rgb = rgb + trace_path::<_, Color>(&objects, ray.clone(), &options, &mut (), &mut rng, &mut PathCounters::default(), None);
+                let mut wavelengths = SampledWavelengths::sample_visible(rng.gen_range(0.0..1.0));
+                let spectrum: SampledSpectrum = trace_path(&objects, ray, &options, &mut wavelengths, &mut rng, &mut PathCounters::default(), None);
+                spectral = spectral + wavelengths.to_rgb(&spectrum);
+                terminated += wavelengths.is_secondary_terminated() as usize;
+            }
+            for channel in [rgb.r, rgb.g, rgb.b, spectral.r, spectral.g, spectral.b] {
+                assert!((channel / COUNT as f64 - 1.0).abs() < 0.1, "{:?} {:?} {:?}", ior, rgb, spectral);
+            }
+            // Only rays that hit dispersive glass lose their secondary wavelengths.
+            if ior.is_dispersive() {
+                assert!(terminated > COUNT / 2 && terminated < COUNT);
+            } else {
+                assert_eq!(terminated, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn aov_test() {
+        // A glowing grey sphere seen through the interface of a thin liquid, inside a white emitter.
+        let grey = Material::Solid { color: Color { r: 0.5, g: 0.5, b: 0.5 }.into(), illuminate: Color { r: 0.2, g: 0.2, b: 0.2 }.into() };
+        let liquid = Material::Filled {
+            material: Box::new(Material::Interface),
+            medium: Medium::homogeneous(Color { r: 0.1, g: 0.1, b: 0.1 }, Color::zero(), 0.0),
+        };
+        let objects: Vec<Box<dyn Collision>> = vec![
+            Box::new(emitter(10.0)),
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, grey)),
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 2.0, liquid)),
+            Box::new(Sphere::new(Vec3::new(0.0, -6.0, 0.0), 2.0, Material::Solid { color: Color { r: 0.5, g: 0.5, b: 0.5 }.into(), illuminate: Color::zero().into() })),
+        ];
+        let options = RenderOptions { samples_per_pixel: 1, min_depth: 10, ..RenderOptions::default() };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sources = Vec::new();
+        for _ in 0..100 {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0).normalize(), 0.0);
+            let mut record = PathRecord::default();
+            let color = trace_path::<_, Color>(&objects, ray, &options, &mut (), &mut rng, &mut PathCounters::default(), Some(&mut record));
+
+            let hit = record.first_hit.as_ref().unwrap();
+            assert_eq!(hit.object_id, 1);
+            assert_eq!(hit.material, 1);
+            assert_eq!((hit.albedo.r, hit.albedo.g, hit.albedo.b), (0.5, 0.5, 0.5));
+            assert!((hit.distance - 4.0).abs() < 1e-9, "{}", hit.distance);
+            assert!((hit.position - Vec3::new(0.0, 0.0, -1.0)).squared_len::<f64>() < 1e-18);
+            assert!((hit.normal.normalize().vec() - Vec3::new(0.0, 0.0, -1.0)).squared_len::<f64>() < 1e-18);
+
+            // Every contribution is either direct or indirect and, without emitting media, comes from some object.
+            let split = record.direct.clone() + record.indirect.clone();
+            let lights = record.lights.iter().fold(Color::zero(), |sum, (_, light)| sum + light.clone());
+            for total in [split, lights] {
+                assert!((total.g - color.g).abs() < 1e-9, "{:?} {:?}", total, color);
+            }
+            sources.extend(record.lights.iter().map(|(id, _)| *id));
+            // The glow of the sphere itself is seen directly.
+            assert!(record.direct.g >= 0.2 * (-0.1f64).exp() - 1e-9, "{:?}", record);
+        }
+        // Light layers exist for the emitters alone, not for the interface or the dark sphere below.
+        sources.sort_unstable();
+        sources.dedup();
+        assert_eq!(sources, [0, 1]);
+    }
+
+    #[test]
+    fn denoise_draw_test() {
+        // The built-in scene at preview sample counts comes much closer to a converged render once denoised.
+        let (width, height) = (32, 24);
+        let render = |samples_per_pixel, denoise| {
+            let options = RenderOptions { samples_per_pixel, aovs: vec![Aov::Beauty], denoise, ..RenderOptions::default() };
+            let mut buffer = vec![0; width * height * 4];
+            draw(&mut buffer, width, height, &options, &mut Statistics::new()).remove(0).data
+        };
+        let reference = render(128, None);
+        let mse = |image: &[f32]| image.iter().zip(&reference).map(|(a, b)| (a - b).powi(2)).sum::<f32>() / image.len() as f32;
+        let noisy = mse(&render(4, None));
+        let denoised = mse(&render(4, Some(Denoiser::default())));
+        assert!(denoised < noisy / 2.0, "{} {}", noisy, denoised);
+    }
+
+    fn emitter(radius: f64) -> Sphere {
+        Sphere::new(Vec3::new(0.0, 0.0, 0.0), radius, Material::Solid { color: Color::zero().into(), illuminate: Color { r: 1.0, g: 1.0, b: 1.0 }.into() })
+    }
+
+    #[test]
+    fn absorbing_medium_test() {
+        // A beam through the middle of a liquid sphere inside a white emitter loses e^(-2 sigma_a) and nothing after leaving it.
+        let liquid = Material::Filled {
+            material: Box::new(Material::Interface),
+            medium: Medium::homogeneous(Color { r: 1.0, g: 0.5, b: 0.0 }, Color::zero(), 0.0),
+        };
+        let objects: Vec<Box<dyn Collision>> = vec![Box::new(emitter(10.0)), Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, liquid))];
+        let options = RenderOptions { samples_per_pixel: 1, min_depth: 10, ..RenderOptions::default() };
+        let mut rng = StdRng::seed_from_u64(0);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0).normalize(), 0.0);
+        let color = trace_path::<_, Color>(&objects, ray, &options, &mut (), &mut rng, &mut PathCounters::default(), None);
+        assert!((color.r - (-2.0f64).exp()).abs() < 1e-9, "{:?}", color);
+        assert!((color.g - (-1.0f64).exp()).abs() < 1e-9, "{:?}", color);
+        assert!((color.b - 1.0).abs() < 1e-9, "{:?}", color);
+    }
+
+    #[test]
+    fn scattering_atmosphere_test() {
+        // Without absorption every path eventually reaches the surrounding emitter unattenuated.
+        let objects = [emitter(1.0)];
+        let options = RenderOptions {
+            samples_per_pixel: 1,
+            min_depth: 1,
+            max_depth: 1000,
+            atmosphere: Some(Medium::homogeneous(Color::zero(), Color { r: 2.0, g: 1.0, b: 0.5 }, 0.6)),
+            ..RenderOptions::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut path_counters = PathCounters::default();
+        let mut sum = Color::zero();
+        const COUNT: usize = 2000;
+        for _ in 0..COUNT {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0).normalize(), 0.0);
+            sum = sum + trace_path::<_, Color>(&objects, ray, &options, &mut (), &mut rng, &mut path_counters, None);
+        }
+        for channel in [sum.r, sum.g, sum.b] {
+            assert!((channel / COUNT as f64 - 1.0).abs() < 0.1, "{:?}", sum);
+        }
+        // Scattering events count towards the path length.
+        assert!(path_counters.path_length_total > COUNT as u64 * 2);
+    }
+
+    #[test]
+    fn grid_atmosphere_test() {
+        // Lights sampled from inside a lossless cloud are seen through its ratio-tracked transmittance; the estimate
+        // still converges to the unattenuated emitter around it.
+        let cloud = Medium::grid(Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)), VoxelGrid::from_noise([16, 16, 16], 3.0, 3),
+                                 Color::zero(), Color { r: 8.0, g: 4.0, b: 2.0 }, 0.3);
+        let objects = [Counting::new(emitter(2.0))];
+        let options = RenderOptions { samples_per_pixel: 1, min_depth: 1, max_depth: 1000, atmosphere: Some(cloud), ..RenderOptions::default() };
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut path_counters = PathCounters::default();
+        let mut sum = Color::zero();
+        const COUNT: usize = 4000;
+        for _ in 0..COUNT {
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let ray = Ray::new(Vec3::new(0.1, -0.2, 0.0), direction.normalize(), 0.0);
+            sum = sum + trace_path::<_, Color>(&objects, ray, &options, &mut (), &mut rng, &mut path_counters, None);
+        }
+        for channel in [sum.r, sum.g, sum.b] {
+            assert!((channel / COUNT as f64 - 1.0).abs() < 0.05, "{:?}", sum);
+        }
+        // Shadow rays are traced besides the path itself, without looking for the nearest hit.
+        assert!(path_counters.rays_traced > path_counters.path_length_total);
+        assert!(objects[0].occluded_count() > COUNT / 2);
+    }
+
+    #[test]
+    fn ray_differential_test() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let direction = Vec3::new(0.0, 0.0, -1.0).normalize();
+        let ray = Ray {
+            initial: Vec3::new(0.0, 0.0, 5.0),
+            direction,
+            differential: Some(RayDifferential {
+                x_initial: Vec3::new(0.01, 0.0, 5.0),
+                x_direction: direction,
+                y_initial: Vec3::new(0.0, 0.01, 5.0),
+                y_direction: direction,
+            }),
+            time: 0.0,
+        };
+        let hit = sphere.collision(&ray, 0.0, f64::INFINITY).unwrap();
+        let differentials = hit.differentials(&ray);
+        assert!((*differentials.dpdx.x() - 0.01).abs() < 1e-9);
+        assert!((differentials.duv_dx.0 - 0.01 / (2.0 * std::f64::consts::PI)).abs() < 1e-9);
+        assert!(differentials.duv_dx.1.abs() < 1e-9);
+        assert!((differentials.duv_dy.1 - 0.01 / std::f64::consts::PI).abs() < 1e-9);
+
+        // parallel rays reflected by a convex mirror diverge by twice the change of the normal
+        let normal = hit.normal.vec();
+        let reflected = Vec3::new(0.0, 0.0, 1.0);
+        let reflection = RayDifferential::reflect(&ray, hit.position, normal, hit.dndu, hit.dndv, &differentials, reflected).unwrap();
+        let x_direction = reflection.x_direction.vec();
+        assert!((*x_direction.x() - 0.02).abs() < 1e-3);
+        assert!((*reflection.x_initial.x() - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn adapt_shading_normal_test() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let wo = Vec3::new(1.0, 0.0, 0.1).normalize().vec();
+        let facing = Vec3::new(0.6, 0.0, 0.8);
+        assert_eq!(adapt_shading_normal(facing, normal, wo), facing);
+
+        let away = Vec3::new(-0.8, 0.0, 0.6);
+        let adapted = adapt_shading_normal(away, normal, wo);
+        assert!(wo.inner_product(adapted) > 0.0);
+        assert!((adapted.squared_len() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn camera_hits_test() {
+        let material = Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() };
+        let objects: Vec<Box<dyn Collision>> = vec![
+            Box::new(Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0).normalize(), material.clone())),
+            Box::new(Sphere::new(Vec3::new(0.3, -0.5, 0.0), 0.5, material.clone())),
+            Box::new(Sphere::new(Vec3::new(-0.4, 0.2, -0.5), 0.3, material)),
+        ];
+        let mut rng = StdRng::seed_from_u64(5);
+        // An odd count leaves the last packet partly empty.
+        let rays: Vec<Ray> = (0..1001).map(|_| {
+            let direction = Vec3::new(rng.gen_range(-0.4..0.4), rng.gen_range(-0.4..0.4), -1.0);
+            Ray::new(Vec3::new(0.0, 0.0, 4.0), direction.normalize(), 0.0)
+        }).collect();
+        let mut counters = PathCounters::default();
+        let hits = camera_hits(&objects, &rays, &mut counters);
+        assert_eq!(hits.len(), rays.len());
+        assert_eq!(counters.rays_traced, rays.len() as u64);
+        for (ray, hit) in rays.iter().zip(&hits) {
+            let expected = nearest_hit(&objects, ray, f64::INFINITY, &mut PathCounters::default());
+            assert_eq!(hit.as_ref().map(|hit| hit.primitive_id), expected.as_ref().map(|hit| hit.primitive_id));
+            if let (Some(hit), Some(expected)) = (hit, expected) {
+                assert!((hit.distance - expected.distance).abs() < 1e-5);
+            }
+        }
     }
 }
@@ -0,0 +1,263 @@
+use crate::geometry::Vec3;
+use crate::ray_tracing::scene::material::Color;
+use crate::ray_tracing::spectrum::Radiance;
+
+pub mod exr;
+
+/// Arbitrary output variable: an image rendered alongside the beauty pass for compositing and denoising.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// Linear radiance in the working space, before the output transform.
+    Beauty,
+    /// Reflectance of the first visible surface, averaged over the pixel; see [`Material::albedo`](crate::ray_tracing::scene::material::Material::albedo).
+    Albedo,
+    /// World-space shading normal of the first visible surface, facing the camera and averaged over the pixel.
+    Normal,
+    /// Distance from the camera to the first visible surface; infinite where nothing was hit.
+    Depth,
+    /// World-space position of the first visible surface.
+    Position,
+    /// Index of the first visible object in the scene, or -1.
+    ObjectId,
+    /// Index of the first visible material among the [`Collision::materials`](crate::ray_tracing::scene::Collision::materials)
+    /// of the scene's objects in turn, or -1.
+    MaterialId,
+    /// Light reaching the camera after at most one scattering event.
+    Direct,
+    /// Light reaching the camera after two or more scattering events.
+    Indirect,
+    /// Light from each emitting object, one layer per object named after its index.
+    Lights,
+}
+
+impl Aov {
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Beauty => "beauty",
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Lights => "light",
+        }
+    }
+
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Beauty | Aov::Albedo | Aov::Direct | Aov::Indirect | Aov::Lights => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+        }
+    }
+}
+
+/// Surface first seen by a camera path, not counting invisible interfaces.
+#[derive(Debug, Clone)]
+pub struct FirstHit {
+    pub albedo: Color,
+    pub normal: Vec3<f64>,
+    pub distance: f64,
+    pub position: Vec3<f64>,
+    pub object_id: usize,
+    /// Index of the material among those of the scene; see [`Aov::MaterialId`].
+    pub material: usize,
+}
+
+/// What a camera path saw besides its radiance.
+#[derive(Debug, Clone)]
+pub struct PathRecord<S> {
+    pub first_hit: Option<FirstHit>,
+    pub direct: S,
+    pub indirect: S,
+    /// Light from emitting objects, by object index. Emitting media count towards direct and indirect light only.
+    pub lights: Vec<(usize, S)>,
+}
+
+impl<S: Radiance> Default for PathRecord<S> {
+    fn default() -> Self {
+        Self { first_hit: None, direct: S::zero(), indirect: S::zero(), lights: Vec::new() }
+    }
+}
+
+impl<S: Radiance> PathRecord<S> {
+    /// Records light reaching the camera after `bounces` scattering events, emitted by object `source` if any. Objects
+    /// only get a light entry once they contribute, so hits on surfaces that do not glow leave none.
+    pub fn add(&mut self, contribution: &S, bounces: usize, source: Option<usize>) {
+        if bounces <= 1 {
+            self.direct = self.direct.clone() + contribution.clone();
+        } else {
+            self.indirect = self.indirect.clone() + contribution.clone();
+        }
+        if let Some(source) = source.filter(|_| contribution.max_value() > 0.0) {
+            match self.lights.iter_mut().find(|(id, _)| *id == source) {
+                Some((_, light)) => *light = light.clone() + contribution.clone(),
+                None => self.lights.push((source, contribution.clone())),
+            }
+        }
+    }
+}
+
+/// Sums of the path records of one pixel. Depth, position and identifiers come from the first sample alone,
+/// so they never blend different surfaces.
+#[derive(Debug, Clone)]
+pub struct PixelAovs {
+    albedo: Color,
+    normal: Vec3<f64>,
+    first_hit: Option<FirstHit>,
+    first_sample: bool,
+    direct: Color,
+    indirect: Color,
+    lights: Vec<(usize, Color)>,
+    samples: usize,
+}
+
+impl Default for PixelAovs {
+    fn default() -> Self {
+        Self {
+            albedo: Color::zero(),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            first_hit: None,
+            first_sample: true,
+            direct: Color::zero(),
+            indirect: Color::zero(),
+            lights: Vec::new(),
+            samples: 0,
+        }
+    }
+}
+
+impl PixelAovs {
+    /// Adds the record of one path, converting its radiance to colours with `to_rgb`.
+    pub fn add<S>(&mut self, record: PathRecord<S>, to_rgb: impl Fn(&S) -> Color) {
+        self.samples += 1;
+        if let Some(hit) = &record.first_hit {
+            self.albedo = self.albedo.clone() + hit.albedo.clone();
+            self.normal = self.normal + hit.normal;
+        }
+        if self.first_sample {
+            self.first_hit = record.first_hit;
+            self.first_sample = false;
+        }
+        self.direct = self.direct.clone() + to_rgb(&record.direct);
+        self.indirect = self.indirect.clone() + to_rgb(&record.indirect);
+        for (source, light) in &record.lights {
+            let light = to_rgb(light);
+            match self.lights.iter_mut().find(|(id, _)| id == source) {
+                Some((_, sum)) => *sum = sum.clone() + light,
+                None => self.lights.push((*source, light)),
+            }
+        }
+    }
+
+    fn average(&self, sum: &Color) -> Color {
+        let count = self.samples.max(1) as f64;
+        Color { r: sum.r / count, g: sum.g / count, b: sum.b / count }
+    }
+
+    /// Mean albedo of the first visible surfaces, black for samples that hit nothing.
+    pub fn albedo(&self) -> Color {
+        self.average(&self.albedo)
+    }
+
+    /// Mean normal of the first visible surfaces, shorter than one where they disagree or some samples missed.
+    pub fn normal(&self) -> Vec3<f64> {
+        self.normal * (1.0 / self.samples.max(1) as f64)
+    }
+
+    /// Distance to the surface seen by the first sample, infinite if it hit nothing.
+    pub fn depth(&self) -> f64 {
+        self.first_hit.as_ref().map_or(f64::INFINITY, |hit| hit.distance)
+    }
+}
+
+/// Named set of per-pixel channels, stored interleaved in scanline order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub name: String,
+    pub channels: &'static [&'static str],
+    pub data: Vec<f32>,
+}
+
+/// Assembles the requested AOVs of an image from its pixels' records and its beauty pass, both in scanline order.
+pub fn layers(aovs: &[Aov], beauty: &[Color], pixels: &[PixelAovs]) -> Vec<Layer> {
+    let rgb = |color: Color| [color.r, color.g, color.b];
+    let mut layers = Vec::new();
+    for &aov in aovs {
+        let channel = |f: &dyn Fn(&PixelAovs, usize) -> [f64; 3]| -> Vec<f32> {
+            let size = aov.channels().len();
+            pixels.iter().enumerate().flat_map(|(i, pixel)| IntoIterator::into_iter(f(pixel, i)).take(size).map(|c| c as f32)).collect()
+        };
+        let id = |id: Option<usize>| [id.map_or(-1.0, |id| id as f64), 0.0, 0.0];
+        let data = match aov {
+            Aov::Beauty => channel(&|_, i| [beauty[i].r, beauty[i].g, beauty[i].b]),
+            Aov::Albedo => channel(&|pixel, _| rgb(pixel.albedo())),
+            Aov::Normal => channel(&|pixel, _| {
+                let normal = pixel.normal();
+                [*normal.x(), *normal.y(), *normal.z()]
+            }),
+            Aov::Depth => channel(&|pixel, _| [pixel.depth(), 0.0, 0.0]),
+            Aov::Position => channel(&|pixel, _| pixel.first_hit.as_ref().map_or([0.0; 3], |hit| [*hit.position.x(), *hit.position.y(), *hit.position.z()])),
+            Aov::ObjectId => channel(&|pixel, _| id(pixel.first_hit.as_ref().map(|hit| hit.object_id))),
+            Aov::MaterialId => channel(&|pixel, _| id(pixel.first_hit.as_ref().map(|hit| hit.material))),
+            Aov::Direct => channel(&|pixel, _| rgb(pixel.average(&pixel.direct))),
+            Aov::Indirect => channel(&|pixel, _| rgb(pixel.average(&pixel.indirect))),
+            Aov::Lights => {
+                let mut sources: Vec<usize> = pixels.iter().flat_map(|pixel| pixel.lights.iter().map(|(id, _)| *id)).collect();
+                sources.sort_unstable();
+                sources.dedup();
+                for source in sources {
+                    let data = channel(&|pixel, _| pixel.lights.iter().find(|(id, _)| *id == source)
+                        .map_or([0.0; 3], |(_, light)| rgb(pixel.average(light))));
+                    layers.push(Layer { name: format!("{}{}", aov.name(), source), channels: aov.channels(), data });
+                }
+                continue;
+            }
+        };
+        layers.push(Layer { name: aov.name().to_string(), channels: aov.channels(), data });
+    }
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::aov::{layers, Aov, FirstHit, PathRecord, PixelAovs};
+    use crate::ray_tracing::scene::material::Color;
+
+    #[test]
+    fn layers_test() {
+        let grey = |v: f64| Color { r: v, g: v, b: v };
+        let hit = |material: usize| FirstHit {
+            albedo: grey(0.5),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            distance: 2.0,
+            position: Vec3::new(1.0, 2.0, 3.0),
+            object_id: 7,
+            material,
+        };
+        let mut pixels = vec![PixelAovs::default(); 3];
+        for (pixel, material) in pixels.iter_mut().zip([Some(900), None, Some(100)]) {
+            for bounces in [1, 2] {
+                let mut record = PathRecord { first_hit: material.map(hit), ..PathRecord::default() };
+                record.add(&grey(1.0), bounces, material.map(|_| bounces));
+                record.add(&grey(0.0), bounces, Some(3));
+                pixel.add(record, Color::clone);
+            }
+        }
+        let beauty = vec![grey(0.0); 3];
+        let layers = layers(&[Aov::Albedo, Aov::Depth, Aov::MaterialId, Aov::Direct, Aov::Lights], &beauty, &pixels);
+        let names: Vec<_> = layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["albedo", "depth", "material_id", "direct", "light1", "light2"]);
+        assert_eq!(layers[0].data, [0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.5, 0.5, 0.5]);
+        assert_eq!(layers[1].data, [2.0, f32::INFINITY, 2.0]);
+        assert_eq!(layers[2].data, [900.0, -1.0, 100.0]);
+        // Each pixel's two samples split evenly between direct and indirect light.
+        assert_eq!(layers[3].data, [0.5; 9]);
+        assert_eq!(layers[4].data, [0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.5, 0.5, 0.5]);
+    }
+}
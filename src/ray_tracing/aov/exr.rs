@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::ray_tracing::aov::Layer;
+
+/// OpenEXR magic number.
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+/// Pixel type of 32-bit floating point channels.
+const FLOAT: i32 = 2;
+
+/// Full channel name: `layer.channel`, or the bare channel for unnamed layers.
+fn channel_name(layer: &Layer, channel: &str) -> String {
+    if layer.name.is_empty() { channel.to_string() } else { format!("{}.{}", layer.name, channel) }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for text in [name, kind] {
+        header.extend_from_slice(text.as_bytes());
+        header.push(0);
+    }
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Writes `layers` as one uncompressed scanline OpenEXR image with 32-bit float channels. Every layer must hold
+/// `width * height` pixels.
+pub fn write(mut writer: impl Write, width: usize, height: usize, layers: &[Layer]) -> io::Result<()> {
+    // EXR stores channels sorted by name; each entry keeps the layer and the channel's offset within a pixel.
+    let mut channels: Vec<(String, &Layer, usize)> = layers.iter()
+        .flat_map(|layer| layer.channels.iter().enumerate().map(move |(offset, channel)| (channel_name(layer, channel), layer, offset)))
+        .collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+    for layer in layers {
+        if layer.data.len() != width * height * layer.channels.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("layer {} has the wrong size", layer.name)));
+        }
+    }
+    let long_names = channels.iter().any(|(name, ..)| name.len() > 31);
+
+    let mut header = Vec::new();
+    let mut list = Vec::new();
+    for (name, ..) in &channels {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        list.extend_from_slice(&FLOAT.to_le_bytes());
+        // Perceptually linear flag and reserved bytes, then x and y sampling.
+        list.extend_from_slice(&[0; 4]);
+        list.extend_from_slice(&1i32.to_le_bytes());
+        list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute(&mut header, "channels", "chlist", &list);
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    writer.write_all(&MAGIC)?;
+    let version: i32 = 2 | if long_names { 0x400 } else { 0 };
+    writer.write_all(&version.to_le_bytes())?;
+    writer.write_all(&header)?;
+    let line_size = width * channels.len() * 4;
+    let first_line = 8 + header.len() + 8 * height;
+    for y in 0..height {
+        writer.write_all(&((first_line + y * (8 + line_size)) as u64).to_le_bytes())?;
+    }
+    for y in 0..height {
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&(line_size as i32).to_le_bytes())?;
+        for (_, layer, offset) in &channels {
+            let stride = layer.channels.len();
+            for x in 0..width {
+                writer.write_all(&layer.data[(y * width + x) * stride + offset].to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+pub fn save(path: impl AsRef<Path>, width: usize, height: usize, layers: &[Layer]) -> io::Result<()> {
+    write(BufWriter::new(File::create(path)?), width, height, layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use crate::ray_tracing::aov::exr::write;
+    use crate::ray_tracing::aov::Layer;
+
+    fn read_i32(bytes: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn exr_test() {
+        let (width, height) = (3, 2);
+        let beauty = Layer { name: String::new(), channels: &["R", "G", "B"], data: (0..18).map(|i| i as f32).collect() };
+        let depth = Layer { name: "depth".to_string(), channels: &["Z"], data: vec![10.0, 11.0, 12.0, 13.0, 14.0, 15.0] };
+        let mut bytes = Vec::new();
+        write(&mut bytes, width, height, &[depth.clone(), beauty.clone()]).unwrap();
+        assert_eq!(&bytes[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        assert_eq!(read_i32(&bytes, 4), 2);
+
+        // Channels are listed in sorted order with float pixels.
+        let header = &bytes[8..];
+        let list = header.windows(7).position(|w| w == b"chlist\0").unwrap() + 7 + 4;
+        let mut names = Vec::new();
+        let mut at = list;
+        while header[at] != 0 {
+            let end = at + header[at..].iter().position(|&b| b == 0).unwrap();
+            names.push(std::str::from_utf8(&header[at..end]).unwrap().to_string());
+            assert_eq!(read_i32(header, end + 1), 2);
+            at = end + 1 + 16;
+        }
+        assert_eq!(names, ["B", "G", "R", "depth.Z"]);
+
+        // The offset table points at scanlines holding each channel's row in turn.
+        let line_size = width * 4 * 4;
+        let table = bytes.len() - height * (8 + line_size) - 8 * height;
+        for y in 0..height {
+            let offset = u64::from_le_bytes(bytes[table + 8 * y..table + 8 * y + 8].try_into().unwrap()) as usize;
+            assert_eq!(read_i32(&bytes, offset), y as i32);
+            assert_eq!(read_i32(&bytes, offset + 4), line_size as i32);
+            let value = |channel: usize, x: usize| f32::from_le_bytes(bytes[offset + 8 + (channel * width + x) * 4..][..4].try_into().unwrap());
+            assert_eq!(value(0, 1), beauty.data[(y * width + 1) * 3 + 2]);
+            assert_eq!(value(2, 2), beauty.data[(y * width + 2) * 3]);
+            assert_eq!(value(3, 0), depth.data[y * width]);
+        }
+
+        let short = Layer { data: vec![0.0; 5], ..depth };
+        assert!(write(&mut Vec::new(), width, height, &[short]).is_err());
+    }
+}
@@ -0,0 +1,635 @@
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rand::Rng;
+
+use crate::geometry::{NormalizedVec3, Vec3};
+use crate::ray_tracing::{leaks, nearest_hit, occluded, sample_cosine, scatter_delta, Lights, Ray, RenderOptions, SurfacePoint};
+use crate::ray_tracing::aov::PathRecord;
+use crate::ray_tracing::scene::{offset_ray_origin, Collision, SurfaceDifferentials, SurfaceInteraction};
+use crate::ray_tracing::scene::camera::Camera;
+use crate::ray_tracing::scene::material::{Color, Material, ShadingGeometry};
+use crate::ray_tracing::spectrum::Radiance;
+use crate::ray_tracing::statistics::PathCounters;
+
+/// Objects, lights and camera that bidirectional paths are traced through.
+pub struct Scene<'a, T> {
+    objects: &'a [T],
+    camera: &'a Camera,
+    lights: Lights,
+}
+
+impl<'a, T: Collision> Scene<'a, T> {
+    pub fn new(objects: &'a [T], camera: &'a Camera) -> Self {
+        Self { objects, camera, lights: Lights::new(objects) }
+    }
+
+    pub fn lights(&self) -> &Lights {
+        &self.lights
+    }
+
+    pub fn objects(&self) -> &'a [T] {
+        self.objects
+    }
+
+    pub fn camera(&self) -> &Camera {
+        self.camera
+    }
+
+    /// Point on a uniformly chosen light, sampled uniformly by area, and a cosine-weighted direction to either side.
+    pub fn sample_emission<S: Radiance>(&self, time: f64, wavelengths: &S::Wavelengths, rng: &mut impl Rng) -> Option<Emission<S>> {
+        let object = self.lights.choose(rng.gen_range(0.0..1.0))?;
+        let (mut point, _) = self.objects[object].sample_area((rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)), time)?;
+        let coordinate = point.texture_coordinate(&SurfaceDifferentials::zero());
+        let radiance = match point.resolve_material(&coordinate) {
+            Material::Solid { illuminate, .. } => S::illuminant(&illuminate.evaluate(&coordinate), wavelengths),
+            _ => S::zero(),
+        };
+        let side = if rng.gen_range(0.0..1.0) < 0.5 { 1.0 } else { -1.0 };
+        let normal = point.normal.vec() * side;
+        let (direction, cosine) = sample_cosine(normal, point.dpdu, rng);
+        Some(Emission {
+            position: point.position,
+            error: point.error,
+            normal,
+            direction: direction.normalize(),
+            object,
+            radiance,
+            pdf_position: self.lights.pdf(object),
+            pdf_direction: cosine / (2.0 * PI),
+        })
+    }
+
+    /// Nearest hit along `ray` that is not an invisible interface, with the distance travelled through interfaces before it.
+    pub fn intersect(&self, mut ray: Ray, path_counters: &mut PathCounters) -> (Option<SurfaceInteraction<'a>>, f64) {
+        let mut travelled = 0.0;
+        loop {
+            match nearest_hit(self.objects, &ray, f64::INFINITY, path_counters) {
+                Some(hit) if hit.material.is_interface() => {
+                    travelled += hit.distance;
+                    ray = Ray { initial: offset_ray_origin(hit.position, hit.error, hit.normal.vec(), ray.direction.vec()), ..ray };
+                }
+                collision => return (collision, travelled),
+            }
+        }
+    }
+
+    /// Whether nothing but interfaces lies between two vertices.
+    fn unoccluded<S>(&self, a: &Vertex<S>, b: &Vertex<S>, time: f64, path_counters: &mut PathCounters) -> bool {
+        let origin = |vertex: &Vertex<S>, toward: Vec3<f64>| match vertex.kind {
+            Kind::Camera => vertex.position,
+            _ => offset_ray_origin(vertex.position, vertex.error, vertex.normal, toward),
+        };
+        !occluded(self.objects, origin(a, b.position - a.position), origin(b, a.position - b.position), time, path_counters)
+    }
+}
+
+/// Start of a light subpath or photon.
+pub struct Emission<S> {
+    pub position: Vec3<f64>,
+    pub error: Vec3<f64>,
+    /// Surface normal on the side `direction` leaves through.
+    pub normal: Vec3<f64>,
+    pub direction: NormalizedVec3<f64>,
+    pub object: usize,
+    pub radiance: S,
+    /// Densities of the point per unit area and of the direction per steradian.
+    pub pdf_position: f64,
+    pub pdf_direction: f64,
+}
+
+impl<S: Radiance> Emission<S> {
+    /// Ray leaving the light along the sampled direction.
+    pub fn ray(&self, time: f64) -> Ray {
+        Ray::new(offset_ray_origin(self.position, self.error, self.normal, self.direction.vec()), self.direction, time)
+    }
+
+    /// Radiance times cosine, divided by the densities of the point and direction.
+    pub fn power(&self) -> S {
+        let cosine = self.direction.vec().inner_product(self.normal.normalize().vec());
+        self.radiance.clone() * S::splat(cosine / (self.pdf_position * self.pdf_direction))
+    }
+}
+
+/// Image that light-tracing contributions are splatted into from all threads at once.
+pub struct SplatFilm {
+    width: usize,
+    height: usize,
+    /// Bits of the `f64` sum of each channel.
+    pixels: Vec<[AtomicU64; 3]>,
+}
+
+impl SplatFilm {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, pixels: (0..width * height).map(|_| Default::default()).collect() }
+    }
+
+    pub fn add(&self, (x, y): (f64, f64), color: &Color) {
+        let (x, y) = ((x as usize).min(self.width - 1), (y as usize).min(self.height - 1));
+        self.add_pixel(y * self.width + x, color);
+    }
+
+    /// Adds `color` to the pixel at `index` in scanline order.
+    pub fn add_pixel(&self, index: usize, color: &Color) {
+        for (channel, value) in self.pixels[index].iter().zip([color.r, color.g, color.b]) {
+            // The closure always returns `Some`, so the update cannot fail.
+            let _ = channel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f64::from_bits(bits) + value).to_bits()));
+        }
+    }
+
+    /// Accumulated image in scanline order, multiplied by `scale`.
+    pub fn colors(&self, scale: f64) -> Vec<Color> {
+        self.pixels.iter().map(|pixel| {
+            let [r, g, b] = [0, 1, 2].map(|i| f64::from_bits(pixel[i].load(Ordering::Relaxed)) * scale);
+            Color { r, g, b }
+        }).collect()
+    }
+}
+
+/// Raster position and radiance of a contribution found by connecting a light subpath to the camera.
+pub type Splat<S> = ((f64, f64), S);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// Quantity carried by a subpath. Shading normals make scattering asymmetric, so the two need different corrections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Radiance,
+    Importance,
+}
+
+#[derive(Debug, Clone)]
+struct Vertex<S> {
+    kind: Kind,
+    position: Vec3<f64>,
+    error: Vec3<f64>,
+    /// Geometric normal, facing the side the subpath arrived from; zero at the camera.
+    normal: Vec3<f64>,
+    /// Shading normal on the same side as `normal`.
+    shading_normal: Vec3<f64>,
+    object: usize,
+    /// Lambertian reflectance, or `None` where the surface only scatters deterministically.
+    reflectance: Option<S>,
+    /// Radiance emitted towards either side.
+    emission: S,
+    /// Subpath throughput up to this vertex, divided by the density of sampling it.
+    beta: S,
+    /// Whether the subpath scattered deterministically here, so no strategy can sample this vertex by connection.
+    delta: bool,
+    /// Area densities of sampling this vertex from its predecessor along the subpath and from its successor.
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+/// Turns a density per steradian of leaving `from` into a density per unit area at `to`.
+fn convert_density(pdf: f64, from: Vec3<f64>, to: Vec3<f64>, normal: Option<Vec3<f64>>) -> f64 {
+    let offset = to - from;
+    let distance2: f64 = offset.squared_len();
+    if distance2 == 0.0 {
+        return 0.0;
+    }
+    let cosine = normal.map_or(1.0, |normal| normal.normalize().vec().inner_product(offset.normalize().vec()).abs());
+    pdf * cosine / distance2
+}
+
+impl<S: Radiance> Vertex<S> {
+    fn surface_normal(&self) -> Option<Vec3<f64>> {
+        (self.kind != Kind::Camera).then_some(self.normal)
+    }
+
+    /// Whether another subpath can be joined to this vertex.
+    fn is_connectible(&self) -> bool {
+        self.kind != Kind::Surface || self.reflectance.is_some()
+    }
+
+    /// Absolute cosine between `direction` and the shading normal.
+    fn cosine(&self, direction: Vec3<f64>) -> f64 {
+        direction.normalize().vec().inner_product(self.shading_normal).abs()
+    }
+
+    /// Reflectance towards `next` of light arriving from `prev`. Light subpaths correct for shading normals so that
+    /// both directions of transport agree.
+    fn f(&self, prev: Vec3<f64>, next: Vec3<f64>, transport: Transport) -> S {
+        let reflectance = match &self.reflectance {
+            Some(reflectance) => reflectance,
+            None => return S::zero(),
+        };
+        let (wo, wi) = ((prev - self.position).normalize().vec(), (next - self.position).normalize().vec());
+        let (cos_o, cos_i) = (wo.inner_product(self.normal), wi.inner_product(self.normal));
+        if cos_o * cos_i <= 0.0 {
+            return S::zero();
+        }
+        let correction = match transport {
+            Transport::Radiance => 1.0,
+            Transport::Importance => {
+                let denominator = cos_o.abs() * self.cosine(wi);
+                if denominator == 0.0 { 0.0 } else { self.cosine(wo) * cos_i.abs() / denominator }
+            }
+        };
+        reflectance.clone() * S::splat(correction / PI)
+    }
+
+    /// Density per steradian of scattering towards `next` after arriving from `prev`, as the random walk samples it.
+    fn pdf_direction(&self, prev: Vec3<f64>, next: Vec3<f64>) -> f64 {
+        if self.reflectance.is_none() {
+            return 0.0;
+        }
+        let (wo, wi) = ((prev - self.position).normalize().vec(), (next - self.position).normalize().vec());
+        if wo.inner_product(self.normal) * wi.inner_product(self.normal) <= 0.0 {
+            return 0.0;
+        }
+        let shading_normal = if wo.inner_product(self.shading_normal) < 0.0 { -self.shading_normal } else { self.shading_normal };
+        wi.inner_product(shading_normal).max(0.0) / PI
+    }
+
+    /// Area density at `next` of continuing a subpath through this vertex from `prev`.
+    fn pdf<T>(&self, scene: &Scene<T>, time: f64, prev: Option<&Vertex<S>>, next: &Vertex<S>) -> f64 {
+        let pdf = match self.kind {
+            Kind::Light => return self.pdf_light(next),
+            Kind::Camera => scene.camera.project(next.position, time)
+                .map_or(0.0, |projection| 1.0 / (scene.camera.film_area() * projection.cosine.powi(3))),
+            Kind::Surface => self.pdf_direction(prev.expect("surface vertices have a predecessor").position, next.position),
+        };
+        convert_density(pdf, self.position, next.position, next.surface_normal())
+    }
+
+    /// Area density at `next` of a light subpath leaving this point of an emitter towards it.
+    fn pdf_light(&self, next: &Vertex<S>) -> f64 {
+        let direction = (next.position - self.position).normalize().vec();
+        let pdf = direction.inner_product(self.normal.normalize().vec()).abs() / (2.0 * PI);
+        convert_density(pdf, self.position, next.position, next.surface_normal())
+    }
+}
+
+/// Extends `path` from its last vertex along `ray`, which was sampled with density `pdf` per steradian, until it
+/// escapes, is absorbed or reaches `max_vertices`.
+#[allow(clippy::too_many_arguments)]
+fn random_walk<T: Collision, S: Radiance>(scene: &Scene<T>, mut ray: Ray, mut beta: S, mut pdf: f64, max_vertices: usize, transport: Transport, path: &mut Vec<Vertex<S>>,
+                                          options: &RenderOptions, wavelengths: &mut S::Wavelengths, rng: &mut impl Rng, path_counters: &mut PathCounters, mut record: Option<&mut PathRecord<S>>) {
+    let camera_path = transport == Transport::Radiance;
+    while path.len() < max_vertices {
+        let (collision, travelled) = scene.intersect(ray.clone(), path_counters);
+        let mut interaction = match collision {
+            Some(interaction) => interaction,
+            None => {
+                path_counters.escaped += camera_path as u64;
+                return;
+            }
+        };
+        let point = SurfacePoint::new(&mut interaction, &ray);
+        if let Some(record) = record.as_deref_mut().filter(|_| path.len() == 1) {
+            record.first_hit = Some(point.first_hit(scene.objects, &interaction, travelled + interaction.distance));
+        }
+        let SurfacePoint { coordinate, material, geometry, shading_normal, .. } = point;
+        let ShadingGeometry { normal, .. } = geometry;
+        let SurfaceInteraction { position, error, dpdu, .. } = interaction;
+        let wo = -ray.direction.vec();
+        let (reflectance, emission) = match material {
+            Material::Solid { color, illuminate } => (Some(S::albedo(&color.evaluate(&coordinate), wavelengths)), S::illuminant(&illuminate.evaluate(&coordinate), wavelengths)),
+            _ => (None, S::zero()),
+        };
+        let prev = path.last().expect("subpaths start at the camera or a light").position;
+        path.push(Vertex {
+            kind: Kind::Surface,
+            position,
+            error,
+            normal,
+            shading_normal,
+            object: interaction.primitive_id,
+            reflectance: reflectance.clone(),
+            emission,
+            beta: beta.clone(),
+            delta: false,
+            pdf_fwd: convert_density(pdf, prev, position, Some(normal)),
+            pdf_rev: 0.0,
+        });
+        path_counters.path_length_total += camera_path as u64;
+        if path.len() >= max_vertices {
+            path_counters.terminated_by_depth += camera_path as u64;
+            return;
+        }
+
+        let (direction, pdf_rev) = match (material, reflectance) {
+            (Material::Solid { .. }, Some(reflectance)) => {
+                let (direction, cosine) = sample_cosine(shading_normal, dpdu, rng);
+                if leaks(direction, normal, false) {
+                    return;
+                }
+                let correction = match transport {
+                    Transport::Radiance => 1.0,
+                    Transport::Importance => wo.inner_product(shading_normal).abs() * direction.normalize().vec().inner_product(normal).abs()
+                        / (wo.inner_product(normal).abs() * cosine),
+                };
+                pdf = cosine / PI;
+                beta = beta * reflectance * S::splat(correction);
+                (direction.normalize(), wo.inner_product(shading_normal).max(0.0) / PI)
+            }
+            (Material::Conductor { .. } | Material::Dielectric { .. }, _) => {
+                let scatter = scatter_delta(material, ray.direction.vec(), shading_normal, interaction.front_face, &coordinate, &beta, wavelengths, rng);
+                if leaks(scatter.direction, normal, scatter.transmitted) {
+                    return;
+                }
+                path.last_mut().expect("just pushed").delta = true;
+                pdf = 0.0;
+                beta = beta * scatter.weight;
+                (scatter.direction.normalize(), 0.0)
+            }
+            _ => unreachable!("intersect skips interfaces and resolve strips bump and fill layers"),
+        };
+        let count = path.len();
+        path[count - 2].pdf_rev = convert_density(pdf_rev, position, prev, path[count - 2].surface_normal());
+        ray = Ray::new(offset_ray_origin(position, error, normal, direction.vec()), direction, ray.time);
+
+        if count > options.min_depth {
+            let survival = beta.max_value().min(1.0);
+            if survival <= 0.0 || rng.gen_range(0.0..1.0) >= survival {
+                path_counters.terminated_by_roulette += camera_path as u64;
+                return;
+            }
+            beta = beta.map(|c| c / survival);
+        }
+    }
+}
+
+/// Subpath starting at a point sampled on a light, emitting towards a cosine-weighted direction on either side.
+fn light_subpath<T: Collision, S: Radiance>(scene: &Scene<T>, time: f64, options: &RenderOptions, wavelengths: &mut S::Wavelengths,
+                                            rng: &mut impl Rng, path_counters: &mut PathCounters) -> Vec<Vertex<S>> {
+    let mut path = Vec::new();
+    let emission: Emission<S> = match scene.sample_emission(time, wavelengths, rng) {
+        Some(emission) => emission,
+        None => return path,
+    };
+    let pdf = emission.pdf_direction;
+    let beta = emission.power();
+    let ray = emission.ray(time);
+    path.push(Vertex {
+        kind: Kind::Light,
+        position: emission.position,
+        error: emission.error,
+        normal: emission.normal,
+        shading_normal: emission.normal,
+        object: emission.object,
+        reflectance: None,
+        emission: emission.radiance,
+        beta: S::splat(1.0 / emission.pdf_position),
+        delta: false,
+        pdf_fwd: emission.pdf_position,
+        pdf_rev: 0.0,
+    });
+    random_walk(scene, ray, beta, pdf, options.max_depth + 1, Transport::Importance, &mut path, options, wavelengths, rng, path_counters, None);
+    path
+}
+
+/// Radiance that the strategy with `s` light and `t` camera vertices finds, already weighted by multiple importance
+/// sampling, with the raster position it belongs to when the camera vertex was chosen by connection.
+#[allow(clippy::too_many_arguments)]
+fn connect<T: Collision, S: Radiance>(scene: &Scene<T>, light: &[Vertex<S>], camera: &[Vertex<S>], s: usize, t: usize, time: f64,
+                                      path_counters: &mut PathCounters) -> Option<(S, Option<(f64, f64)>)> {
+    let pt = &camera[t - 1];
+    let (contribution, raster) = if s == 0 {
+        (pt.beta.clone() * pt.emission.clone(), None)
+    } else {
+        let qs = &light[s - 1];
+        if !qs.is_connectible() || !pt.is_connectible() {
+            return None;
+        }
+        let offset = pt.position - qs.position;
+        let distance2: f64 = offset.squared_len();
+        let (f_p, cosine_p, raster) = if t == 1 {
+            let projection = scene.camera.project(qs.position, time)?;
+            let importance = 1.0 / (scene.camera.film_area() * projection.cosine.powi(4));
+            (S::splat(importance), projection.cosine, Some(projection.raster))
+        } else {
+            (pt.f(camera[t - 2].position, qs.position, Transport::Radiance), pt.cosine(offset), None)
+        };
+        let f_q = if s == 1 { qs.emission.clone() } else { qs.f(light[s - 2].position, pt.position, Transport::Importance) };
+        let geometry = qs.cosine(offset) * cosine_p / distance2;
+        (qs.beta.clone() * f_q * f_p * pt.beta.clone() * S::splat(geometry), raster)
+    };
+    if contribution.max_value() <= 0.0 {
+        return None;
+    }
+    if s > 0 && !scene.unoccluded(&light[s - 1], pt, time, path_counters) {
+        return None;
+    }
+    Some((contribution * S::splat(mis_weight(scene, light, camera, s, t, time)), raster))
+}
+
+/// Balance heuristic weight of the strategy with `s` light and `t` camera vertices among all strategies that could
+/// have produced the same path.
+fn mis_weight<T, S: Radiance>(scene: &Scene<T>, light: &[Vertex<S>], camera: &[Vertex<S>], s: usize, t: usize, time: f64) -> f64 {
+    if s + t == 2 {
+        return 1.0;
+    }
+    let pt = &camera[t - 1];
+    // Emitters that cannot be sampled are only ever found by camera subpaths.
+    if s == 0 && scene.lights.pdf(pt.object) == 0.0 {
+        return 1.0;
+    }
+    // Forward and reverse densities and delta flags of each vertex, with those next to the connection recomputed.
+    let mut camera_pdfs: Vec<(f64, f64, bool)> = camera[..t].iter().map(|vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.delta)).collect();
+    let mut light_pdfs: Vec<(f64, f64, bool)> = light[..s].iter().map(|vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.delta)).collect();
+    camera_pdfs[t - 1].2 = false;
+    camera_pdfs[t - 1].1 = if s > 0 {
+        light[s - 1].pdf(scene, time, s.checked_sub(2).map(|i| &light[i]), pt)
+    } else {
+        scene.lights.pdf(pt.object)
+    };
+    if t > 1 {
+        camera_pdfs[t - 2].1 = if s > 0 { pt.pdf(scene, time, Some(&light[s - 1]), &camera[t - 2]) } else { pt.pdf_light(&camera[t - 2]) };
+    }
+    if s > 0 {
+        light_pdfs[s - 1].2 = false;
+        light_pdfs[s - 1].1 = pt.pdf(scene, time, t.checked_sub(2).map(|i| &camera[i]), &light[s - 1]);
+        if s > 1 {
+            light_pdfs[s - 2].1 = light[s - 1].pdf(scene, time, Some(pt), &light[s - 2]);
+        }
+    }
+
+    // Delta vertices have no density; they cancel out of every ratio that does not skip them anyway.
+    let remap = |pdf: f64| if pdf == 0.0 { 1.0 } else { pdf };
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..t).rev() {
+        ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+        if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+            sum += ratio;
+        }
+    }
+    ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+        let previous_delta = i > 0 && light_pdfs[i - 1].2;
+        if !light_pdfs[i].2 && !previous_delta {
+            sum += ratio;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+/// Estimates the radiance along the camera ray `ray` by joining its subpath to a light subpath with every strategy,
+/// weighted by the balance heuristic. Paths that connect the light subpath to the camera land elsewhere on the image;
+/// they are returned with their raster positions, and the image they add up to must be divided by the samples per pixel.
+/// Participating media are ignored.
+pub fn trace<T: Collision, S: Radiance>(scene: &Scene<T>, ray: Ray, options: &RenderOptions, wavelengths: &mut S::Wavelengths, rng: &mut impl Rng,
+                                        path_counters: &mut PathCounters, mut record: Option<&mut PathRecord<S>>) -> (S, Vec<Splat<S>>) {
+    path_counters.samples += 1;
+    let time = ray.time;
+    let camera_position = ray.initial;
+    let pdf = scene.camera.project(camera_position + ray.direction.vec(), time)
+        .map_or(0.0, |projection| 1.0 / (scene.camera.film_area() * projection.cosine.powi(3)));
+    let mut camera = vec![Vertex {
+        kind: Kind::Camera,
+        position: camera_position,
+        error: Vec3::new(0.0, 0.0, 0.0),
+        normal: Vec3::new(0.0, 0.0, 0.0),
+        shading_normal: Vec3::new(0.0, 0.0, 0.0),
+        object: usize::MAX,
+        reflectance: None,
+        emission: S::zero(),
+        beta: S::splat(1.0),
+        delta: false,
+        pdf_fwd: 0.0,
+        pdf_rev: 0.0,
+    }];
+    random_walk(scene, ray, S::splat(1.0), pdf, options.max_depth + 2, Transport::Radiance, &mut camera, options, wavelengths, rng, path_counters, record.as_deref_mut());
+    let light = light_subpath(scene, time, options, wavelengths, rng, path_counters);
+
+    let mut radiance = S::zero();
+    let mut splats = Vec::new();
+    for t in 1..=camera.len() {
+        for s in 0..=light.len() {
+            if s + t < 2 || s + t - 2 > options.max_depth || (s == 1 && t == 1) {
+                continue;
+            }
+            match connect(scene, &light, &camera, s, t, time, path_counters) {
+                Some((contribution, Some(raster))) => splats.push((raster, contribution)),
+                Some((contribution, None)) => {
+                    if let Some(record) = record.as_deref_mut() {
+                        let source = if s == 0 { camera[t - 1].object } else { light[0].object };
+                        record.add(&contribution, s + t - 2, Some(source));
+                    }
+                    radiance = radiance + contribution;
+                }
+                None => {}
+            }
+        }
+    }
+    (radiance, splats)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::{trace_path, RenderOptions};
+    use crate::ray_tracing::bdpt::{trace, Scene, SplatFilm};
+    use crate::ray_tracing::scene::Collision;
+    use crate::ray_tracing::scene::camera::Camera;
+    use crate::ray_tracing::scene::material::Color;
+    use crate::ray_tracing::spectrum::{SampledSpectrum, SampledWavelengths};
+    use crate::ray_tracing::statistics::PathCounters;
+    use crate::ray_tracing::test_util::{camera, caustic, furnace, Counting, SIZE};
+
+    /// Mean of the camera estimates and of the splatted image over all pixels, and the image itself.
+    fn render<T: Collision>(objects: &[T], camera: &Camera, options: &RenderOptions, spectral: bool) -> (Color, Color, Vec<Color>) {
+        let scene = Scene::new(objects, camera);
+        let film = SplatFilm::new(SIZE, SIZE);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut counters = PathCounters::default();
+        let mut image = Vec::new();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let mut sum = Color::zero();
+                for _ in 0..options.samples_per_pixel {
+                    let ray = camera.create_ray(x as f64 + rng.gen_range(0.0..1.0), y as f64 + rng.gen_range(0.0..1.0), 0.0);
+                    sum = sum + if spectral {
+                        let mut wavelengths = SampledWavelengths::sample_visible(rng.gen_range(0.0..1.0));
+                        let (spectrum, splats): (SampledSpectrum, _) = trace(&scene, ray, options, &mut wavelengths, &mut rng, &mut counters, None);
+                        for (raster, splat) in splats {
+                            film.add(raster, &wavelengths.to_rgb(&splat));
+                        }
+                        wavelengths.to_rgb(&spectrum)
+                    } else {
+                        let (color, splats) = trace::<_, Color>(&scene, ray, options, &mut (), &mut rng, &mut counters, None);
+                        for (raster, splat) in splats {
+                            film.add(raster, &splat);
+                        }
+                        color
+                    };
+                }
+                let count = options.samples_per_pixel as f64;
+                image.push(Color { r: sum.r / count, g: sum.g / count, b: sum.b / count });
+            }
+        }
+        let splats = film.colors(1.0 / options.samples_per_pixel as f64);
+        let mean = |image: &[Color]| image.iter().fold(Color::zero(), |sum, c| sum + c.clone()) * Color { r: 1.0 / image.len() as f64, g: 1.0 / image.len() as f64, b: 1.0 / image.len() as f64 };
+        let total: Vec<Color> = image.iter().zip(&splats).map(|(a, b)| a.clone() + b.clone()).collect();
+        (mean(&image), mean(&splats), total)
+    }
+
+    #[test]
+    fn bdpt_furnace_test() {
+        // Every pixel of the furnace sees the same light, whichever strategy finds it.
+        let objects = furnace();
+        let options = RenderOptions { samples_per_pixel: 32, min_depth: 3, ..RenderOptions::default() };
+        let camera = camera(Vec3::new(0.0, 0.0, 0.5), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, -1.0, 0.0));
+        for spectral in [false, true] {
+            let (camera_mean, splat_mean, image) = render(&objects, &camera, &options, spectral);
+            for pixel in &image {
+                assert!((pixel.g - 1.0).abs() < 0.15, "{:?}", pixel);
+            }
+            let total = camera_mean.clone() + splat_mean.clone();
+            for channel in [total.r, total.g, total.b] {
+                assert!((channel - 1.0).abs() < 0.02, "{} {:?} {:?}", spectral, camera_mean, splat_mean);
+            }
+            // Light tracing carries a share of the estimate, if a small one when every surface is diffuse.
+            assert!(splat_mean.g > 0.005 && camera_mean.g > 0.5, "{:?} {:?}", camera_mean, splat_mean);
+        }
+    }
+
+    #[test]
+    fn bdpt_shadow_ray_test() {
+        // Connections between subpaths test visibility with shadow rays rather than full intersections.
+        let [sphere] = furnace();
+        let objects = [Counting::new(sphere)];
+        let options = RenderOptions { samples_per_pixel: 1, ..RenderOptions::default() };
+        render(&objects, &camera(Vec3::new(0.0, 0.0, 0.5), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, -1.0, 0.0)), &options, false);
+        assert!(objects[0].occluded_count() > SIZE * SIZE);
+    }
+
+    #[test]
+    fn bdpt_caustic_test() {
+        // Light tracing finds the caustic that camera paths only hit by chance, and both integrators agree on the
+        // brightness of the floor.
+        let objects = caustic();
+        let camera = camera(Vec3::new(0.0, 1.0, 3.0), Vec3::new(0.0, -0.6, -1.0), Vec3::new(0.0, -1.0, 0.6));
+        let options = RenderOptions { samples_per_pixel: 64, ..RenderOptions::default() };
+        let (camera_mean, splat_mean, bidirectional) = render(&objects, &camera, &options, false);
+        assert!(splat_mean.g > 0.1 * camera_mean.g, "{:?} {:?}", camera_mean, splat_mean);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut reference = Vec::new();
+        const COUNT: usize = 2048;
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let mut sum = 0.0;
+                for _ in 0..COUNT {
+                    let ray = camera.create_ray(x as f64 + rng.gen_range(0.0..1.0), y as f64 + rng.gen_range(0.0..1.0), 0.0);
+                    sum += trace_path::<_, Color>(&objects, ray, &options, &mut (), &mut rng, &mut PathCounters::default(), None).g;
+                }
+                reference.push(sum / COUNT as f64);
+            }
+        }
+        let mean = |values: &mut dyn Iterator<Item=f64>| values.sum::<f64>() / (SIZE * SIZE) as f64;
+        let (expected, actual) = (mean(&mut reference.iter().copied()), mean(&mut bidirectional.iter().map(|c| c.g)));
+        assert!((actual - expected).abs() < 0.05 * expected, "{} {}", actual, expected);
+    }
+}
@@ -0,0 +1,275 @@
+use std::sync::OnceLock;
+
+use crate::geometry::{Matrix3, Vec3};
+use crate::ray_tracing::scene::material::Color;
+
+/// RGB colour space given by the chromaticities of its primaries and white point. Values are linear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Rec. 709 primaries with a D65 white point, shared by sRGB.
+    LinearSrgb,
+    /// ACES AP1 primaries with the ACES white point, close to D60.
+    AcesCg,
+    /// Rec. 2020 primaries with a D65 white point.
+    Rec2020,
+    /// DCI-P3 primaries with a D65 white point.
+    DisplayP3,
+}
+
+impl ColorSpace {
+    const ALL: [ColorSpace; 4] = [ColorSpace::LinearSrgb, ColorSpace::AcesCg, ColorSpace::Rec2020, ColorSpace::DisplayP3];
+
+    /// CIE xy chromaticities of the red, green and blue primaries and of the white point.
+    pub fn chromaticities(self) -> [(f64, f64); 4] {
+        const D65: (f64, f64) = (0.3127, 0.3290);
+        match self {
+            ColorSpace::LinearSrgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+            ColorSpace::AcesCg => [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044), (0.32168, 0.33767)],
+            ColorSpace::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+            ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65],
+        }
+    }
+
+    /// CIE XYZ with luminance `Y = 1` at the given chromaticity.
+    fn xyz((x, y): (f64, f64)) -> Vec3<f64> {
+        Vec3::new(x / y, 1.0, (1.0 - x - y) / y)
+    }
+
+    /// CIE XYZ from linear RGB in this space, mapping RGB white to the white point with `Y = 1`.
+    pub fn to_xyz(self) -> Matrix3 {
+        let [r, g, b, white] = self.chromaticities().map(Self::xyz);
+        let primaries = Matrix3::from_columns(r, g, b);
+        let scale = primaries.inverse().expect("primaries must not be collinear") * white;
+        Matrix3::from_columns(r * *scale.x(), g * *scale.y(), b * *scale.z())
+    }
+
+    pub fn from_xyz(self) -> Matrix3 {
+        self.to_xyz().inverse().expect("primaries must not be collinear")
+    }
+
+    /// Linear matrix from this space to `target`. Differing white points are adapted with the Bradford transform,
+    /// so white stays white.
+    pub fn conversion(self, target: ColorSpace) -> Matrix3 {
+        static CONVERSIONS: OnceLock<Vec<Matrix3>> = OnceLock::new();
+        let conversions = CONVERSIONS.get_or_init(|| {
+            ColorSpace::ALL.iter().flat_map(|&source| ColorSpace::ALL.iter().map(move |&target| {
+                let adaptation = bradford(Self::xyz(source.chromaticities()[3]), Self::xyz(target.chromaticities()[3]));
+                target.from_xyz() * adaptation * source.to_xyz()
+            })).collect()
+        });
+        conversions[self as usize * ColorSpace::ALL.len() + target as usize]
+    }
+
+    /// Relative luminance of a colour in this space, 1 for white.
+    pub fn luminance(self, color: &Color) -> f64 {
+        static ROWS: OnceLock<Vec<Vec3<f64>>> = OnceLock::new();
+        let rows = ROWS.get_or_init(|| ColorSpace::ALL.iter().map(|space| space.to_xyz().row(1)).collect());
+        rows[self as usize].inner_product(Vec3::new(color.r, color.g, color.b))
+    }
+
+    pub fn convert(self, color: &Color, target: ColorSpace) -> Color {
+        if self == target {
+            return color.clone();
+        }
+        let rgb = self.conversion(target) * Vec3::new(color.r, color.g, color.b);
+        Color { r: *rgb.x(), g: *rgb.y(), b: *rgb.z() }
+    }
+}
+
+/// Chromatic adaptation between white points in CIE XYZ, scaling the responses of the Bradford cone space.
+fn bradford(source: Vec3<f64>, target: Vec3<f64>) -> Matrix3 {
+    const CONES: Matrix3 = Matrix3::new([
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ]);
+    let (source, target) = (CONES * source, CONES * target);
+    let scale = Matrix3::new([
+        [target.x() / source.x(), 0.0, 0.0],
+        [0.0, target.y() / source.y(), 0.0],
+        [0.0, 0.0, target.z() / source.z()],
+    ]);
+    CONES.inverse().expect("cone matrix is invertible") * scale * CONES
+}
+
+/// Nonlinear encoding of linear values into signals in `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferFunction {
+    Linear,
+    /// The piecewise sRGB curve of IEC 61966-2-1.
+    Srgb,
+    /// Pure power law with the given exponent.
+    Gamma(f64),
+    /// SMPTE ST 2084 perceptual quantizer; linear `1` is 10000 cd/m².
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma, from scene light in `[0, 1]`.
+    Hlg,
+}
+
+impl TransferFunction {
+    const PQ_M1: f64 = 2610.0 / 16384.0;
+    const PQ_M2: f64 = 2523.0 / 4096.0 * 128.0;
+    const PQ_C1: f64 = 3424.0 / 4096.0;
+    const PQ_C2: f64 = 2413.0 / 4096.0 * 32.0;
+    const PQ_C3: f64 = 2392.0 / 4096.0 * 32.0;
+    const HLG_A: f64 = 0.178_832_77;
+    const HLG_B: f64 = 1.0 - 4.0 * Self::HLG_A;
+    const HLG_C: f64 = 0.559_910_73;
+
+    /// Signal for linear `value`, clamped to the encodable range.
+    pub fn encode(self, value: f64) -> f64 {
+        let value = value.clamp(0.0, 1.0);
+        match self {
+            TransferFunction::Linear => value,
+            TransferFunction::Srgb => if value <= 0.003_130_8 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 },
+            TransferFunction::Gamma(gamma) => value.powf(1.0 / gamma),
+            TransferFunction::Pq => {
+                let power = value.powf(Self::PQ_M1);
+                ((Self::PQ_C1 + Self::PQ_C2 * power) / (1.0 + Self::PQ_C3 * power)).powf(Self::PQ_M2)
+            }
+            TransferFunction::Hlg => if value <= 1.0 / 12.0 {
+                (3.0 * value).sqrt()
+            } else {
+                Self::HLG_A * (12.0 * value - Self::HLG_B).ln() + Self::HLG_C
+            },
+        }
+    }
+
+    /// Linear value for `signal`; the inverse of [`encode`](Self::encode).
+    pub fn decode(self, signal: f64) -> f64 {
+        let signal = signal.clamp(0.0, 1.0);
+        match self {
+            TransferFunction::Linear => signal,
+            TransferFunction::Srgb => if signal <= 0.04045 { signal / 12.92 } else { ((signal + 0.055) / 1.055).powf(2.4) },
+            TransferFunction::Gamma(gamma) => signal.powf(gamma),
+            TransferFunction::Pq => {
+                let power = signal.powf(1.0 / Self::PQ_M2);
+                ((power - Self::PQ_C1).max(0.0) / (Self::PQ_C2 - Self::PQ_C3 * power)).powf(1.0 / Self::PQ_M1)
+            }
+            TransferFunction::Hlg => if signal <= 0.5 {
+                signal * signal / 3.0
+            } else {
+                (((signal - Self::HLG_C) / Self::HLG_A).exp() + Self::HLG_B) / 12.0
+            },
+        }
+    }
+}
+
+/// Display encoding of rendered images. Scene-linear `1` is diffuse white, placed at the reference white of
+/// ITU-R BT.2408 on HDR displays: 203 cd/m² for PQ and a 75% signal for HLG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputTransform {
+    Srgb,
+    DisplayP3,
+    Rec2020Pq,
+    Rec2020Hlg,
+}
+
+impl OutputTransform {
+    const PQ_REFERENCE_WHITE: f64 = 203.0 / 10000.0;
+    const HLG_REFERENCE_SIGNAL: f64 = 0.75;
+
+    pub fn space(self) -> ColorSpace {
+        match self {
+            OutputTransform::Srgb => ColorSpace::LinearSrgb,
+            OutputTransform::DisplayP3 => ColorSpace::DisplayP3,
+            OutputTransform::Rec2020Pq | OutputTransform::Rec2020Hlg => ColorSpace::Rec2020,
+        }
+    }
+
+    pub fn transfer(self) -> TransferFunction {
+        match self {
+            OutputTransform::Srgb | OutputTransform::DisplayP3 => TransferFunction::Srgb,
+            OutputTransform::Rec2020Pq => TransferFunction::Pq,
+            OutputTransform::Rec2020Hlg => TransferFunction::Hlg,
+        }
+    }
+
+    /// Linear value the transfer function receives for scene-linear `1`.
+    fn white(self) -> f64 {
+        match self {
+            OutputTransform::Srgb | OutputTransform::DisplayP3 => 1.0,
+            OutputTransform::Rec2020Pq => Self::PQ_REFERENCE_WHITE,
+            OutputTransform::Rec2020Hlg => TransferFunction::Hlg.decode(Self::HLG_REFERENCE_SIGNAL),
+        }
+    }
+
+    /// Display signals in `[0, 1]` for a scene-linear colour in `space`; out-of-gamut components clip.
+    pub fn encode(self, color: &Color, space: ColorSpace) -> Color {
+        let color = space.convert(color, self.space());
+        let (transfer, white) = (self.transfer(), self.white());
+        Color { r: transfer.encode(color.r * white), g: transfer.encode(color.g * white), b: transfer.encode(color.b * white) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Matrix3;
+    use crate::ray_tracing::color_space::{ColorSpace, OutputTransform, TransferFunction};
+    use crate::ray_tracing::scene::material::Color;
+
+    fn assert_matrix(actual: Matrix3, expected: [[f64; 3]; 3], tolerance: f64) {
+        for (row, expected) in expected.iter().enumerate() {
+            for (column, expected) in expected.iter().enumerate() {
+                assert!((actual.get(row, column) - expected).abs() < tolerance, "{:?} expected {:?}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn color_space_test() {
+        // Reference matrices from IEC 61966-2-1, ITU-R BT.2087, SMPTE EG 432-1 and the ACES documentation.
+        assert_matrix(ColorSpace::LinearSrgb.to_xyz(), [[0.4124, 0.3576, 0.1805], [0.2126, 0.7152, 0.0722], [0.0193, 0.1192, 0.9505]], 1e-4);
+        assert_matrix(ColorSpace::AcesCg.to_xyz(), [[0.6624, 0.1340, 0.1562], [0.2722, 0.6741, 0.0537], [-0.0056, 0.0041, 1.0103]], 1e-4);
+        assert_matrix(ColorSpace::LinearSrgb.conversion(ColorSpace::Rec2020), [[0.6274, 0.3293, 0.0433], [0.0691, 0.9195, 0.0114], [0.0164, 0.0880, 0.8956]], 1e-4);
+        assert_matrix(ColorSpace::LinearSrgb.conversion(ColorSpace::DisplayP3), [[0.8225, 0.1774, 0.0], [0.0332, 0.9669, 0.0], [0.0171, 0.0724, 0.9108]], 5e-4);
+        assert_matrix(ColorSpace::LinearSrgb.conversion(ColorSpace::AcesCg), [[0.6131, 0.3395, 0.0474], [0.0702, 0.9164, 0.0134], [0.0206, 0.1096, 0.8698]], 1e-3);
+
+        let color = Color { r: 0.8, g: 0.3, b: 0.1 };
+        assert!((ColorSpace::LinearSrgb.luminance(&color) - (0.2126 * 0.8 + 0.7152 * 0.3 + 0.0722 * 0.1)).abs() < 1e-4);
+        for space in ColorSpace::ALL {
+            // White stays white and conversions round-trip.
+            let white = ColorSpace::LinearSrgb.convert(&Color { r: 1.0, g: 1.0, b: 1.0 }, space);
+            for c in [white.r, white.g, white.b] {
+                assert!((c - 1.0).abs() < 1e-9, "{:?} {:?}", space, white);
+            }
+            assert!((space.luminance(&white) - 1.0).abs() < 1e-9);
+            let back = space.convert(&ColorSpace::AcesCg.convert(&ColorSpace::LinearSrgb.convert(&color, space), ColorSpace::AcesCg), ColorSpace::LinearSrgb);
+            assert!((back.r - color.r).abs() < 1e-9 && (back.g - color.g).abs() < 1e-9 && (back.b - color.b).abs() < 1e-9, "{:?}", back);
+        }
+    }
+
+    #[test]
+    fn transfer_function_test() {
+        assert!((TransferFunction::Srgb.encode(0.5) - 0.735_356_983).abs() < 1e-9);
+        assert!((TransferFunction::Srgb.encode(0.001) - 0.01292).abs() < 1e-12);
+        assert!((TransferFunction::Gamma(2.2).encode(0.5) - 0.5f64.powf(1.0 / 2.2)).abs() < 1e-12);
+        // ST 2084: 100 cd/m² encodes to 0.5081 and 10000 cd/m² to one.
+        assert!((TransferFunction::Pq.encode(0.01) - 0.508_078).abs() < 1e-5);
+        assert!((TransferFunction::Pq.encode(1.0) - 1.0).abs() < 1e-12);
+        assert!(TransferFunction::Pq.encode(0.0) < 1e-6);
+        // BT.2100 HLG: the square-root segment meets the logarithm at 1/12.
+        assert!((TransferFunction::Hlg.encode(1.0 / 12.0) - 0.5).abs() < 1e-12);
+        assert!((TransferFunction::Hlg.encode(1.0) - 1.0).abs() < 1e-6);
+        for transfer in [TransferFunction::Linear, TransferFunction::Srgb, TransferFunction::Gamma(2.4), TransferFunction::Pq, TransferFunction::Hlg] {
+            for value in [0.0, 0.002, 0.05, 0.3, 0.9, 1.0] {
+                assert!((transfer.decode(transfer.encode(value)) - value).abs() < 1e-9, "{:?} {}", transfer, value);
+            }
+        }
+    }
+
+    #[test]
+    fn output_transform_test() {
+        let white = Color { r: 1.0, g: 1.0, b: 1.0 };
+        let srgb = OutputTransform::Srgb.encode(&white, ColorSpace::LinearSrgb);
+        assert!((srgb.r - 1.0).abs() < 1e-9 && (srgb.b - 1.0).abs() < 1e-9);
+        // Reference white sits at 58% of the PQ signal and 75% of the HLG signal.
+        let pq = OutputTransform::Rec2020Pq.encode(&white, ColorSpace::LinearSrgb);
+        assert!((pq.g - 0.5806).abs() < 1e-3, "{:?}", pq);
+        let hlg = OutputTransform::Rec2020Hlg.encode(&white, ColorSpace::AcesCg);
+        assert!((hlg.g - 0.75).abs() < 1e-9, "{:?}", hlg);
+        // Saturated sRGB red lies inside P3 and encodes to less than full red there.
+        let red = OutputTransform::DisplayP3.encode(&Color { r: 1.0, g: 0.0, b: 0.0 }, ColorSpace::LinearSrgb);
+        assert!(red.r < 1.0 && red.g > 0.0 && red.b > 0.0, "{:?}", red);
+    }
+}
@@ -0,0 +1,226 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::geometry::Vec3;
+use crate::ray_tracing::color_space::ColorSpace;
+use crate::ray_tracing::scene::material::Color;
+
+/// B3 spline taps of the à-trous kernel, applied along both axes.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Smallest albedo divided out of a pixel; darker channels are filtered as they are.
+const MIN_ALBEDO: f64 = 0.01;
+
+/// Per-pixel guides for the denoiser, usually taken from the first visible surfaces.
+#[derive(Debug, Clone)]
+pub struct Features {
+    pub albedo: Color,
+    /// Mean shading normal; zero where nothing was hit.
+    pub normal: Vec3<f64>,
+    /// Distance to the first visible surface, infinite where nothing was hit.
+    pub depth: f64,
+}
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) with the variance-guided luminance weights of SVGF
+/// (Schied et al. 2017). Lighting is filtered apart from albedo, so textures stay sharp.
+#[derive(Debug, Clone)]
+pub struct Denoiser {
+    /// Number of passes; each doubles the spacing between the taps of the 5×5 kernel.
+    pub iterations: usize,
+    /// Luminance difference tolerated between pixels, in standard deviations of their noise.
+    pub sigma_luminance: f64,
+    /// Exponent applied to the cosine between normals.
+    pub sigma_normal: f64,
+    /// Depth difference tolerated between pixels, relative to the local depth gradient.
+    pub sigma_depth: f64,
+    /// Albedo difference tolerated between pixels.
+    pub sigma_albedo: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self { iterations: 5, sigma_luminance: 4.0, sigma_normal: 128.0, sigma_depth: 1.0, sigma_albedo: 0.1 }
+    }
+}
+
+impl Denoiser {
+    /// Filters `color`, whose luminance in `space` has the per-pixel variance `variance`, guided by `features`.
+    /// All buffers hold `width * height` pixels in scanline order.
+    pub fn denoise(&self, color: &[Color], variance: &[f64], features: &[Features], width: usize, height: usize, space: ColorSpace) -> Vec<Color> {
+        assert!(color.len() == width * height && variance.len() == width * height && features.len() == width * height, "buffers must match the image size");
+        let modulation: Vec<Color> = features.iter().map(|features| {
+            let albedo = &features.albedo;
+            let channel = |c: f64| if c > MIN_ALBEDO { c } else { 1.0 };
+            Color { r: channel(albedo.r), g: channel(albedo.g), b: channel(albedo.b) }
+        }).collect();
+        let mut lighting: Vec<Color> = color.iter().zip(&modulation)
+            .map(|(color, modulation)| Color { r: color.r / modulation.r, g: color.g / modulation.g, b: color.b / modulation.b })
+            .collect();
+        let mut variance: Vec<f64> = variance.iter().zip(&modulation)
+            .map(|(variance, modulation)| variance / space.luminance(modulation).powi(2))
+            .collect();
+        let gradients = depth_gradients(features, width, height);
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            // Variance estimates from few samples are noisy themselves, so the weights see them slightly blurred.
+            let blurred = blur(&variance, width, height);
+            let luminance: Vec<f64> = lighting.iter().map(|color| space.luminance(color)).collect();
+            let (next_lighting, next_variance) = (0..width * height).into_par_iter().map(|i| {
+                let (x, y) = (i % width, i / width);
+                let center = &features[i];
+                let scale = self.sigma_luminance * blurred[i].max(0.0).sqrt() + 1e-9;
+                let (mut sum, mut variance_sum, mut weights) = (Color::zero(), 0.0, 0.0);
+                for (dy, ky) in (-2..=2isize).zip(KERNEL) {
+                    for (dx, kx) in (-2..=2isize).zip(KERNEL) {
+                        let (qx, qy) = (x as isize + dx * step, y as isize + dy * step);
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+                        let j = qy as usize * width + qx as usize;
+                        let other = &features[j];
+                        let distance = (((dx * dx + dy * dy) * step * step) as f64).sqrt();
+                        let weight = if i == j {
+                            kx * ky
+                        } else {
+                            kx * ky
+                                * (-(luminance[i] - luminance[j]).abs() / scale).exp()
+                                * normal_weight(center.normal, other.normal, self.sigma_normal)
+                                * depth_weight(center.depth, other.depth, gradients[i] * distance * self.sigma_depth)
+                                * (-squared_distance(&center.albedo, &other.albedo) / (self.sigma_albedo * self.sigma_albedo)).exp()
+                        };
+                        sum = sum + lighting[j].clone() * Color { r: weight, g: weight, b: weight };
+                        variance_sum += weight * weight * variance[j];
+                        weights += weight;
+                    }
+                }
+                (Color { r: sum.r / weights, g: sum.g / weights, b: sum.b / weights }, variance_sum / (weights * weights))
+            }).unzip();
+            lighting = next_lighting;
+            variance = next_variance;
+        }
+        lighting.into_iter().zip(modulation).map(|(lighting, modulation)| lighting * modulation).collect()
+    }
+}
+
+fn squared_distance(a: &Color, b: &Color) -> f64 {
+    (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2)
+}
+
+/// Similarity of two mean normals. Pixels that hit nothing only match each other.
+fn normal_weight(a: Vec3<f64>, b: Vec3<f64>, exponent: f64) -> f64 {
+    let (a_len, b_len): (f64, f64) = (a.squared_len::<f64>().sqrt(), b.squared_len::<f64>().sqrt());
+    if a_len == 0.0 || b_len == 0.0 {
+        return if a_len == b_len { 1.0 } else { 0.0 };
+    }
+    (a.inner_product::<_, f64>(b) / (a_len * b_len)).max(0.0).powf(exponent)
+}
+
+/// Similarity of two depths given the difference `tolerance` expected between them on a continuous surface.
+fn depth_weight(a: f64, b: f64, tolerance: f64) -> f64 {
+    if a.is_infinite() || b.is_infinite() {
+        return if a == b { 1.0 } else { 0.0 };
+    }
+    (-(a - b).abs() / (tolerance + 1e-9)).exp()
+}
+
+/// Change of depth per pixel. Each axis takes the smaller difference to its two neighbours, so depth edges do not
+/// widen the tolerance of the pixels beside them.
+fn depth_gradients(features: &[Features], width: usize, height: usize) -> Vec<f64> {
+    let depth = |x: isize, y: isize| {
+        if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+            f64::INFINITY
+        } else {
+            features[y as usize * width + x as usize].depth
+        }
+    };
+    (0..width * height).map(|i| {
+        let (x, y) = ((i % width) as isize, (i / width) as isize);
+        let center = depth(x, y);
+        let axis = |a: f64, b: f64| {
+            let difference = (center - a).abs().min((center - b).abs());
+            if difference.is_finite() { difference } else { 0.0 }
+        };
+        axis(depth(x - 1, y), depth(x + 1, y)).max(axis(depth(x, y - 1), depth(x, y + 1)))
+    }).collect()
+}
+
+/// 3×3 binomial blur with clamped borders.
+fn blur(values: &[f64], width: usize, height: usize) -> Vec<f64> {
+    const TAPS: [f64; 3] = [0.25, 0.5, 0.25];
+    (0..width * height).map(|i| {
+        let (x, y) = (i % width, i / width);
+        let mut sum = 0.0;
+        for (dy, ky) in (-1..=1isize).zip(TAPS) {
+            for (dx, kx) in (-1..=1isize).zip(TAPS) {
+                let qx = (x as isize + dx).clamp(0, width as isize - 1) as usize;
+                let qy = (y as isize + dy).clamp(0, height as isize - 1) as usize;
+                sum += kx * ky * values[qy * width + qx];
+            }
+        }
+        sum
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::color_space::ColorSpace;
+    use crate::ray_tracing::denoise::{Denoiser, Features};
+    use crate::ray_tracing::scene::material::Color;
+
+    fn mse(a: &[Color], b: &[Color]) -> f64 {
+        a.iter().zip(b).map(|(a, b)| (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2)).sum::<f64>() / a.len() as f64
+    }
+
+    #[test]
+    fn denoise_test() {
+        // A textured floor meeting a wall, lit by a smooth gradient; each pixel averages a few noisy samples.
+        let (width, height, samples) = (64, 48, 4);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut truth = Vec::new();
+        let mut noisy = Vec::new();
+        let mut variance = Vec::new();
+        let mut features = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let checker = if (x / 8 + y / 8) % 2 == 0 { 0.8 } else { 0.2 };
+                let wall = y < height / 2;
+                let albedo = if wall { Color { r: 0.8, g: 0.3, b: 0.3 } } else { Color { r: checker, g: checker, b: checker } };
+                let lighting = if wall { 0.4 + 0.4 * x as f64 / width as f64 } else { 1.0 };
+                let expected = albedo.clone() * Color { r: lighting, g: lighting, b: lighting };
+                let (mut sum, mut squares) = (Color::zero(), 0.0);
+                for _ in 0..samples {
+                    let u: f64 = rng.gen_range(0.0..2.0);
+                    let sample = expected.clone() * Color { r: u, g: u, b: u };
+                    squares += ColorSpace::LinearSrgb.luminance(&sample).powi(2);
+                    sum = sum + sample;
+                }
+                let count = samples as f64;
+                let mean = Color { r: sum.r / count, g: sum.g / count, b: sum.b / count };
+                let luminance = ColorSpace::LinearSrgb.luminance(&mean);
+                variance.push((squares / count - luminance * luminance).max(0.0) / (count - 1.0));
+                noisy.push(mean);
+                truth.push(expected);
+                features.push(Features {
+                    albedo,
+                    normal: if wall { Vec3::new(0.0, 0.0, 1.0) } else { Vec3::new(0.0, 1.0, 0.0) },
+                    depth: if wall { 5.0 } else { 2.0 + 3.0 * (height - y) as f64 / height as f64 },
+                });
+            }
+        }
+        let denoised = Denoiser::default().denoise(&noisy, &variance, &features, width, height, ColorSpace::LinearSrgb);
+        let (before, after) = (mse(&noisy, &truth), mse(&denoised, &truth));
+        assert!(after < before / 10.0, "{} {}", before, after);
+
+        // Edges in the guides survive: the rows either side of the fold keep their own colours.
+        let fold = (height / 2) * width + width / 2;
+        for i in [fold - width, fold] {
+            assert!((denoised[i].g - truth[i].g).abs() < 0.1, "{:?} {:?}", denoised[i], truth[i]);
+        }
+
+        // Without passes the image is unchanged.
+        let unchanged = Denoiser { iterations: 0, ..Denoiser::default() }.denoise(&noisy, &variance, &features, width, height, ColorSpace::LinearSrgb);
+        assert!(mse(&unchanged, &noisy) < 1e-20);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::geometry::simd::Vec3A;
+use crate::geometry::{Aabb, Vec3};
+use crate::ray_tracing::Ray;
+
+/// Number of rays [`Collision::collision_packet`](super::scene::Collision::collision_packet) traces together.
+/// Eight lanes fill an AVX register; four fill SSE and NEON registers.
+#[cfg(feature = "wide-packets")]
+pub const PACKET_WIDTH: usize = 8;
+#[cfg(not(feature = "wide-packets"))]
+pub const PACKET_WIDTH: usize = 4;
+
+/// Relative slack for the single-precision box test, covering the rounding of inputs and arithmetic many times over.
+const SLACK: f32 = 1e-5;
+
+/// Widens a parametric range converted to single precision.
+fn widen(t_min: f64, t_max: f64) -> (f32, f32) {
+    let (t_min, t_max) = (t_min as f32, t_max as f32);
+    (t_min - t_min.abs() * SLACK, t_max + t_max.abs() * SLACK)
+}
+
+/// `N` rays in structure-of-arrays single precision, so the tests below run across all lanes at once.
+/// The original rays are kept to fill in the details of the hits found.
+pub struct RayPacket<const N: usize> {
+    rays: [Ray; N],
+    initial: [[f32; N]; 3],
+    inverse_direction: [[f32; N]; 3],
+    direction: [[f32; N]; 3],
+    /// Largest origin coordinate per lane, scaling the error of `initial`.
+    magnitude: [f32; N],
+}
+
+impl<const N: usize> RayPacket<N> {
+    pub fn new(rays: [Ray; N]) -> Self {
+        let mut packet = Self {
+            initial: [[0.0; N]; 3],
+            inverse_direction: [[0.0; N]; 3],
+            direction: [[0.0; N]; 3],
+            magnitude: [0.0; N],
+            rays,
+        };
+        for (i, ray) in packet.rays.iter().enumerate() {
+            let initial = Vec3A::from(ray.initial);
+            let direction = Vec3A::from(ray.direction.vec());
+            for (axis, (o, d)) in [(initial.x(), direction.x()), (initial.y(), direction.y()), (initial.z(), direction.z())].iter().enumerate() {
+                packet.initial[axis][i] = *o;
+                packet.direction[axis][i] = *d;
+                // A finite stand-in for infinity keeps `0 * inverse_direction` from producing NaN in the slab test.
+                packet.inverse_direction[axis][i] = (1.0 / d).clamp(-f32::MAX, f32::MAX);
+            }
+            packet.magnitude[i] = initial.abs().max_element();
+        }
+        packet
+    }
+
+    pub fn rays(&self) -> &[Ray; N] {
+        &self.rays
+    }
+
+    /// Lanes whose ray may enter `aabb` within `[t_min, t_max[i]]`. Conservative: a lane reported as missing misses in exact arithmetic.
+    #[inline]
+    pub fn intersect_aabb(&self, aabb: &Aabb, t_min: f64, t_max: &[f64; N]) -> [bool; N] {
+        let (min, max) = (Vec3A::from(aabb.min()), Vec3A::from(aabb.max()));
+        let size = min.abs().max(max.abs()).max_element();
+        let mut t0 = [0.0; N];
+        let mut t1 = [0.0; N];
+        let mut pad = [0.0; N];
+        for i in 0..N {
+            let (near, far) = widen(t_min, t_max[i]);
+            t0[i] = near;
+            t1[i] = far;
+            pad[i] = (size + self.magnitude[i]) * SLACK;
+        }
+        // Plain comparisons instead of `f32::min` and `f32::max`, whose NaN handling keeps the loops from vectorizing.
+        for (axis, (min, max)) in [(min.x(), max.x()), (min.y(), max.y()), (min.z(), max.z())].iter().enumerate() {
+            let (o, inv) = (&self.initial[axis], &self.inverse_direction[axis]);
+            for i in 0..N {
+                let a = (min - pad[i] - o[i]) * inv[i];
+                let b = (max + pad[i] - o[i]) * inv[i];
+                let (near, far) = if a < b { (a, b) } else { (b, a) };
+                t0[i] = if near > t0[i] { near } else { t0[i] };
+                t1[i] = if far < t1[i] { far } else { t1[i] };
+            }
+        }
+        let mut hits = [false; N];
+        for i in 0..N {
+            hits[i] = t0[i] <= t1[i];
+        }
+        hits
+    }
+
+    /// Distance along each lane's ray to its nearest intersection with the sphere around `centers[i]` within `(t_min, t_max[i])`,
+    /// or infinity where there is none. Solved in single precision for all lanes at once, so distances are only accurate to
+    /// about `1e-6` of the scene scale: enough for camera rays, too little for rays leaving a surface they must not hit again.
+    #[inline]
+    pub fn intersect_sphere(&self, centers: &[Vec3<f64>; N], radius: f64, t_min: f64, t_max: &[f64; N]) -> [f32; N] {
+        let radius2 = (radius * radius) as f32;
+        let t_min = t_min as f32;
+        let mut oc = [[0.0f32; N]; 3];
+        let mut far = [0.0f32; N];
+        for (i, center) in centers.iter().enumerate() {
+            let center = Vec3A::from(*center);
+            oc[0][i] = center.x() - self.initial[0][i];
+            oc[1][i] = center.y() - self.initial[1][i];
+            oc[2][i] = center.z() - self.initial[2][i];
+            far[i] = t_max[i] as f32;
+        }
+        let [dx, dy, dz] = &self.direction;
+        let mut distances = [f32::INFINITY; N];
+        for i in 0..N {
+            let b = oc[0][i] * dx[i] + oc[1][i] * dy[i] + oc[2][i] * dz[i];
+            // The distance to the line through the perpendicular is better conditioned than |oc|^2 - b^2.
+            let (px, py, pz) = (oc[0][i] - dx[i] * b, oc[1][i] - dy[i] * b, oc[2][i] - dz[i] * b);
+            let h = radius2 - (px * px + py * py + pz * pz);
+            let root = (if h > 0.0 { h } else { 0.0 }).sqrt();
+            let t = if b - root > t_min { b - root } else { b + root };
+            distances[i] = if h >= 0.0 && t > t_min && t < far[i] { t } else { f32::INFINITY };
+        }
+        distances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::{Aabb, Vec3};
+    use crate::ray_tracing::packet::RayPacket;
+    use crate::ray_tracing::Ray;
+
+    fn random_packet<const N: usize>(rng: &mut StdRng) -> RayPacket<N> {
+        RayPacket::new([(); N].map(|_| {
+            let initial = Vec3::new(rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            Ray::new(initial, direction.normalize(), 0.0)
+        }))
+    }
+
+    fn packet_matches_scalar<const N: usize>() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let aabb = Aabb::new(Vec3::new(-1.0, -0.5, 0.0), Vec3::new(1.0, 0.5, 2.0));
+        let center = Vec3::new(0.5, -0.25, 1.0);
+        for _ in 0..2000 {
+            let packet = random_packet::<N>(&mut rng);
+            let t_max = [(); N].map(|_| rng.gen_range(0.5..10.0));
+            let boxes = packet.intersect_aabb(&aabb, 0.0, &t_max);
+            let spheres = packet.intersect_sphere(&[center; N], 0.75, 0.0, &t_max);
+            for (i, ray) in packet.rays().iter().enumerate() {
+                let d = ray.direction.vec();
+                let inverse_direction = Vec3::new(1.0 / d.x(), 1.0 / d.y(), 1.0 / d.z());
+                if aabb.intersect(ray.initial, inverse_direction, 0.0, t_max[i]).is_some() {
+                    assert!(boxes[i], "box culled a ray that hits it");
+                }
+                let oc = center - ray.initial;
+                let b = oc.inner_product(ray.direction.vec());
+                let discriminant = b * b - (oc.squared_len() - 0.75 * 0.75);
+                let root = discriminant.max(0.0).sqrt();
+                let t = if b - root > 0.0 { b - root } else { b + root };
+                let expected = if discriminant >= 0.0 && t > 0.0 && t < t_max[i] { t } else { f64::INFINITY };
+                // Grazing rays and hits right at the ends of the range may go either way in single precision.
+                let marginal = discriminant.abs() < 1e-4 || (t - t_max[i]).abs() < 1e-4 || t.abs() < 1e-4;
+                if expected.is_finite() && spheres[i].is_finite() {
+                    assert!((spheres[i] as f64 - expected).abs() < 1e-4, "{} {}", spheres[i], expected);
+                } else {
+                    assert!(marginal || expected.is_finite() == spheres[i].is_finite(), "{} {}", spheres[i], expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn packet_matches_scalar_test() {
+        packet_matches_scalar::<4>();
+        packet_matches_scalar::<8>();
+    }
+
+    #[test]
+    fn packet_culls_misses_test() {
+        let rays = [Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)]
+            .map(|direction| Ray::new(Vec3::new(0.0, 0.0, 5.0), direction.normalize(), 0.0));
+        let packet = RayPacket::new(rays);
+        let aabb = Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let t_max = [f64::INFINITY, f64::INFINITY, f64::INFINITY, 3.0];
+        assert_eq!(packet.intersect_aabb(&aabb, 0.0, &t_max), [true, false, false, false]);
+        assert_eq!(packet.intersect_sphere(&[Vec3::new(0.0, 0.0, 0.0); 4], 1.0, 0.0, &t_max), [4.0, f32::INFINITY, f32::INFINITY, f32::INFINITY]);
+    }
+}
@@ -1,11 +1,327 @@
-use crate::geometry::NormalizedVec3;
+use std::sync::Arc;
+
+use crate::geometry::{Aabb, NormalizedVec3, Vec3};
+use crate::ray_tracing::packet::{RayPacket, PACKET_WIDTH};
 use crate::ray_tracing::Ray;
-use crate::ray_tracing::scene::material::Material;
+use crate::ray_tracing::scene::material::{Material, ShadingGeometry};
+use crate::ray_tracing::scene::texture::TextureCoordinate;
 
+pub mod bvh;
 pub mod camera;
+pub mod csg;
+pub mod instance;
 pub mod object;
 pub mod material;
+pub mod medium;
+pub mod texture;
+
+/// Everything known about a ray's hit on a surface, produced by [`Collision`] and consumed by materials and integrators.
+/// Borrows the material of the object that was hit, so hit tests never copy material data.
+#[derive(Debug, Clone)]
+pub struct SurfaceInteraction<'a> {
+    pub distance: f64,
+    /// Geometric normal, pointing out of the primitive regardless of the side that was hit.
+    pub normal: NormalizedVec3<f64>,
+    /// Whether the ray arrived on the side `normal` points to.
+    pub front_face: bool,
+    /// Surface parameterization in `[0, 1]^2`, used for texture lookups.
+    pub uv: (f64, f64),
+    pub position: Vec3<f64>,
+    /// Conservative per-axis bound on the floating-point error in `position`.
+    pub error: Vec3<f64>,
+    /// Hit position in the primitive's own frame, for object-space textures.
+    pub local_position: Vec3<f64>,
+    pub dpdu: Vec3<f64>,
+    pub dpdv: Vec3<f64>,
+    pub dndu: Vec3<f64>,
+    pub dndv: Vec3<f64>,
+    /// Frame perturbed by the material, e.g. by a normal map. `None` until [`resolve_material`](Self::resolve_material) sets it.
+    pub shading: Option<ShadingGeometry>,
+    /// Index of the hit object within the outermost aggregate that reported it.
+    pub primitive_id: usize,
+    pub material: &'a Material,
+}
+
+/// How the hit position and surface parameterization change between neighbouring pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceDifferentials {
+    pub dpdx: Vec3<f64>,
+    pub dpdy: Vec3<f64>,
+    pub duv_dx: (f64, f64),
+    pub duv_dy: (f64, f64),
+}
+
+impl SurfaceDifferentials {
+    pub fn zero() -> Self {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        Self { dpdx: zero, dpdy: zero, duv_dx: (0.0, 0.0), duv_dy: (0.0, 0.0) }
+    }
+}
+
+impl<'a> SurfaceInteraction<'a> {
+    /// The surface's own differential geometry, turned to face the incoming ray as surfaces are two-sided.
+    pub fn geometry(&self) -> ShadingGeometry {
+        let sign = if self.front_face { 1.0 } else { -1.0 };
+        ShadingGeometry {
+            normal: self.normal.vec() * sign,
+            dpdu: self.dpdu,
+            dpdv: self.dpdv,
+            dndu: self.dndu * sign,
+            dndv: self.dndv * sign,
+        }
+    }
+
+    /// Shading geometry facing the incoming ray, falling back to [`geometry`](Self::geometry) when no material perturbed it.
+    pub fn shading(&self) -> ShadingGeometry {
+        self.shading.unwrap_or_else(|| self.geometry())
+    }
+
+    /// Strips bump layers off the material, recording the shading frame they produce.
+    pub fn resolve_material(&mut self, coordinate: &TextureCoordinate) -> &'a Material {
+        let geometry = self.geometry();
+        let (material, normal) = self.material.resolve(&geometry, coordinate);
+        self.shading = Some(ShadingGeometry { normal, ..geometry });
+        material
+    }
+
+    /// Intersects the offset rays of `ray` with the tangent plane at the hit and expresses the offsets in `(u, v)`.
+    pub fn differentials(&self, ray: &Ray) -> SurfaceDifferentials {
+        let differential = match &ray.differential {
+            Some(differential) => differential,
+            None => return SurfaceDifferentials::zero(),
+        };
+        let normal: Vec3<f64> = self.normal.into();
+        let plane = normal.inner_product(self.position);
+        let offset = |initial: Vec3<f64>, direction: NormalizedVec3<f64>| {
+            let direction: Vec3<f64> = direction.into();
+            let t = (plane - normal.inner_product(initial)) / normal.inner_product(direction);
+            initial + direction * t - self.position
+        };
+        let dpdx = offset(differential.x_initial, differential.x_direction);
+        let dpdy = offset(differential.y_initial, differential.y_direction);
+        if !(dpdx.squared_len().is_finite() && dpdy.squared_len().is_finite()) {
+            return SurfaceDifferentials::zero();
+        }
+
+        // Solve the over-determined system dp = dpdu * du + dpdv * dv on the two axes where the normal is smallest.
+        let (nx, ny, nz) = (normal.x().abs(), normal.y().abs(), normal.z().abs());
+        let axes = |v: Vec3<f64>| if nx > ny && nx > nz {
+            (*v.y(), *v.z())
+        } else if ny > nz {
+            (*v.x(), *v.z())
+        } else {
+            (*v.x(), *v.y())
+        };
+        let (a00, a10) = axes(self.dpdu);
+        let (a01, a11) = axes(self.dpdv);
+        let det = a00 * a11 - a01 * a10;
+        let solve = |dp: Vec3<f64>| {
+            if det.abs() < 1e-20 {
+                return (0.0, 0.0);
+            }
+            let (b0, b1) = axes(dp);
+            let du = (a11 * b0 - a01 * b1) / det;
+            let dv = (a00 * b1 - a10 * b0) / det;
+            if du.is_finite() && dv.is_finite() { (du, dv) } else { (0.0, 0.0) }
+        };
+        SurfaceDifferentials { dpdx, dpdy, duv_dx: solve(dpdx), duv_dy: solve(dpdy) }
+    }
+
+    pub fn texture_coordinate(&self, differentials: &SurfaceDifferentials) -> TextureCoordinate {
+        TextureCoordinate {
+            uv: self.uv,
+            duv_dx: differentials.duv_dx,
+            duv_dy: differentials.duv_dy,
+            position: self.position,
+            local_position: self.local_position,
+        }
+    }
+}
+
+/// Origin for a ray leaving `position` in `direction`: pushed along `normal` just past the error bound,
+/// then rounded away from the surface, so the new ray cannot hit it again at a positive distance.
+pub fn offset_ray_origin(position: Vec3<f64>, error: Vec3<f64>, normal: Vec3<f64>, direction: Vec3<f64>) -> Vec3<f64> {
+    let d = normal.abs().inner_product(error);
+    let offset = if direction.inner_product(normal) < 0.0 { -normal * d } else { normal * d };
+    let p = position + offset;
+    let round = |p: f64, offset: f64| if offset > 0.0 { p.next_up() } else if offset < 0.0 { p.next_down() } else { p };
+    Vec3::new(round(*p.x(), *offset.x()), round(*p.y(), *offset.y()), round(*p.z(), *offset.z()))
+}
+
+pub trait Collision: Send + Sync {
+    /// Nearest hit at a distance in `(t_min, t_max)`.
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>>;
+
+    /// Whether anything but invisible interfaces lies along `ray` closer than `t_max`. Shadow rays need no hit details,
+    /// so implementations may stop at the first such hit in any order.
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        let mut t_min = 0.0;
+        while let Some(hit) = self.collision(ray, t_min, t_max) {
+            if !hit.material.is_interface() {
+                return true;
+            }
+            t_min = hit.distance;
+        }
+        false
+    }
+
+    /// For each `active` lane of `packet`, finds the nearest hit within `(t_min, t_max[i])` as [`collision`](Self::collision) would,
+    /// storing it in `hits[i]` and shrinking `t_max[i]` to its distance. Lanes without such a hit are left untouched.
+    /// Implementations with a vectorized test override the lane-by-lane default.
+    fn collision_packet<'a>(&'a self, packet: &RayPacket<PACKET_WIDTH>, active: &[bool; PACKET_WIDTH], t_min: f64,
+                            t_max: &mut [f64; PACKET_WIDTH], hits: &mut [Option<SurfaceInteraction<'a>>; PACKET_WIDTH]) {
+        for (((ray, &active), t_max), slot) in packet.rays().iter().zip(active).zip(t_max).zip(hits) {
+            if !active {
+                continue;
+            }
+            if let Some(hit) = self.collision(ray, t_min, *t_max) {
+                *t_max = hit.distance;
+                *slot = Some(hit);
+            }
+        }
+    }
+
+    /// Materials this object's hits may borrow, in a fixed order. Listed for every object of a scene in turn, they number
+    /// the scene's materials, as [`Aov::MaterialId`](crate::ray_tracing::aov::Aov::MaterialId) reports them.
+    fn materials(&self) -> Vec<&Material>;
+
+    /// World-space bounds; [`Aabb::infinite`] for unbounded primitives.
+    fn bounding_box(&self) -> Aabb;
+
+    /// Point spread uniformly by area over the surface at `time` for `u` in `[0, 1)^2`, as an interaction at distance zero,
+    /// together with the surface area. Lets integrators aim shadow rays at emitters; `None` for surfaces that cannot be
+    /// sampled.
+    fn sample_area(&self, _u: (f64, f64), _time: f64) -> Option<(SurfaceInteraction<'_>, f64)> {
+        None
+    }
+}
+
+impl<T: Collision + ?Sized> Collision for Arc<T> {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        (**self).collision(ray, t_min, t_max)
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        (**self).occluded(ray, t_max)
+    }
+
+    fn collision_packet<'a>(&'a self, packet: &RayPacket<PACKET_WIDTH>, active: &[bool; PACKET_WIDTH], t_min: f64,
+                            t_max: &mut [f64; PACKET_WIDTH], hits: &mut [Option<SurfaceInteraction<'a>>; PACKET_WIDTH]) {
+        (**self).collision_packet(packet, active, t_min, t_max, hits)
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        (**self).materials()
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn sample_area(&self, u: (f64, f64), time: f64) -> Option<(SurfaceInteraction<'_>, f64)> {
+        (**self).sample_area(u, time)
+    }
+}
+
+impl<T: Collision + ?Sized> Collision for Box<T> {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        (**self).collision(ray, t_min, t_max)
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        (**self).occluded(ray, t_max)
+    }
+
+    fn collision_packet<'a>(&'a self, packet: &RayPacket<PACKET_WIDTH>, active: &[bool; PACKET_WIDTH], t_min: f64,
+                            t_max: &mut [f64; PACKET_WIDTH], hits: &mut [Option<SurfaceInteraction<'a>>; PACKET_WIDTH]) {
+        (**self).collision_packet(packet, active, t_min, t_max, hits)
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        (**self).materials()
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn sample_area(&self, u: (f64, f64), time: f64) -> Option<(SurfaceInteraction<'_>, f64)> {
+        (**self).sample_area(u, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::{Transform, Vec3};
+    use crate::ray_tracing::Ray;
+    use crate::ray_tracing::scene::{offset_ray_origin, Collision};
+    use crate::ray_tracing::scene::instance::Instance;
+    use crate::ray_tracing::scene::material::{Bump, Color, Material};
+    use crate::ray_tracing::scene::object::plane::Plane;
+    use crate::ray_tracing::scene::object::torus::Torus;
+    use crate::ray_tracing::scene::object::Sphere;
+    use crate::ray_tracing::scene::object::test_util::material;
+
+    /// Rays respawned from a hit in random directions must not hit the same surface again right away.
+    fn assert_no_self_intersection(object: &dyn Collision, target: Vec3<f64>, from: Vec3<f64>, rng: &mut StdRng) {
+        let ray = Ray { initial: from, direction: (target - from).normalize(), differential: None, time: 0.0 };
+        let hit = object.collision(&ray, 0.0, f64::INFINITY).unwrap();
+        let scale = hit.position.abs().inner_product(Vec3::new(1.0, 1.0, 1.0)).max(1.0);
+        for _ in 0..1000 {
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize();
+            let initial = offset_ray_origin(hit.position, hit.error, hit.normal.vec(), direction.vec());
+            let ray = Ray { initial, direction, differential: None, time: 0.0 };
+            if let Some(again) = object.collision(&ray, 0.0, f64::INFINITY) {
+                assert!(again.distance > 1e-9 * scale, "self-intersection at distance {}", again.distance);
+            }
+        }
+    }
+
+    #[test]
+    fn offset_ray_origin_test() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let huge = Sphere::new(Vec3::new(1e6 + 1.0, 0.0, 0.0), 1e6, material());
+        assert_no_self_intersection(&huge, Vec3::new(1.0, 0.3, -0.2), Vec3::new(0.0, 0.0, 4.0), &mut rng);
+        let far = Sphere::new(Vec3::new(1e5, -3e4, 2e5), 0.5, material());
+        assert_no_self_intersection(&far, Vec3::new(1e5, -3e4, 2e5), Vec3::new(0.0, 0.0, 0.0), &mut rng);
+        let plane = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.1, 1.0, 0.2).normalize(), material());
+        assert_no_self_intersection(&plane, Vec3::new(-3e3, -1.0, -7e3), Vec3::new(0.0, 5.0, 0.0), &mut rng);
+        let torus: Arc<dyn Collision> = Arc::new(Torus::new(Vec3::new(0.0, 0.0, 0.0), 2.0, 0.5, material()));
+        let instance = Instance::new(torus, Transform::translate(Vec3::new(1e3, 2e3, -5e2)) * Transform::scale(Vec3::new(3.0, 1.0, 2.0)));
+        assert_no_self_intersection(&instance, Vec3::new(1e3 + 6.0, 2e3, -5e2), Vec3::new(1e3 + 20.0, 2e3 + 1.0, -5e2), &mut rng);
+    }
+
+    #[test]
+    fn surface_interaction_test() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material());
+        let outside = Ray { initial: Vec3::new(0.0, 0.0, 3.0), direction: Vec3::new(0.0, 0.0, -1.0).normalize(), differential: None, time: 0.0 };
+        let hit = sphere.collision(&outside, 0.0, f64::INFINITY).unwrap();
+        assert!(hit.front_face);
+        assert!(hit.shading.is_none());
+        assert!((hit.shading().normal - Vec3::new(0.0, 0.0, 1.0)).squared_len() < 1e-18);
+
+        // From inside, the geometric normal still points out but shading faces the ray.
+        let inside = Ray { initial: Vec3::new(0.0, 0.0, 0.0), ..outside };
+        let hit = sphere.collision(&inside, 0.0, f64::INFINITY).unwrap();
+        assert!(!hit.front_face);
+        assert!((hit.normal.vec() - Vec3::new(0.0, 0.0, -1.0)).squared_len() < 1e-18);
+        let geometry = hit.geometry();
+        assert!((geometry.normal - Vec3::new(0.0, 0.0, 1.0)).squared_len() < 1e-18);
+        assert!((geometry.dndu + hit.dndu).squared_len() < 1e-18);
 
-pub trait Collision {
-    fn collision(&self, ray: &Ray) -> Option<(f64, NormalizedVec3<f64>, Material)>;
+        let bumped = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Bumped {
+            material: Box::new(material()),
+            bump: Bump::Normal(Color { r: 1.0, g: 0.5, b: 1.0 }.into()),
+        });
+        let mut hit = bumped.collision(&outside, 0.0, f64::INFINITY).unwrap();
+        let coordinate = hit.texture_coordinate(&hit.differentials(&outside));
+        assert!(matches!(hit.resolve_material(&coordinate), Material::Solid { .. }));
+        let shading = hit.shading.unwrap();
+        assert!((shading.normal.inner_product(hit.normal.vec()) - 0.5f64.sqrt()).abs() < 1e-9);
+    }
 }
@@ -0,0 +1,299 @@
+use std::sync::Arc;
+
+use crate::geometry::{Aabb, Vec3};
+use crate::ray_tracing::packet::{RayPacket, PACKET_WIDTH};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::material::Material;
+
+enum Node {
+    Leaf { aabb: Aabb, start: usize, end: usize },
+    Interior { aabb: Aabb, left: usize, right: usize },
+}
+
+impl Node {
+    fn aabb(&self) -> &Aabb {
+        match self {
+            Node::Leaf { aabb, .. } | Node::Interior { aabb, .. } => aabb,
+        }
+    }
+}
+
+/// An object with its index in the list the hierarchy was built from.
+type Entry = (usize, Arc<dyn Collision>);
+
+/// Bounding volume hierarchy over shared objects, split at the centroid median of the longest axis.
+/// Unbounded objects are kept out of the tree and tested against every ray.
+pub struct Bvh {
+    /// Objects in tree order.
+    objects: Vec<Entry>,
+    unbounded: Vec<Entry>,
+    nodes: Vec<Node>,
+}
+
+impl Bvh {
+    const LEAF_SIZE: usize = 2;
+
+    pub fn new(objects: Vec<Arc<dyn Collision>>) -> Self {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = objects.into_iter().enumerate().partition(|(_, object)| object.bounding_box().is_finite());
+        let mut entries: Vec<(Aabb, Entry)> = bounded.into_iter().map(|entry| (entry.1.bounding_box(), entry)).collect();
+        let mut nodes = Vec::new();
+        if !entries.is_empty() {
+            let count = entries.len();
+            Self::build(&mut entries, 0, count, &mut nodes);
+        }
+        Self {
+            objects: entries.into_iter().map(|(_, object)| object).collect(),
+            unbounded,
+            nodes,
+        }
+    }
+
+    fn build(entries: &mut [(Aabb, Entry)], start: usize, end: usize, nodes: &mut Vec<Node>) -> usize {
+        let aabb = entries[start..end].iter().fold(Aabb::empty(), |aabb, (bounds, _)| aabb.union(bounds));
+        let index = nodes.len();
+        if end - start <= Self::LEAF_SIZE {
+            nodes.push(Node::Leaf { aabb, start, end });
+            return index;
+        }
+        let centroids = entries[start..end].iter().fold(Aabb::empty(), |centroids, (bounds, _)| {
+            let center = bounds.center();
+            centroids.union(&Aabb::new(center, center))
+        });
+        let axis = centroids.longest_axis();
+        let key = |aabb: &Aabb| {
+            let center = aabb.center();
+            match axis {
+                0 => *center.x(),
+                1 => *center.y(),
+                _ => *center.z(),
+            }
+        };
+        let middle = (end - start) / 2;
+        entries[start..end].select_nth_unstable_by(middle, |(a, _), (b, _)| key(a).partial_cmp(&key(b)).unwrap());
+        nodes.push(Node::Leaf { aabb, start, end });
+        let left = Self::build(entries, start, start + middle, nodes);
+        let right = Self::build(entries, start + middle, end, nodes);
+        nodes[index] = Node::Interior { aabb, left, right };
+        index
+    }
+}
+
+impl Bvh {
+    /// Visits the objects in every leaf whose box `ray` enters within `(t_min, t_max())`, stopping once `visit` returns true.
+    fn traverse<'a>(&'a self, ray: &Ray, t_min: f64, t_max: impl Fn() -> f64, mut visit: impl FnMut(usize, &'a Arc<dyn Collision>) -> bool) {
+        for (id, object) in &self.unbounded {
+            if visit(*id, object) {
+                return;
+            }
+        }
+        if self.nodes.is_empty() {
+            return;
+        }
+        let direction: Vec3<f64> = ray.direction.into();
+        let inverse_direction = Vec3::new(1.0 / direction.x(), 1.0 / direction.y(), 1.0 / direction.z());
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.aabb().intersect(ray.initial, inverse_direction, t_min, t_max()).is_none() {
+                continue;
+            }
+            match node {
+                Node::Leaf { start, end, .. } => {
+                    for (id, object) in &self.objects[*start..*end] {
+                        if visit(*id, object) {
+                            return;
+                        }
+                    }
+                }
+                Node::Interior { left, right, .. } => {
+                    stack.push(*right);
+                    stack.push(*left);
+                }
+            }
+        }
+    }
+
+    /// Tests the `active` lanes of `packet` against one object, labelling the hits it reports with its id.
+    fn visit_packet<'a>((id, object): &'a Entry, packet: &RayPacket<PACKET_WIDTH>, active: &[bool; PACKET_WIDTH], t_min: f64,
+                        t_max: &mut [f64; PACKET_WIDTH], hits: &mut [Option<SurfaceInteraction<'a>>; PACKET_WIDTH]) {
+        let before = *t_max;
+        object.collision_packet(packet, active, t_min, t_max, hits);
+        for ((before, after), hit) in before.iter().zip(t_max.iter()).zip(hits.iter_mut()) {
+            if after < before {
+                if let Some(hit) = hit {
+                    hit.primitive_id = *id;
+                }
+            }
+        }
+    }
+}
+
+impl Collision for Bvh {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        let nearest: std::cell::Cell<Option<f64>> = std::cell::Cell::new(None);
+        let mut hit = None;
+        self.traverse(ray, t_min, || nearest.get().unwrap_or(t_max), |id, object| {
+            // Each hit shrinks the interval, so later objects and boxes are culled against it.
+            if let Some(current) = object.collision(ray, t_min, nearest.get().unwrap_or(t_max)) {
+                nearest.set(Some(current.distance));
+                hit = Some(SurfaceInteraction { primitive_id: id, ..current });
+            }
+            false
+        });
+        hit
+    }
+
+    fn collision_packet<'a>(&'a self, packet: &RayPacket<PACKET_WIDTH>, active: &[bool; PACKET_WIDTH], t_min: f64,
+                            t_max: &mut [f64; PACKET_WIDTH], hits: &mut [Option<SurfaceInteraction<'a>>; PACKET_WIDTH]) {
+        for entry in &self.unbounded {
+            Self::visit_packet(entry, packet, active, t_min, t_max, hits);
+        }
+        let mut stack = if self.nodes.is_empty() { vec![] } else { vec![0] };
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            // A box is visited once for the whole packet as long as any lane enters it.
+            let entered = packet.intersect_aabb(node.aabb(), t_min, t_max);
+            let lanes = std::array::from_fn(|i| active[i] && entered[i]);
+            if !lanes.contains(&true) {
+                continue;
+            }
+            match node {
+                Node::Leaf { start, end, .. } => {
+                    for entry in &self.objects[*start..*end] {
+                        Self::visit_packet(entry, packet, &lanes, t_min, t_max, hits);
+                    }
+                }
+                Node::Interior { left, right, .. } => {
+                    stack.push(*right);
+                    stack.push(*left);
+                }
+            }
+        }
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        let mut occluded = false;
+        self.traverse(ray, 0.0, || t_max, |_, object| {
+            occluded = object.occluded(ray, t_max);
+            occluded
+        });
+        occluded
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        // In the order the objects were given, not the order of the tree.
+        let mut entries: Vec<&Entry> = self.objects.iter().chain(&self.unbounded).collect();
+        entries.sort_unstable_by_key(|(id, _)| *id);
+        entries.into_iter().flat_map(|(_, object)| object.materials()).collect()
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let bounded = self.nodes.first().map_or(Aabb::empty(), |node| *node.aabb());
+        self.unbounded.iter().fold(bounded, |aabb, (_, object)| aabb.union(&object.bounding_box()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::packet::{RayPacket, PACKET_WIDTH};
+    use crate::ray_tracing::Ray;
+    use crate::ray_tracing::scene::bvh::Bvh;
+    use crate::ray_tracing::scene::Collision;
+    use crate::ray_tracing::scene::material::{Color, Material};
+    use crate::ray_tracing::scene::object::plane::Plane;
+    use crate::ray_tracing::scene::object::Sphere;
+
+    #[test]
+    fn bvh_matches_brute_force_test() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let material = Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() };
+        let spheres: Vec<Arc<dyn Collision>> = (0..200).map(|_| {
+            let center = Vec3::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0));
+            Arc::new(Sphere::new(center, rng.gen_range(0.1..1.0), material.clone())) as Arc<dyn Collision>
+        }).collect();
+        let bvh = Bvh::new(spheres.clone());
+        for _ in 0..500 {
+            let initial = Vec3::new(rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let ray = Ray { initial, direction: direction.normalize(), differential: None, time: 0.0 };
+            let expected = spheres.iter().filter_map(|sphere| sphere.collision(&ray, 0.0, f64::INFINITY)).map(|hit| hit.distance).fold(f64::INFINITY, f64::min);
+            let hit = bvh.collision(&ray, 0.0, f64::INFINITY);
+            let actual = hit.as_ref().map_or(f64::INFINITY, |hit| hit.distance);
+            assert_eq!(expected, actual);
+            // The reported id is the object's index in the list the hierarchy was built from.
+            if let Some(hit) = hit {
+                assert_eq!(spheres[hit.primitive_id].collision(&ray, 0.0, f64::INFINITY).unwrap().distance, actual);
+            }
+        }
+        let aabb = bvh.bounding_box();
+        assert!(*aabb.min().x() >= -11.0 && *aabb.max().x() <= 11.0);
+        // Materials keep the order of the objects rather than that of the tree.
+        let materials = bvh.materials();
+        assert_eq!(materials.len(), spheres.len());
+        assert!(materials.iter().zip(&spheres).all(|(&material, sphere)| std::ptr::eq(material, sphere.materials()[0])));
+    }
+
+    #[test]
+    fn bvh_interval_queries_match_brute_force_test() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let material = Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() };
+        let spheres: Vec<Arc<dyn Collision>> = (0..200).map(|_| {
+            let center = Vec3::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0));
+            Arc::new(Sphere::new(center, rng.gen_range(0.1..1.0), material.clone())) as Arc<dyn Collision>
+        }).collect();
+        let bvh = Bvh::new(spheres.clone());
+        for _ in 0..500 {
+            let initial = Vec3::new(rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let ray = Ray { initial, direction: direction.normalize(), differential: None, time: 0.0 };
+            let (t_min, t_max) = (rng.gen_range(0.0..10.0), rng.gen_range(10.0..30.0));
+            let expected = spheres.iter().filter_map(|sphere| sphere.collision(&ray, t_min, t_max)).map(|hit| hit.distance).fold(f64::INFINITY, f64::min);
+            let actual = bvh.collision(&ray, t_min, t_max).map_or(f64::INFINITY, |hit| hit.distance);
+            assert_eq!(expected, actual);
+            assert!(actual > t_min && (actual < t_max || actual.is_infinite()));
+            let occluded = spheres.iter().any(|sphere| sphere.occluded(&ray, t_max));
+            assert_eq!(occluded, bvh.occluded(&ray, t_max));
+        }
+    }
+
+    #[test]
+    fn bvh_packet_matches_scalar_test() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let material = Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() };
+        let mut objects: Vec<Arc<dyn Collision>> = (0..200).map(|_| {
+            let center = Vec3::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0));
+            Arc::new(Sphere::new(center, rng.gen_range(0.1..1.0), material.clone())) as Arc<dyn Collision>
+        }).collect();
+        objects.push(Arc::new(Plane::new(Vec3::new(0.0, -12.0, 0.0), Vec3::new(0.0, 1.0, 0.0).normalize(), material.clone())));
+        let bvh = Bvh::new(objects);
+        for _ in 0..200 {
+            // Rays of a packet start together and fan out slightly, like neighbouring camera rays.
+            let initial = Vec3::new(rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let packet = RayPacket::new([(); PACKET_WIDTH].map(|_| {
+                let spread = Vec3::new(rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1));
+                Ray::new(initial, (direction + spread).normalize(), 0.0)
+            }));
+            let t_max = [(); PACKET_WIDTH].map(|_| if rng.gen_bool(0.5) { f64::INFINITY } else { rng.gen_range(1.0..20.0) });
+            let active = [(); PACKET_WIDTH].map(|_| rng.gen_bool(0.9));
+            let (mut nearest, mut hits) = (t_max, std::array::from_fn(|_| None));
+            bvh.collision_packet(&packet, &active, 0.0, &mut nearest, &mut hits);
+            for (i, ray) in packet.rays().iter().enumerate() {
+                let expected = bvh.collision(ray, 0.0, t_max[i]).filter(|_| active[i]);
+                // Spheres are solved in single precision, so distances only match closely.
+                assert_eq!(hits[i].as_ref().map(|hit| hit.primitive_id), expected.as_ref().map(|hit| hit.primitive_id));
+                if let (Some(hit), Some(expected)) = (&hits[i], &expected) {
+                    assert!((hit.distance - expected.distance).abs() < 1e-4, "{} {}", hit.distance, expected.distance);
+                }
+                assert_eq!(nearest[i], hits[i].as_ref().map_or(t_max[i], |hit| hit.distance));
+            }
+        }
+    }
+}
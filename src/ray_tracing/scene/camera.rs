@@ -1,17 +1,29 @@
-use crate::geometry::{NormalizedVec3, Vec3};
-use crate::ray_tracing::Ray;
+use crate::geometry::{AnimatedTransform, Matrix3, NormalizedVec3, Vec3};
+use crate::ray_tracing::{Ray, RayDifferential};
+
+/// A point seen by the camera: its raster position, the cosine between its direction and the optical axis, and
+/// its distance from the pinhole.
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    pub raster: (f64, f64),
+    pub cosine: f64,
+    pub distance: f64,
+}
 
 pub struct Camera {
     position: Vec3<f64>,
     direction_forward: Vec3<f64>/*z*/,
     direction_bottom: Vec3<f64>/*y*/,
     direction_right: Vec3<f64>/*x*/,
-    inverse_direction_x: Vec3<f64>,
-    inverse_direction_y: Vec3<f64>,
-    inverse_direction_z: Vec3<f64>,
+    /// Maps world directions into the camera's right, bottom and forward axes.
+    inverse: Matrix3,
     width: usize,
     height: usize,
     unit_per_pixel: f64,
+    shutter_open: f64,
+    shutter_close: f64,
+    /// Moves the whole rig away from the pose given to [`Camera::new`] over time.
+    motion: Option<AnimatedTransform>,
 }
 
 impl Camera {
@@ -25,65 +37,166 @@ impl Camera {
         let direction_forward = direction_forward.into();
         let direction_bottom = direction_bottom.into();
         let direction_right = direction_right.into();
-        let (inverse_direction_x,
-            inverse_direction_y,
-            inverse_direction_z, ) = {
-            let r: Vec3<_> = direction_right;
-            let b: Vec3<_> = direction_bottom;
-            let f: Vec3<_> = direction_forward;
-            let det = (
-                r.x() * b.y() * f.z()
-                    + r.y() * b.z() * f.x()
-                    + r.z() * b.x() * f.y()
-                    - r.x() * b.z() * f.y()
-                    - r.y() * b.x() * f.z()
-                    - r.z() * b.y() * f.x()
-            ).abs() / 6.0;
-            (
-                Vec3::new((b.y() * f.z() - b.z() * f.y()) / det,
-                          -(b.x() * f.z() - b.z() * f.x()) / det,
-                          (b.x() * f.y() - b.y() * f.x()) / det),
-                Vec3::new(-(r.y() * f.z() - r.z() * f.y()) / det,
-                          (r.x() * f.z() - r.z() * f.x()) / det,
-                          -(r.x() * f.y() - r.y() * f.x()) / det),
-                Vec3::new((r.y() * b.z() - r.z() * b.y()) / det,
-                          -(r.x() * b.z() - r.z() * b.x()) / det,
-                          (r.x() * b.y() - r.y() * b.x()) / det)//あとで行列計算は別に分ける
-            )
-        };
+        let inverse = Matrix3::from_columns(direction_right, direction_bottom, direction_forward).inverse().expect("camera axes must be linearly independent");
         Self {
             position,
             direction_forward,
             direction_bottom,
             direction_right,
-            inverse_direction_x,
-            inverse_direction_y,
-            inverse_direction_z,
+            inverse,
             width,
             height,
             unit_per_pixel: fov.sin() / (width / 2) as f64,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            motion: None,
         }
     }
 
-    pub fn create_ray(&self, x: f64, y: f64) -> Ray {
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        assert!(open <= close, "shutter must open before it closes");
+        Self { shutter_open: open, shutter_close: close, ..self }
+    }
+
+    pub fn with_motion(self, motion: AnimatedTransform) -> Self {
+        Self { motion: Some(motion), ..self }
+    }
+
+    /// Maps `u` in `[0, 1)` uniformly onto the shutter interval.
+    pub fn sample_time(&self, u: f64) -> f64 {
+        self.shutter_open + (self.shutter_close - self.shutter_open) * u
+    }
+
+    /// Creates the ray through raster position `(x, y)` at `time`, with differentials towards `(x + 1, y)` and `(x, y + 1)`.
+    pub fn create_ray(&self, x: f64, y: f64, time: f64) -> Ray {
+        let (initial, direction, x_direction, y_direction) = (self.position, self.direction(x, y), self.direction(x + 1.0, y), self.direction(x, y + 1.0));
+        let (initial, direction, x_direction, y_direction) = match &self.motion {
+            Some(motion) => {
+                let transform = motion.at(time);
+                (
+                    transform.apply_point(initial),
+                    transform.apply_vector(direction.vec()).normalize(),
+                    transform.apply_vector(x_direction.vec()).normalize(),
+                    transform.apply_vector(y_direction.vec()).normalize(),
+                )
+            }
+            None => (initial, direction, x_direction, y_direction),
+        };
+        Ray {
+            initial,
+            direction,
+            differential: Some(RayDifferential { x_initial: initial, x_direction, y_initial: initial, y_direction }),
+            time,
+        }
+    }
+
+    fn direction(&self, x: f64, y: f64) -> NormalizedVec3<f64> {
         let x = x - (self.width / 2) as f64;
         let y = y - (self.height / 2) as f64;
-        Ray {
-            initial: self.position,
-            direction: (self.direction_forward + self.direction_right * x * self.unit_per_pixel + self.direction_bottom * y * self.unit_per_pixel).normalize(),
+        (self.direction_forward + self.direction_right * x * self.unit_per_pixel + self.direction_bottom * y * self.unit_per_pixel).normalize()
+    }
+
+    /// Position of the pinhole at `time`.
+    pub fn position(&self, time: f64) -> Vec3<f64> {
+        match &self.motion {
+            Some(motion) => motion.at(time).apply_point(self.position),
+            None => self.position,
         }
     }
 
+    /// Area of the image on the plane at unit distance in front of the pinhole.
+    pub fn film_area(&self) -> f64 {
+        (self.width * self.height) as f64 * self.unit_per_pixel * self.unit_per_pixel
+    }
+
+    /// Where `position` appears on the image at `time`, or `None` if it is behind the camera or outside the image.
+    pub fn project(&self, position: Vec3<f64>, time: f64) -> Option<Projection> {
+        let position = match &self.motion {
+            Some(motion) => motion.at(time).inverse().apply_point(position),
+            None => position,
+        };
+        let offset = self.transform_position(position);
+        let distance = offset.squared_len().sqrt();
+        if distance == 0.0 {
+            return None;
+        }
+        let local = self.transform_direction(offset.normalize()).vec();
+        let cosine = *local.z();
+        if cosine <= 0.0 {
+            return None;
+        }
+        let x = local.x() / cosine / self.unit_per_pixel + (self.width / 2) as f64;
+        let y = local.y() / cosine / self.unit_per_pixel + (self.height / 2) as f64;
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
+        }
+        Some(Projection { raster: (x, y), cosine, distance })
+    }
+
     pub fn transform_direction(&self, direction: NormalizedVec3<f64>) -> NormalizedVec3<f64> {
-        let direction: Vec3<_> = direction.into();
-        Vec3::new(
-            self.inverse_direction_x.inner_product(direction),
-            self.inverse_direction_y.inner_product(direction),
-            self.inverse_direction_z.inner_product(direction),
-        ).normalize()
+        (self.inverse * direction.vec()).normalize()
     }
 
     pub fn transform_position(&self, position: Vec3<f64>) -> Vec3<f64> {
         position - self.position
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::geometry::{AnimatedTransform, Transform, Vec3};
+    use crate::ray_tracing::scene::camera::Camera;
+
+    fn camera() -> Camera {
+        Camera::new(Vec3::new(0.0, 0.0, 4.0),
+                    Vec3::new(0.0, 0.0, -1.0).normalize(),
+                    Vec3::new(0.0, -1.0, 0.0).normalize(),
+                    Vec3::new(1.0, 0.0, 0.0).normalize(),
+                    16, 16, PI / 4.0)
+    }
+
+    #[test]
+    fn camera_shutter_test() {
+        let camera = camera().with_shutter(0.25, 0.75);
+        assert_eq!(camera.sample_time(0.0), 0.25);
+        assert_eq!(camera.sample_time(0.5), 0.5);
+        let ray = camera.create_ray(8.0, 8.0, 0.5);
+        assert_eq!(ray.time, 0.5);
+        assert!((ray.direction.vec() - Vec3::new(0.0, 0.0, -1.0)).squared_len() < 1e-18);
+    }
+
+    #[test]
+    fn camera_project_test() {
+        let camera = camera().with_shutter(0.0, 1.0).with_motion(AnimatedTransform::new(vec![
+            (0.0, Transform::identity()),
+            (1.0, Transform::translate(Vec3::new(2.0, 0.0, 0.0))),
+        ]));
+        // Points along a camera ray project back onto the raster position it was created for.
+        for (x, y, time) in [(8.0, 8.0, 0.0), (3.25, 12.5, 0.5), (15.9, 0.1, 1.0)] {
+            let ray = camera.create_ray(x, y, time);
+            let projection = camera.project(ray.initial + ray.direction.vec() * 3.0, time).unwrap();
+            assert!((projection.raster.0 - x).abs() < 1e-9 && (projection.raster.1 - y).abs() < 1e-9, "{:?}", projection);
+            assert!((projection.distance - 3.0).abs() < 1e-9);
+            assert!((projection.cosine - ray.direction.vec().z().abs()).abs() < 1e-9);
+            assert!((camera.position(time) - ray.initial).squared_len() < 1e-18);
+        }
+        assert!(camera.project(Vec3::new(0.0, 0.0, 5.0), 0.0).is_none());
+        assert!(camera.project(Vec3::new(10.0, 0.0, 0.0), 0.0).is_none());
+    }
+
+    #[test]
+    fn camera_motion_test() {
+        let motion = AnimatedTransform::new(vec![
+            (0.0, Transform::identity()),
+            (1.0, Transform::translate(Vec3::new(2.0, 0.0, 0.0))),
+        ]);
+        let camera = camera().with_shutter(0.0, 1.0).with_motion(motion);
+        let start = camera.create_ray(8.0, 8.0, 0.0);
+        let middle = camera.create_ray(8.0, 8.0, 0.5);
+        assert!((start.initial - Vec3::new(0.0, 0.0, 4.0)).squared_len() < 1e-18);
+        assert!((middle.initial - Vec3::new(1.0, 0.0, 4.0)).squared_len() < 1e-18);
+        assert!((middle.differential.unwrap().x_initial - middle.initial).squared_len() < 1e-18);
+    }
+}
@@ -0,0 +1,245 @@
+use std::sync::Arc;
+
+use crate::geometry::{Aabb, Vec3};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::material::Material;
+
+/// Span of a ray's line inside a solid, bounded by the surface hits where it enters and leaves.
+#[derive(Debug, Clone)]
+pub struct Interval<'a> {
+    pub enter: SurfaceInteraction<'a>,
+    pub exit: SurfaceInteraction<'a>,
+}
+
+impl<'a> Interval<'a> {
+    /// Pairs up the crossings of a closed surface, which alternate between entering and leaving along a line.
+    /// An unpaired trailing crossing, as left by a grazing ray, is dropped.
+    pub fn pair(mut crossings: Vec<SurfaceInteraction<'a>>) -> Vec<Interval<'a>> {
+        crossings.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        let mut crossings = crossings.into_iter();
+        let mut intervals = Vec::new();
+        while let (Some(enter), Some(exit)) = (crossings.next(), crossings.next()) {
+            intervals.push(Interval { enter, exit });
+        }
+        intervals
+    }
+}
+
+/// A closed object that can report where a ray is inside it, as CSG needs more than the nearest hit.
+pub trait Solid: Collision {
+    /// Sorted, disjoint intervals along the whole line through `ray`, including behind its origin.
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>>;
+}
+
+impl<T: Solid + ?Sized> Solid for Arc<T> {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        (**self).intervals(ray)
+    }
+}
+
+impl<T: Solid + ?Sized> Solid for Box<T> {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        (**self).intervals(ray)
+    }
+}
+
+/// Nearest interval boundary within `(t_min, t_max)`.
+pub fn first_crossing(intervals: Vec<Interval<'_>>, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+    intervals.into_iter()
+        .flat_map(|interval| [interval.enter, interval.exit])
+        .find(|hit| hit.distance > t_min)
+        .filter(|hit| hit.distance < t_max)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    /// Left minus right.
+    Difference,
+}
+
+impl Operation {
+    fn contains(self, left: bool, right: bool) -> bool {
+        match self {
+            Operation::Union => left || right,
+            Operation::Intersection => left && right,
+            Operation::Difference => left && !right,
+        }
+    }
+}
+
+/// Boolean combination of two solids. Nodes nest, since a `Csg` is itself a [`Solid`].
+pub struct Csg {
+    operation: Operation,
+    left: Arc<dyn Solid>,
+    right: Arc<dyn Solid>,
+}
+
+impl Csg {
+    pub fn new(operation: Operation, left: Arc<dyn Solid>, right: Arc<dyn Solid>) -> Self {
+        Self { operation, left, right }
+    }
+
+    pub fn union(left: Arc<dyn Solid>, right: Arc<dyn Solid>) -> Self {
+        Self::new(Operation::Union, left, right)
+    }
+
+    pub fn intersection(left: Arc<dyn Solid>, right: Arc<dyn Solid>) -> Self {
+        Self::new(Operation::Intersection, left, right)
+    }
+
+    pub fn difference(left: Arc<dyn Solid>, right: Arc<dyn Solid>) -> Self {
+        Self::new(Operation::Difference, left, right)
+    }
+}
+
+/// The same surface point seen from the other side, as the right operand's surface bounds a difference from within.
+fn flip(hit: SurfaceInteraction<'_>) -> SurfaceInteraction<'_> {
+    SurfaceInteraction {
+        normal: (-hit.normal.vec()).normalize(),
+        front_face: !hit.front_face,
+        dndu: -hit.dndu,
+        dndv: -hit.dndv,
+        ..hit
+    }
+}
+
+impl Solid for Csg {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        fn events(intervals: Vec<Interval<'_>>, left: bool) -> impl Iterator<Item=(SurfaceInteraction<'_>, bool, bool)> {
+            intervals.into_iter().flat_map(move |interval| [(interval.enter, left, true), (interval.exit, left, false)])
+        }
+        let mut events: Vec<_> = events(self.left.intervals(ray), true).chain(events(self.right.intervals(ray), false)).collect();
+        events.sort_by(|a, b| a.0.distance.total_cmp(&b.0.distance));
+
+        let (mut in_left, mut in_right, mut inside) = (false, false, false);
+        let mut enter = None;
+        let mut intervals = Vec::new();
+        for (hit, left, entering) in events {
+            if left {
+                in_left = entering;
+            } else {
+                in_right = entering;
+            }
+            if self.operation.contains(in_left, in_right) == inside {
+                continue;
+            }
+            inside = !inside;
+            let hit = if !left && self.operation == Operation::Difference { flip(hit) } else { hit };
+            match enter.take() {
+                Some(enter) => intervals.push(Interval { enter, exit: hit }),
+                None => enter = Some(hit),
+            }
+        }
+        intervals
+    }
+}
+
+impl Collision for Csg {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        first_crossing(self.intervals(ray), t_min, t_max)
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        let mut materials = self.left.materials();
+        materials.extend(self.right.materials());
+        materials
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let (left, right) = (self.left.bounding_box(), self.right.bounding_box());
+        match self.operation {
+            Operation::Union => left.union(&right),
+            Operation::Intersection => {
+                let (a, b) = (left.min(), right.min());
+                let min = Vec3::new(a.x().max(*b.x()), a.y().max(*b.y()), a.z().max(*b.z()));
+                let (a, b) = (left.max(), right.max());
+                let max = Vec3::new(a.x().min(*b.x()), a.y().min(*b.y()), a.z().min(*b.z()));
+                Aabb::new(min, max)
+            }
+            Operation::Difference => left,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+    use crate::ray_tracing::scene::csg::{Csg, Solid};
+    use crate::ray_tracing::scene::material::Material;
+    use crate::ray_tracing::scene::object::cuboid::Cuboid;
+    use crate::ray_tracing::scene::object::Sphere;
+    use crate::ray_tracing::scene::object::test_util::{material, ray};
+
+    fn spheres() -> (Arc<dyn Solid>, Arc<dyn Solid>) {
+        (Arc::new(Sphere::new(Vec3::new(-0.5, 0.0, 0.0), 1.0, material())),
+         Arc::new(Sphere::new(Vec3::new(0.5, 0.0, 0.0), 1.0, material())))
+    }
+
+    fn assert_hit(hit: Option<SurfaceInteraction>, distance: f64, normal: Vec3<f64>) {
+        let hit = hit.unwrap();
+        assert!((hit.distance - distance).abs() < 1e-9, "distance {} != {}", hit.distance, distance);
+        assert!((hit.normal.vec() - normal).squared_len() < 1e-18);
+    }
+
+    #[test]
+    fn csg_union_test() {
+        let (left, right) = spheres();
+        let union = Csg::union(left, right);
+        assert_hit(union.collision(&ray(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY), 3.5, Vec3::new(1.0, 0.0, 0.0));
+        // The overlapping boundaries inside the union are not reported.
+        assert_hit(union.collision(&ray(Vec3::new(0.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY), 1.5, Vec3::new(-1.0, 0.0, 0.0));
+        assert_eq!(union.intervals(&ray(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0))).len(), 1);
+        assert_eq!(union.bounding_box().min(), Vec3::new(-1.5, -1.0, -1.0));
+
+        // Shadow rays pass the interface they enter through and stop at the opaque side they leave through.
+        let bubble = Csg::union(Arc::new(Sphere::new(Vec3::new(-0.5, 0.0, 0.0), 1.0, Material::Interface)), spheres().1);
+        let shadow = ray(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(!bubble.occluded(&shadow, 6.0));
+        assert!(bubble.occluded(&shadow, 7.0));
+    }
+
+    #[test]
+    fn csg_intersection_test() {
+        let (left, right) = spheres();
+        let intersection = Csg::intersection(left, right);
+        assert_hit(intersection.collision(&ray(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY), 4.5, Vec3::new(1.0, 0.0, 0.0));
+        assert!(intersection.collision(&ray(Vec3::new(1.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert_eq!(intersection.bounding_box().min(), Vec3::new(-0.5, -1.0, -1.0));
+        assert_eq!(intersection.bounding_box().max(), Vec3::new(0.5, 1.0, 1.0));
+    }
+
+    #[test]
+    fn csg_difference_test() {
+        let (left, right) = spheres();
+        let difference = Csg::difference(left, right);
+        // The cut face is the right sphere's surface, facing out of the remaining solid.
+        assert_hit(difference.collision(&ray(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY), 5.5, Vec3::new(1.0, 0.0, 0.0));
+        assert!(difference.collision(&ray(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY).unwrap().front_face);
+        assert_hit(difference.collision(&ray(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.0, f64::INFINITY), 3.5, Vec3::new(-1.0, 0.0, 0.0));
+
+        let hollow = Csg::difference(Arc::new(Cuboid::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0), material())),
+                                     Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 0.5, material())));
+        let through = ray(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let intervals = hollow.intervals(&through);
+        assert_eq!(intervals.len(), 2);
+        assert!((intervals[0].exit.distance - 4.5).abs() < 1e-9);
+        assert!((intervals[1].enter.distance - 5.5).abs() < 1e-9);
+        // From the cavity, the nearest surface is the cavity wall.
+        assert_hit(hollow.collision(&ray(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)), 0.0, f64::INFINITY), 0.5, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn csg_nested_test() {
+        let (left, right) = spheres();
+        let lens: Arc<dyn Solid> = Arc::new(Csg::intersection(left, right));
+        let cut = Csg::difference(lens, Arc::new(Cuboid::new(Vec3::new(-2.0, 0.0, -2.0), Vec3::new(2.0, 2.0, 2.0), material())));
+        assert!(cut.collision(&ray(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.0, f64::INFINITY).is_some_and(|hit| (hit.distance - 5.0).abs() < 1e-9));
+        assert!(cut.collision(&ray(Vec3::new(0.0, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::INFINITY).is_none());
+    }
+}
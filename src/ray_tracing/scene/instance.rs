@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use crate::geometry::{Aabb, AnimatedTransform, Transform};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::material::Material;
+
+/// Places a shared object in the world through an affine transform.
+/// Instances of the same object share its geometry, so only the transform is stored per copy.
+pub struct Instance {
+    object: Arc<dyn Collision>,
+    transform: Transform,
+    motion: Option<AnimatedTransform>,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Collision>, transform: Transform) -> Self {
+        Self { object, transform, motion: None }
+    }
+
+    /// An instance whose transform is interpolated at each ray's time.
+    pub fn animated(object: Arc<dyn Collision>, motion: AnimatedTransform) -> Self {
+        Self { object, transform: motion.at(0.0), motion: Some(motion) }
+    }
+
+    /// The transform at the ray's time, the ray in object space and the factor from world to local distances.
+    fn local_ray(&self, ray: &Ray) -> (Transform, Ray, f64) {
+        let transform = match &self.motion {
+            Some(motion) => motion.at(ray.time),
+            None => self.transform,
+        };
+        let inverse = transform.inverse();
+        let direction = inverse.apply_vector(ray.direction.vec());
+        // Distances along the normalized local ray are scaled by |M^-1 d| relative to world distances.
+        let scale = direction.squared_len().sqrt();
+        let local_ray = Ray {
+            initial: inverse.apply_point(ray.initial),
+            direction: direction.normalize(),
+            differential: None,
+            time: ray.time,
+        };
+        (transform, local_ray, scale)
+    }
+}
+
+impl Collision for Instance {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        let (transform, local_ray, scale) = self.local_ray(ray);
+        let hit = self.object.collision(&local_ray, t_min * scale, t_max * scale)?;
+        Some(SurfaceInteraction {
+            distance: hit.distance / scale,
+            normal: transform.apply_normal(hit.normal.vec()).normalize(),
+            position: transform.apply_point(hit.position),
+            error: transform.apply_error(hit.position, hit.error),
+            dpdu: transform.apply_vector(hit.dpdu),
+            dpdv: transform.apply_vector(hit.dpdv),
+            dndu: transform.apply_normal(hit.dndu),
+            dndv: transform.apply_normal(hit.dndv),
+            ..hit
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        let (_, local_ray, scale) = self.local_ray(ray);
+        self.object.occluded(&local_ray, t_max * scale)
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        self.object.materials()
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match &self.motion {
+            Some(motion) => motion.motion_bounds(&self.object.bounding_box()),
+            None => self.transform.apply_aabb(&self.object.bounding_box()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    use crate::geometry::{AnimatedTransform, Transform, Vec3};
+    use crate::ray_tracing::Ray;
+    use crate::ray_tracing::scene::bvh::Bvh;
+    use crate::ray_tracing::scene::Collision;
+    use crate::ray_tracing::scene::instance::Instance;
+    use crate::ray_tracing::scene::object::plane::Plane;
+    use crate::ray_tracing::scene::object::Sphere;
+    use crate::ray_tracing::scene::object::test_util::material;
+
+    #[test]
+    fn instance_matches_transformed_sphere_test() {
+        let unit: Arc<dyn Collision> = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material()));
+        let transform = Transform::translate(Vec3::new(3.0, 1.0, 0.0)) * Transform::rotate(Vec3::new(0.0, 1.0, 0.0).normalize(), PI / 3.0) * Transform::scale(Vec3::new(2.0, 2.0, 2.0));
+        let instance = Instance::new(unit, transform);
+        let sphere = Sphere::new(Vec3::new(3.0, 1.0, 0.0), 2.0, material());
+        let ray = Ray { initial: Vec3::new(-5.0, 1.5, 0.3), direction: Vec3::new(1.0, 0.0, 0.0).normalize(), differential: None, time: 0.0 };
+        let expected = sphere.collision(&ray, 0.0, f64::INFINITY).unwrap();
+        let actual = instance.collision(&ray, 0.0, f64::INFINITY).unwrap();
+        assert!((expected.distance - actual.distance).abs() < 1e-9);
+        assert!((expected.position - actual.position).squared_len() < 1e-18);
+        assert!((expected.normal.vec() - actual.normal.vec()).squared_len() < 1e-18);
+        assert!(*instance.bounding_box().min().x() <= 1.0 && *instance.bounding_box().max().x() >= 5.0);
+    }
+
+    #[test]
+    fn instance_ellipsoid_normal_test() {
+        let unit: Arc<dyn Collision> = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material()));
+        let instance = Instance::new(unit, Transform::scale(Vec3::new(4.0, 1.0, 1.0)));
+        let ray = Ray { initial: Vec3::new(2.0, 5.0, 0.0), direction: Vec3::new(0.0, -1.0, 0.0).normalize(), differential: None, time: 0.0 };
+        let hit = instance.collision(&ray, 0.0, f64::INFINITY).unwrap();
+        // x^2 / 16 + y^2 = 1 at x = 2 gives y = sqrt(3) / 2, normal ∝ (x / 16, y, 0)
+        let y = 3f64.sqrt() / 2.0;
+        assert!((hit.distance - (5.0 - y)).abs() < 1e-9);
+        let expected = Vec3::new(2.0 / 16.0, y, 0.0).normalize().vec();
+        assert!((hit.normal.vec() - expected).squared_len() < 1e-18);
+    }
+
+    #[test]
+    fn instances_share_geometry_test() {
+        let unit: Arc<dyn Collision> = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 0.5, material()));
+        let instances: Vec<Arc<dyn Collision>> = (0..1000)
+            .map(|i| Arc::new(Instance::new(unit.clone(), Transform::translate(Vec3::new((i % 10) as f64, (i / 10 % 10) as f64, (i / 100) as f64)))) as Arc<dyn Collision>)
+            .collect();
+        assert_eq!(Arc::strong_count(&unit), 1001);
+        let bvh: Arc<dyn Collision> = Arc::new(Bvh::new(instances));
+        // instancing a whole hierarchy
+        let moved = Instance::new(bvh, Transform::translate(Vec3::new(100.0, 0.0, 0.0)));
+        let ray = Ray { initial: Vec3::new(104.0, 5.0, 3.0), direction: Vec3::new(0.0, -1.0, 0.0).normalize(), differential: None, time: 0.0 };
+        let hit = moved.collision(&ray, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.distance - 0.5).abs() < 1e-9);
+        assert!(moved.occluded(&ray, 0.6));
+        assert!(!moved.occluded(&ray, 0.4));
+    }
+
+    #[test]
+    fn animated_instance_test() {
+        let unit: Arc<dyn Collision> = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material()));
+        let motion = AnimatedTransform::new(vec![
+            (0.0, Transform::identity()),
+            (1.0, Transform::translate(Vec3::new(0.0, 4.0, 0.0))),
+        ]);
+        let instance = Instance::animated(unit, motion);
+        let ray = |time| Ray { initial: Vec3::new(0.0, 2.0, 5.0), direction: Vec3::new(0.0, 0.0, -1.0).normalize(), differential: None, time };
+        assert!(instance.collision(&ray(0.0), 0.0, f64::INFINITY).is_none());
+        let hit = instance.collision(&ray(0.5), 0.0, f64::INFINITY).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-9);
+        assert!((hit.position - Vec3::new(0.0, 2.0, 1.0)).squared_len() < 1e-18);
+        let aabb = instance.bounding_box();
+        assert!(*aabb.min().y() <= -1.0 && *aabb.max().y() >= 5.0);
+    }
+
+    #[test]
+    fn instanced_plane_in_bvh_test() {
+        // An unbounded object stays unbounded through an instance, so nested hierarchies never cull it.
+        let plane: Arc<dyn Collision> = Arc::new(Plane::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0).normalize(), material()));
+        let sphere: Arc<dyn Collision> = Arc::new(Sphere::new(Vec3::new(0.0, 3.0, 0.0), 0.5, material()));
+        let instance: Arc<dyn Collision> = Arc::new(Instance::new(plane, Transform::translate(Vec3::new(0.0, -1.0, 0.0)) * Transform::rotate(Vec3::new(0.0, 0.0, 1.0).normalize(), 0.1)));
+        assert!(!instance.bounding_box().is_finite());
+        let inner: Arc<dyn Collision> = Arc::new(Bvh::new(vec![instance, sphere]));
+        let outer = Bvh::new(vec![inner, Arc::new(Sphere::new(Vec3::new(10.0, 3.0, 0.0), 0.5, material()))]);
+        let ray = Ray { initial: Vec3::new(50.0, 5.0, 20.0), direction: Vec3::new(0.0, -1.0, 0.0).normalize(), differential: None, time: 0.0 };
+        let hit = outer.collision(&ray, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.position.y() - (-1.0 + 50f64 * 0.1f64.tan())).abs() < 1e-6, "{:?}", hit.position);
+        assert!(outer.occluded(&ray, f64::INFINITY));
+    }
+}
@@ -1,5 +1,10 @@
 use std::ops::{Add, Mul};
 
+use crate::geometry::{Frame, Vec3};
+use crate::ray_tracing::scene::medium::Medium;
+use crate::ray_tracing::scene::texture::{Texture, TextureCoordinate};
+use crate::ray_tracing::spectrum::{Spectrum, RGB_WAVELENGTHS};
+
 #[derive(Debug, Clone)]
 pub struct Color {
     pub r: f64,
@@ -43,5 +48,322 @@ impl Mul for Color {
 
 #[derive(Debug, Clone)]
 pub enum Material {
-    Solid { color: Color, illuminate: Color },
+    Solid { color: Texture, illuminate: Texture },
+    /// Metal described by its complex index of refraction `eta + i k`, such as measured data for gold or copper.
+    /// Reflects with the Fresnel reflectance of each wavelength; `roughness` perturbs the mirror direction, 0 being a
+    /// perfect mirror.
+    Conductor { eta: Spectrum, k: Spectrum, roughness: Texture },
+    /// Smooth glass-like surface that reflects or refracts by the Fresnel equations. Dispersive indices split light
+    /// into its colours, which restricts paths to a single wavelength.
+    Dielectric { ior: Ior },
+    /// Shades `material` with a normal perturbed by `bump`.
+    Bumped { material: Box<Material>, bump: Bump },
+    /// Invisible surface that only bounds a medium; rays pass straight through it.
+    Interface,
+    /// Shades like `material` and fills the inside of the object with `medium`. The object must be closed.
+    Filled { material: Box<Material>, medium: Medium },
+}
+
+impl Material {
+    /// Strips [`Material::Bumped`] and [`Material::Filled`] layers, returning the underlying material and its shading normal.
+    pub fn resolve(&self, geometry: &ShadingGeometry, coordinate: &TextureCoordinate) -> (&Material, Vec3<f64>) {
+        match self {
+            Material::Bumped { material, bump } => {
+                let normal = bump.shading_normal(geometry, coordinate);
+                material.resolve(&ShadingGeometry { normal, ..*geometry }, coordinate)
+            }
+            Material::Filled { material, .. } => material.resolve(geometry, coordinate),
+            material => (material, geometry.normal),
+        }
+    }
+
+    /// Reflectance at normal incidence as denoisers expect it: one for dielectrics and interfaces, whose appearance
+    /// comes from what lies behind them.
+    pub fn albedo(&self, coordinate: &TextureCoordinate) -> Color {
+        match self {
+            Material::Solid { color, .. } => color.evaluate(coordinate),
+            Material::Conductor { eta, k, .. } => {
+                let [r, g, b] = RGB_WAVELENGTHS.map(|lambda| fresnel_conductor(1.0, eta.evaluate(lambda), k.evaluate(lambda)));
+                Color { r, g, b }
+            }
+            Material::Dielectric { .. } | Material::Interface => Color { r: 1.0, g: 1.0, b: 1.0 },
+            Material::Bumped { material, .. } | Material::Filled { material, .. } => material.albedo(coordinate),
+        }
+    }
+
+    /// Whether surfaces with this material give off light anywhere.
+    pub fn emits(&self) -> bool {
+        match self {
+            Material::Solid { illuminate: Texture::Constant(color), .. } => color.r > 0.0 || color.g > 0.0 || color.b > 0.0,
+            Material::Solid { .. } => true,
+            Material::Bumped { material, .. } | Material::Filled { material, .. } => material.emits(),
+            _ => false,
+        }
+    }
+
+    /// Whether rays pass straight through surfaces with this material.
+    pub fn is_interface(&self) -> bool {
+        match self {
+            Material::Interface => true,
+            Material::Bumped { material, .. } | Material::Filled { material, .. } => material.is_interface(),
+            _ => false,
+        }
+    }
+
+    /// Medium inside objects with this material, if any layer fills them.
+    pub fn medium(&self) -> Option<&Medium> {
+        match self {
+            Material::Filled { medium, .. } => Some(medium),
+            Material::Bumped { material, .. } => material.medium(),
+            _ => None,
+        }
+    }
+}
+
+/// Index of refraction of a dielectric, possibly varying with wavelength.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ior {
+    Constant(f64),
+    /// Cauchy's equation `n = a + b / lambda^2`, with the wavelength in micrometres.
+    Cauchy { a: f64, b: f64 },
+    /// Sellmeier equation `n^2 = 1 + sum b lambda^2 / (lambda^2 - c)`, with the wavelength in micrometres.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+    /// Measured indices over wavelengths in nanometres.
+    Measured(Spectrum),
+}
+
+impl Ior {
+    /// Schott N-BK7 borosilicate crown glass.
+    pub fn bk7() -> Self {
+        Ior::Sellmeier { b: [1.039_612_12, 0.231_792_344, 1.010_469_45], c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653] }
+    }
+
+    /// Diamond, whose high dispersion gives gems their fire.
+    pub fn diamond() -> Self {
+        Ior::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.175 * 0.175, 0.106 * 0.106, 0.0] }
+    }
+
+    pub fn evaluate(&self, lambda: f64) -> f64 {
+        let micrometres = lambda * 1e-3;
+        let squared = micrometres * micrometres;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / squared,
+            Ior::Sellmeier { b, c } => (1.0 + b.iter().zip(c).map(|(b, c)| b * squared / (squared - c)).sum::<f64>()).sqrt(),
+            Ior::Measured(spectrum) => spectrum.evaluate(lambda),
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_) | Ior::Measured(Spectrum::Constant(_)))
+    }
+}
+
+/// Fresnel reflectance of unpolarized light arriving at `cosine` to the normal of a conductor with complex index
+/// of refraction `eta + i k` relative to the outside. Dielectrics have `k = 0`, with total internal reflection for `eta < 1`.
+pub fn fresnel_conductor(cosine: f64, eta: f64, k: f64) -> f64 {
+    type Complex = (f64, f64);
+    let mul = |a: Complex, b: Complex| (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0);
+    let div = |a: Complex, b: Complex| {
+        let norm = b.0 * b.0 + b.1 * b.1;
+        ((a.0 * b.0 + a.1 * b.1) / norm, (a.1 * b.0 - a.0 * b.1) / norm)
+    };
+    let sqrt = |z: Complex| {
+        let norm = z.0.hypot(z.1);
+        if norm == 0.0 {
+            return (0.0, 0.0);
+        }
+        let t1 = (0.5 * (norm + z.0.abs())).sqrt();
+        let t2 = 0.5 * z.1 / t1;
+        if z.0 >= 0.0 { (t1, t2) } else { (t2.abs(), t1.copysign(z.1)) }
+    };
+    let squared_norm = |z: Complex| z.0 * z.0 + z.1 * z.1;
+    let cos_i = cosine.clamp(0.0, 1.0);
+    let eta = (eta, k);
+    let sin2_t = div((1.0 - cos_i * cos_i, 0.0), mul(eta, eta));
+    let cos_t = sqrt((1.0 - sin2_t.0, -sin2_t.1));
+    let eta_cos_i = (eta.0 * cos_i, eta.1 * cos_i);
+    let eta_cos_t = mul(eta, cos_t);
+    let parallel = div((eta_cos_i.0 - cos_t.0, eta_cos_i.1 - cos_t.1), (eta_cos_i.0 + cos_t.0, eta_cos_i.1 + cos_t.1));
+    let perpendicular = div((cos_i - eta_cos_t.0, -eta_cos_t.1), (cos_i + eta_cos_t.0, eta_cos_t.1));
+    0.5 * (squared_norm(parallel) + squared_norm(perpendicular))
+}
+
+/// Differential geometry at a hit, with the normal facing the incoming ray.
+#[derive(Debug, Clone, Copy)]
+pub struct ShadingGeometry {
+    pub normal: Vec3<f64>,
+    pub dpdu: Vec3<f64>,
+    pub dpdv: Vec3<f64>,
+    pub dndu: Vec3<f64>,
+    pub dndv: Vec3<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Bump {
+    /// Tangent-space normal map; texel components are remapped from `[0, 1]` to `[-1, 1]`.
+    /// The tangent follows `dpdu` and the bitangent `dpdv`.
+    Normal(Texture),
+    /// Height field displacing the surface along the normal by `scale * height`.
+    Height { height: Texture, scale: f64 },
+}
+
+impl Bump {
+    /// Offset used for finite differences when the footprint is unknown.
+    const DEFAULT_DELTA: f64 = 0.0005;
+
+    pub fn shading_normal(&self, geometry: &ShadingGeometry, coordinate: &TextureCoordinate) -> Vec3<f64> {
+        let n = geometry.normal;
+        match self {
+            Bump::Normal(texture) => {
+                let texel = texture.evaluate(coordinate);
+                let frame = Frame::from_normal_tangent(n.normalize(), geometry.dpdu);
+                let handedness = if frame.bitangent().inner_product(geometry.dpdv) < 0.0 { -1.0 } else { 1.0 };
+                let local = Vec3::new(texel.r * 2.0 - 1.0, (texel.g * 2.0 - 1.0) * handedness, texel.b * 2.0 - 1.0);
+                let normal = frame.to_world(local);
+                if normal.squared_len() > 0.0 { normal.normalize().vec() } else { n }
+            }
+            Bump::Height { height, scale } => {
+                let delta = |a: f64, b: f64| match 0.5 * (a.abs() + b.abs()) {
+                    d if d > 0.0 => d,
+                    _ => Self::DEFAULT_DELTA,
+                };
+                let du = delta(coordinate.duv_dx.0, coordinate.duv_dy.0);
+                let dv = delta(coordinate.duv_dx.1, coordinate.duv_dy.1);
+                let shifted_u = TextureCoordinate {
+                    uv: (coordinate.uv.0 + du, coordinate.uv.1),
+                    position: coordinate.position + geometry.dpdu * du,
+                    ..*coordinate
+                };
+                let shifted_v = TextureCoordinate {
+                    uv: (coordinate.uv.0, coordinate.uv.1 + dv),
+                    position: coordinate.position + geometry.dpdv * dv,
+                    ..*coordinate
+                };
+                let displace = height.evaluate_scalar(coordinate) * scale;
+                let u_displace = height.evaluate_scalar(&shifted_u) * scale;
+                let v_displace = height.evaluate_scalar(&shifted_v) * scale;
+                let dpdu = geometry.dpdu + n * ((u_displace - displace) / du) + geometry.dndu * displace;
+                let dpdv = geometry.dpdv + n * ((v_displace - displace) / dv) + geometry.dndv * displace;
+                let normal = dpdu.outer_product(dpdv);
+                if normal.squared_len().is_nan() || normal.squared_len() == 0.0 {
+                    return n;
+                }
+                let normal = normal.normalize().vec();
+                if normal.inner_product(n) < 0.0 { -normal } else { normal }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::material::{fresnel_conductor, Bump, Color, Ior, Material, ShadingGeometry};
+    use crate::ray_tracing::scene::medium::Medium;
+    use crate::ray_tracing::scene::texture::procedural::{ColorRamp, Pattern, TextureSpace};
+    use crate::ray_tracing::scene::texture::{Texture, TextureCoordinate};
+
+    fn flat() -> ShadingGeometry {
+        ShadingGeometry {
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            dpdu: Vec3::new(2.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 2.0, 0.0),
+            dndu: Vec3::new(0.0, 0.0, 0.0),
+            dndv: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn coordinate(uv: (f64, f64)) -> TextureCoordinate {
+        TextureCoordinate { uv, duv_dx: (0.0, 0.0), duv_dy: (0.0, 0.0), position: Vec3::new(uv.0 * 2.0, uv.1 * 2.0, 0.0), local_position: Vec3::new(0.0, 0.0, 0.0) }
+    }
+
+    #[test]
+    fn fresnel_conductor_test() {
+        // Dielectrics reflect ((n - 1) / (n + 1))^2 head on and everything at grazing angles.
+        assert!((fresnel_conductor(1.0, 1.5, 0.0) - 0.04).abs() < 1e-12);
+        assert!((fresnel_conductor(0.0, 1.5, 0.0) - 1.0).abs() < 1e-12);
+        // Gold at 600 nm: strongly absorbing metals reflect most light at every angle.
+        let (n, k) = (0.25, 3.0);
+        let normal = ((n - 1.0f64).powi(2) + k * k) / ((n + 1.0f64).powi(2) + k * k);
+        assert!((fresnel_conductor(1.0, n, k) - normal).abs() < 1e-12);
+        for i in 0..=10 {
+            let r = fresnel_conductor(i as f64 / 10.0, n, k);
+            assert!(r > 0.8 && r <= 1.0, "{}", r);
+        }
+    }
+
+    #[test]
+    fn ior_test() {
+        assert!((Ior::bk7().evaluate(587.6) - 1.5168).abs() < 1e-4);
+        assert!((Ior::diamond().evaluate(589.3) - 2.417).abs() < 2e-3);
+        // Normal dispersion: shorter wavelengths refract more.
+        for ior in [Ior::bk7(), Ior::diamond(), Ior::Cauchy { a: 1.5, b: 0.004 }] {
+            assert!(ior.evaluate(400.0) > ior.evaluate(550.0) && ior.evaluate(550.0) > ior.evaluate(700.0), "{:?}", ior);
+            assert!(ior.is_dispersive());
+        }
+        assert!((Ior::Cauchy { a: 1.5, b: 0.004 }.evaluate(500.0) - 1.516).abs() < 1e-12);
+        assert!(!Ior::Constant(1.33).is_dispersive());
+        // Total internal reflection beyond the critical angle.
+        assert!((fresnel_conductor(0.5, 1.0 / 1.5, 0.0) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn normal_map_test() {
+        let flat_map = Bump::Normal(Color { r: 0.5, g: 0.5, b: 1.0 }.into());
+        let normal = flat_map.shading_normal(&flat(), &coordinate((0.5, 0.5)));
+        assert!((normal - Vec3::new(0.0, 0.0, 1.0)).squared_len() < 1e-18);
+
+        let tilted = Bump::Normal(Color { r: 1.0, g: 0.5, b: 1.0 }.into());
+        let normal = tilted.shading_normal(&flat(), &coordinate((0.5, 0.5)));
+        let expected = Vec3::new(1.0, 0.0, 1.0) / 2f64.sqrt();
+        assert!((normal - expected).squared_len() < 1e-18);
+
+        // bitangent follows dpdv even for a left-handed parameterization
+        let geometry = ShadingGeometry { dpdv: Vec3::new(0.0, -2.0, 0.0), ..flat() };
+        let tilted = Bump::Normal(Color { r: 0.5, g: 1.0, b: 1.0 }.into());
+        let normal = tilted.shading_normal(&geometry, &coordinate((0.5, 0.5)));
+        assert!(*normal.y() < 0.0);
+    }
+
+    #[test]
+    fn height_map_test() {
+        // height rises linearly along u: h = u, displaced by scale 0.5 over dp/du = 2 gives slope 0.25
+        let ramp = Texture::Procedural {
+            pattern: Pattern::Linear { axis: Vec3::new(1.0, 0.0, 0.0) },
+            ramp: ColorRamp::two(Color::zero(), Color { r: 1.0, g: 1.0, b: 1.0 }),
+            space: TextureSpace::Uv,
+            scale: 1.0,
+        };
+        let bump = Bump::Height { height: ramp, scale: 0.5 };
+        let normal = bump.shading_normal(&flat(), &coordinate((0.5, 0.5)));
+        let expected = Vec3::new(-0.25, 0.0, 1.0).normalize().vec();
+        assert!((normal - expected).squared_len() < 1e-12, "{:?}", normal);
+
+        let constant = Bump::Height { height: Color { r: 0.3, g: 0.3, b: 0.3 }.into(), scale: 1.0 };
+        let normal = constant.shading_normal(&flat(), &coordinate((0.5, 0.5)));
+        assert!((normal - Vec3::new(0.0, 0.0, 1.0)).squared_len() < 1e-18);
+    }
+
+    #[test]
+    fn resolve_test() {
+        let material = Material::Bumped {
+            material: Box::new(Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() }),
+            bump: Bump::Normal(Color { r: 1.0, g: 0.5, b: 1.0 }.into()),
+        };
+        let (material, normal) = material.resolve(&flat(), &coordinate((0.5, 0.5)));
+        assert!(matches!(material, Material::Solid { .. }));
+        assert!(*normal.x() > 0.5);
+
+        let filled = Material::Bumped {
+            material: Box::new(Material::Filled { material: Box::new(Material::Interface), medium: Medium::homogeneous(Color::zero(), Color { r: 1.0, g: 1.0, b: 1.0 }, 0.0) }),
+            bump: Bump::Normal(Color { r: 0.5, g: 0.5, b: 1.0 }.into()),
+        };
+        assert!(filled.medium().is_some());
+        assert!(matches!(filled.resolve(&flat(), &coordinate((0.5, 0.5))).0, Material::Interface));
+        assert!(Material::Interface.medium().is_none());
+        assert!(filled.is_interface() && !filled.emits());
+        assert!(!material.is_interface() && !material.emits());
+        assert!(Material::Solid { color: Color::zero().into(), illuminate: Color { r: 0.0, g: 0.1, b: 0.0 }.into() }.emits());
+    }
 }
@@ -0,0 +1,375 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::geometry::{Aabb, Frame, NormalizedVec3, Vec3};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::material::Color;
+use crate::ray_tracing::scene::medium::grid::{MajorantGrid, VoxelGrid};
+use crate::ray_tracing::spectrum::{Radiance, Spectrum};
+
+pub mod grid;
+
+/// Henyey-Greenstein phase function. `g` in `(-1, 1)` is the mean cosine of the scattering angle:
+/// positive values scatter forward, negative backward, zero uniformly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        assert!(g > -1.0 && g < 1.0, "asymmetry must lie in (-1, 1)");
+        Self { g }
+    }
+
+    /// Density per steradian of turning by an angle with cosine `cosine`; also the pdf of [`sample`](Self::sample).
+    pub fn evaluate(&self, cosine: f64) -> f64 {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cosine;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+    }
+
+    /// Samples a new direction of travel for a ray travelling along `direction`, exactly proportional to [`evaluate`](Self::evaluate).
+    pub fn sample(&self, direction: NormalizedVec3<f64>, u: (f64, f64)) -> NormalizedVec3<f64> {
+        let g = self.g;
+        let cosine = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u.0
+        } else {
+            let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * u.0);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        }.clamp(-1.0, 1.0);
+        let sine = (1.0 - cosine * cosine).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        Frame::from_normal(direction).to_world(Vec3::new(sine * phi.cos(), sine * phi.sin(), cosine)).normalize()
+    }
+}
+
+/// Outcome of sampling the free path of a ray through a medium.
+#[derive(Debug, Clone)]
+pub enum MediumSample<S> {
+    /// The ray scatters at `distance`; `weight` multiplies the path throughput and already includes the scattering albedo.
+    Scattered { distance: f64, weight: S },
+    /// The ray reaches the end of the segment; `weight` is its transmittance over the sampling probability.
+    Passed { weight: S },
+    /// The ray is absorbed and the path ends.
+    Absorbed,
+}
+
+/// Colour of a black body at `temperature` Kelvin, normalized so its strongest channel at the peak wavelength is one.
+/// Planck's law is evaluated at the wavelengths of the CIE RGB primaries.
+pub fn blackbody(temperature: f64) -> Color {
+    Color::spectrum(&Spectrum::Blackbody(temperature), &())
+}
+
+/// Beer-Lambert attenuation over `distance`; zero coefficients attenuate nothing even over infinite distances.
+fn beer_lambert<S: Radiance>(extinction: &S, distance: f64) -> S {
+    extinction.map(|extinction| if extinction == 0.0 { 1.0 } else { (-extinction * distance).exp() })
+}
+
+/// Volume filling space, described by per-channel coefficients per unit distance.
+#[derive(Debug, Clone)]
+pub enum Medium {
+    /// Coefficients constant throughout, such as fog or a clear liquid.
+    Homogeneous { absorption: Color, scattering: Color, phase: HenyeyGreenstein },
+    /// Coefficients scaled by `density` stretched over `bounds`, such as smoke or clouds; empty outside `bounds`.
+    /// Absorbing regions glow with the black body colour of `temperature` times `emission_scale`, like fire.
+    Grid {
+        bounds: Aabb,
+        density: VoxelGrid,
+        majorant: MajorantGrid,
+        absorption: Color,
+        scattering: Color,
+        phase: HenyeyGreenstein,
+        temperature: Option<VoxelGrid>,
+        emission_scale: f64,
+    },
+}
+
+impl Medium {
+    /// Resolution of the majorant grid along each axis, at most.
+    const MAJORANT_RESOLUTION: usize = 16;
+
+    /// Panics on negative coefficients.
+    pub fn homogeneous(absorption: Color, scattering: Color, g: f64) -> Self {
+        Self::check(&absorption, &scattering);
+        Medium::Homogeneous { absorption, scattering, phase: HenyeyGreenstein::new(g) }
+    }
+
+    /// Panics on negative coefficients; [`VoxelGrid`] already rejects negative densities.
+    pub fn grid(bounds: Aabb, density: VoxelGrid, absorption: Color, scattering: Color, g: f64) -> Self {
+        Self::check(&absorption, &scattering);
+        let resolution = density.resolution().map(|n| n.min(Self::MAJORANT_RESOLUTION));
+        let majorant = MajorantGrid::new(&density, resolution);
+        Medium::Grid { bounds, majorant, density, absorption, scattering, phase: HenyeyGreenstein::new(g), temperature: None, emission_scale: 0.0 }
+    }
+
+    /// Adds emission to a grid medium from a grid of temperatures in Kelvin over the same bounds. Panics for homogeneous media.
+    pub fn with_temperature(self, temperature: VoxelGrid, scale: f64) -> Self {
+        match self {
+            Medium::Grid { bounds, density, majorant, absorption, scattering, phase, .. } => {
+                Medium::Grid { bounds, density, majorant, absorption, scattering, phase, temperature: Some(temperature), emission_scale: scale }
+            }
+            Medium::Homogeneous { .. } => panic!("only grid media have temperatures"),
+        }
+    }
+
+    fn check(absorption: &Color, scattering: &Color) {
+        for value in [absorption.r, absorption.g, absorption.b, scattering.r, scattering.g, scattering.b] {
+            assert!(value >= 0.0, "medium coefficients must not be negative");
+        }
+    }
+
+    pub fn phase(&self) -> &HenyeyGreenstein {
+        match self {
+            Medium::Homogeneous { phase, .. } | Medium::Grid { phase, .. } => phase,
+        }
+    }
+
+    /// Origin and direction of `ray` in the unit cube spanned by `bounds`, keeping the ray parameter unchanged.
+    fn to_grid(bounds: &Aabb, ray: &Ray) -> (Vec3<f64>, Vec3<f64>) {
+        let size = bounds.max() - bounds.min();
+        let scale = |v: Vec3<f64>| Vec3::new(v.x() / size.x(), v.y() / size.y(), v.z() / size.z());
+        (scale(ray.initial - bounds.min()), scale(ray.direction.vec()))
+    }
+
+    /// Fraction of light per channel surviving from the origin of `ray` to `t_max` (Beer-Lambert). Exact for homogeneous
+    /// media; estimated without bias by ratio tracking through the majorant grid otherwise.
+    pub fn transmittance<S: Radiance>(&self, ray: &Ray, t_max: f64, wavelengths: &S::Wavelengths, rng: &mut impl Rng) -> S {
+        match self {
+            Medium::Homogeneous { absorption, scattering, .. } => {
+                beer_lambert(&(S::unbounded(absorption, wavelengths) + S::unbounded(scattering, wavelengths)), t_max)
+            }
+            Medium::Grid { bounds, density, majorant, absorption, scattering, .. } => {
+                let extinction = S::unbounded(absorption, wavelengths) + S::unbounded(scattering, wavelengths);
+                let scale = extinction.max_value();
+                let (initial, direction) = Self::to_grid(bounds, ray);
+                let mut transmittance = S::splat(1.0);
+                majorant.traverse(initial, direction, 0.0, t_max, |t0, t1, bound| {
+                    let sigma_majorant = bound * scale;
+                    if sigma_majorant <= 0.0 {
+                        return true;
+                    }
+                    let mut t = t0;
+                    loop {
+                        t -= (1.0 - rng.gen_range(0.0..1.0f64)).ln() / sigma_majorant;
+                        if t >= t1 {
+                            return true;
+                        }
+                        let d = density.lookup(initial + direction * t);
+                        transmittance = transmittance.clone() * extinction.map(|sigma| 1.0 - d * sigma / sigma_majorant);
+                        // Russian roulette ends estimates that can no longer contribute much.
+                        if transmittance.max_value() < 0.1 {
+                            if rng.gen_range(0.0..1.0) < 0.75 {
+                                transmittance = S::zero();
+                                return false;
+                            }
+                            transmittance = transmittance.map(|c| c / 0.25);
+                        }
+                    }
+                });
+                transmittance
+            }
+        }
+    }
+
+    /// Samples where `ray` first interacts with the medium within `t_max`, also returning the light emitted along the way
+    /// towards the ray's origin, to be scaled by the path throughput before the sample's weight.
+    ///
+    /// A channel chosen uniformly sets the distance distribution of homogeneous media and the weights divide by the
+    /// average density over all channels, so coloured media stay unbiased in every channel. Purely absorbing
+    /// homogeneous media pass with their exact transmittance. Grid media use delta tracking against the majorant
+    /// grid, choosing between absorption, scattering and null collisions by their average coefficients.
+    pub fn sample<S: Radiance>(&self, ray: &Ray, t_max: f64, wavelengths: &S::Wavelengths, rng: &mut impl Rng) -> (MediumSample<S>, S) {
+        match self {
+            Medium::Homogeneous { absorption, scattering: Color { r, g, b }, .. } if *r == 0.0 && *g == 0.0 && *b == 0.0 => {
+                (MediumSample::Passed { weight: beer_lambert(&S::unbounded(absorption, wavelengths), t_max) }, S::zero())
+            }
+            Medium::Homogeneous { absorption, scattering, .. } => {
+                let scattering = S::unbounded(scattering, wavelengths);
+                let extinction = S::unbounded(absorption, wavelengths) + scattering.clone();
+                let channel = extinction.channel(rng.gen_range(0..S::CHANNELS));
+                let distance = if channel > 0.0 { -(1.0 - rng.gen_range(0.0..1.0f64)).ln() / channel } else { f64::INFINITY };
+                let sample = if distance < t_max {
+                    let transmittance = beer_lambert(&extinction, distance);
+                    let density = (extinction * transmittance.clone()).mean();
+                    MediumSample::Scattered { distance, weight: (scattering * transmittance).map(|c| c / density) }
+                } else {
+                    let transmittance = beer_lambert(&extinction, t_max);
+                    let probability = transmittance.mean();
+                    MediumSample::Passed { weight: transmittance.map(|c| if probability > 0.0 { c / probability } else { 0.0 }) }
+                };
+                (sample, S::zero())
+            }
+            Medium::Grid { bounds, density, majorant, absorption, scattering, temperature, emission_scale, .. } => {
+                let (absorption, scattering) = (S::unbounded(absorption, wavelengths), S::unbounded(scattering, wavelengths));
+                let scale = (absorption.clone() + scattering.clone()).max_value();
+                let (initial, direction) = Self::to_grid(bounds, ray);
+                let mut weight = S::splat(1.0);
+                let mut emitted = S::zero();
+                let mut sample = None;
+                majorant.traverse(initial, direction, 0.0, t_max, |t0, t1, bound| {
+                    let sigma_majorant = bound * scale;
+                    if sigma_majorant <= 0.0 {
+                        return true;
+                    }
+                    let mut t = t0;
+                    loop {
+                        t -= (1.0 - rng.gen_range(0.0..1.0f64)).ln() / sigma_majorant;
+                        if t >= t1 {
+                            return true;
+                        }
+                        let p = initial + direction * t;
+                        let d = density.lookup(p);
+                        let (sigma_a, sigma_s) = (absorption.map(|sigma| sigma * d), scattering.map(|sigma| sigma * d));
+                        if let Some(temperature) = temperature {
+                            let radiance = S::spectrum(&Spectrum::Blackbody(temperature.lookup(p)), wavelengths).map(|c| c * emission_scale);
+                            emitted = emitted.clone() + (weight.clone() * sigma_a.clone() * radiance).map(|c| c / sigma_majorant);
+                        }
+                        let (p_absorb, p_scatter) = (sigma_a.mean() / sigma_majorant, sigma_s.mean() / sigma_majorant);
+                        let u = rng.gen_range(0.0..1.0);
+                        if u < p_absorb {
+                            sample = Some(MediumSample::Absorbed);
+                            return false;
+                        }
+                        if u < p_absorb + p_scatter {
+                            let average = sigma_s.mean();
+                            sample = Some(MediumSample::Scattered { distance: t, weight: weight.clone() * sigma_s.map(|sigma| sigma / average) });
+                            return false;
+                        }
+                        let p_null = 1.0 - p_absorb - p_scatter;
+                        weight = weight.clone() * (sigma_a + sigma_s).map(|sigma| (sigma_majorant - sigma) / (sigma_majorant * p_null));
+                    }
+                });
+                (sample.unwrap_or(MediumSample::Passed { weight }), emitted)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::{Aabb, Vec3};
+    use crate::ray_tracing::Ray;
+    use crate::ray_tracing::scene::material::Color;
+    use crate::ray_tracing::scene::medium::grid::VoxelGrid;
+    use crate::ray_tracing::scene::medium::{blackbody, HenyeyGreenstein, Medium, MediumSample};
+
+    #[test]
+    fn henyey_greenstein_test() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let phase = HenyeyGreenstein::new(g);
+            // The density integrates to one over the sphere.
+            const STEPS: usize = 20000;
+            let integral: f64 = (0..STEPS).map(|i| {
+                let cosine = -1.0 + 2.0 * (i as f64 + 0.5) / STEPS as f64;
+                phase.evaluate(cosine) * 2.0 * PI * 2.0 / STEPS as f64
+            }).sum();
+            assert!((integral - 1.0).abs() < 1e-3, "g {} integral {}", g, integral);
+
+            // Samples have mean cosine g around the direction of travel.
+            let direction = Vec3::new(1.0, 2.0, -0.5).normalize();
+            const COUNT: usize = 20000;
+            let mean: f64 = (0..COUNT).map(|_| {
+                let sample = phase.sample(direction, (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)));
+                assert!((sample.vec().squared_len() - 1.0).abs() < 1e-9);
+                sample.vec().inner_product(direction.vec())
+            }).sum::<f64>() / COUNT as f64;
+            assert!((mean - g).abs() < 0.02, "g {} mean {}", g, mean);
+        }
+    }
+
+    fn ray() -> Ray {
+        Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0).normalize(), 0.0)
+    }
+
+    /// Checks per channel that passing weights estimate transmittance over 2 units and scattering weights the probability of scattering.
+    fn check_sampling(medium: &Medium, absorption: &Color, scattering: &Color) {
+        let mut rng = StdRng::seed_from_u64(1);
+        let extinction = absorption.clone() + scattering.clone();
+        let expected = |extinction: f64| (-2.0 * extinction).exp();
+        const COUNT: usize = 100000;
+        let (mut passed, mut scattered, mut transmittance) = (Color::zero(), Color::zero(), Color::zero());
+        for _ in 0..COUNT {
+            match medium.sample::<Color>(&ray(), 2.0, &(), &mut rng).0 {
+                MediumSample::Passed { weight } => passed = passed + weight,
+                MediumSample::Scattered { distance, weight } => {
+                    assert!((0.0..2.0).contains(&distance));
+                    scattered = scattered + weight;
+                }
+                MediumSample::Absorbed => {}
+            }
+            transmittance = transmittance + medium.transmittance::<Color>(&ray(), 2.0, &(), &mut rng);
+        }
+        let count = COUNT as f64;
+        for (passed, scattered, transmittance, extinction, scattering) in [
+            (passed.r, scattered.r, transmittance.r, extinction.r, scattering.r),
+            (passed.g, scattered.g, transmittance.g, extinction.g, scattering.g),
+            (passed.b, scattered.b, transmittance.b, extinction.b, scattering.b),
+        ] {
+            assert!((passed / count - expected(extinction)).abs() < 0.01, "{:?}", medium);
+            assert!((transmittance / count - expected(extinction)).abs() < 0.01, "{:?}", medium);
+            let albedo = if extinction > 0.0 { scattering / extinction } else { 0.0 };
+            assert!((scattered / count - albedo * (1.0 - expected(extinction))).abs() < 0.01, "{:?}", medium);
+        }
+    }
+
+    #[test]
+    fn medium_sample_test() {
+        let (absorption, scattering) = (Color { r: 0.5, g: 0.0, b: 0.2 }, Color { r: 0.5, g: 1.0, b: 0.0 });
+        let medium = Medium::homogeneous(absorption.clone(), scattering.clone(), 0.0);
+        let mut rng = StdRng::seed_from_u64(0);
+        let transmittance = medium.transmittance::<Color>(&ray(), 2.0, &(), &mut rng);
+        assert!((transmittance.r - (-2.0f64).exp()).abs() < 1e-12);
+        assert!((transmittance.b - (-0.4f64).exp()).abs() < 1e-12);
+        check_sampling(&medium, &absorption, &scattering);
+
+        // Delta and ratio tracking through a uniform grid agree with the closed forms.
+        let bounds = Aabb::new(Vec3::new(-5.0, -5.0, -5.0), Vec3::new(5.0, 5.0, 5.0));
+        let grid = Medium::grid(bounds, VoxelGrid::new([2, 2, 2], vec![0.5; 8]), absorption.clone() * Color { r: 2.0, g: 2.0, b: 2.0 }, scattering.clone() * Color { r: 2.0, g: 2.0, b: 2.0 }, 0.3);
+        check_sampling(&grid, &absorption, &scattering);
+
+        // Nothing interacts outside the bounds.
+        let outside = Ray::new(Vec3::new(6.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0).normalize(), 0.0);
+        assert!(matches!(grid.sample::<Color>(&outside, 2.0, &(), &mut rng).0, MediumSample::Passed { weight: Color { r, g, b } } if r == 1.0 && g == 1.0 && b == 1.0));
+    }
+
+    #[test]
+    fn grid_emission_test() {
+        // A purely absorbing glowing slab of optical depth s emits Le (1 - e^-s) towards the viewer.
+        let bounds = Aabb::new(Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 2.0));
+        let absorption = Color { r: 1.0, g: 0.5, b: 0.25 };
+        let medium = Medium::grid(bounds, VoxelGrid::new([1, 1, 1], vec![1.0]), absorption, Color::zero(), 0.0)
+            .with_temperature(VoxelGrid::new([1, 1, 1], vec![1500.0]), 2.0);
+        let radiance = blackbody(1500.0);
+        let mut rng = StdRng::seed_from_u64(2);
+        const COUNT: usize = 50000;
+        let mut emitted = Color::zero();
+        for _ in 0..COUNT {
+            let (sample, light) = medium.sample::<Color>(&ray(), 10.0, &(), &mut rng);
+            assert!(!matches!(sample, MediumSample::Scattered { .. }));
+            emitted = emitted + light;
+        }
+        let count = COUNT as f64;
+        assert!((emitted.r / count - 2.0 * radiance.r * (1.0 - (-2.0f64).exp())).abs() < 0.02);
+        assert!((emitted.g / count - 2.0 * radiance.g * (1.0 - (-1.0f64).exp())).abs() < 0.02);
+        assert!((emitted.b / count - 2.0 * radiance.b * (1.0 - (-0.5f64).exp())).abs() < 0.02);
+    }
+
+    #[test]
+    fn blackbody_test() {
+        let (ember, sky) = (blackbody(1500.0), blackbody(12000.0));
+        assert!(ember.r > ember.g && ember.g > ember.b);
+        assert!(sky.b > sky.g && sky.g > sky.r);
+        for color in [ember, sky, blackbody(5000.0)] {
+            assert!(color.r <= 1.0 && color.g <= 1.0 && color.b <= 1.0);
+        }
+        assert_eq!(blackbody(0.0).r, 0.0);
+    }
+}
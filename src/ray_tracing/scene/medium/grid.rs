@@ -0,0 +1,265 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::geometry::{Aabb, Vec3};
+use crate::ray_tracing::scene::texture::noise::fbm;
+
+/// Scalar field over the unit cube, sampled at voxel centres and reconstructed trilinearly.
+/// Voxels are stored with x varying fastest, then y, then z. Values are finite and non-negative, like densities and temperatures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelGrid {
+    resolution: [usize; 3],
+    values: Vec<f32>,
+}
+
+impl VoxelGrid {
+    /// Identifies the raw format read by [`read`](Self::read): the magic bytes, three little-endian `u32`
+    /// resolutions, then one little-endian `f32` per voxel.
+    const MAGIC: &'static [u8; 4] = b"VOXG";
+
+    pub fn new(resolution: [usize; 3], values: Vec<f32>) -> Self {
+        assert!(resolution.iter().all(|&n| n > 0), "grid resolution must be positive");
+        assert_eq!(values.len(), resolution[0] * resolution[1] * resolution[2]);
+        assert!(values.iter().all(|value| value.is_finite() && *value >= 0.0), "voxel values must be finite and non-negative");
+        Self { resolution, values }
+    }
+
+    /// Samples `f` at every voxel centre, given in unit-cube coordinates.
+    pub fn from_fn(resolution: [usize; 3], f: impl Fn(Vec3<f64>) -> f64) -> Self {
+        let [nx, ny, nz] = resolution;
+        let values = (0..nz).flat_map(|z| (0..ny).flat_map(move |y| (0..nx).map(move |x| (x, y, z))))
+            .map(|(x, y, z)| f(Vec3::new((x as f64 + 0.5) / nx as f64, (y as f64 + 0.5) / ny as f64, (z as f64 + 0.5) / nz as f64)) as f32)
+            .collect();
+        Self::new(resolution, values)
+    }
+
+    /// Cloud-like density: positive lobes of fractal noise at `frequency` cycles across the cube, fading out
+    /// towards the faces so the volume has no hard edges.
+    pub fn from_noise(resolution: [usize; 3], frequency: f64, octaves: usize) -> Self {
+        Self::from_fn(resolution, |p| {
+            let centered = (p - Vec3::new(0.5, 0.5, 0.5)) * 2.0;
+            let falloff = (1.0 - centered.squared_len().sqrt()).max(0.0);
+            (fbm(p * frequency, octaves) + 0.3).max(0.0) * falloff
+        })
+    }
+
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != Self::MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a voxel grid"));
+        }
+        let mut word = [0; 4];
+        let mut resolution = [0; 3];
+        for n in &mut resolution {
+            reader.read_exact(&mut word)?;
+            *n = u32::from_le_bytes(word) as usize;
+        }
+        let count = resolution.iter().try_fold(1usize, |count, &n| count.checked_mul(n))
+            .filter(|&count| count > 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid grid resolution"))?;
+        let mut values = Vec::with_capacity(count.min(1 << 24));
+        for _ in 0..count {
+            reader.read_exact(&mut word)?;
+            let value = f32::from_le_bytes(word);
+            if !value.is_finite() || value < 0.0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "voxel values must be finite and non-negative"));
+            }
+            values.push(value);
+        }
+        Ok(Self::new(resolution, values))
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(Self::MAGIC)?;
+        for &n in &self.resolution {
+            writer.write_all(&(n as u32).to_le_bytes())?;
+        }
+        for value in &self.values {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[(z * self.resolution[1] + y) * self.resolution[0] + x] as f64
+    }
+
+    /// Trilinear reconstruction at `p` in unit-cube coordinates; zero outside the cube.
+    pub fn lookup(&self, p: Vec3<f64>) -> f64 {
+        let p = [*p.x(), *p.y(), *p.z()];
+        if p.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return 0.0;
+        }
+        let mut lower = [0; 3];
+        let mut upper = [0; 3];
+        let mut weight = [0.0; 3];
+        for axis in 0..3 {
+            let n = self.resolution[axis];
+            let x = p[axis] * n as f64 - 0.5;
+            let base = x.floor();
+            lower[axis] = (base.max(0.0) as usize).min(n - 1);
+            upper[axis] = ((base + 1.0).max(0.0) as usize).min(n - 1);
+            weight[axis] = x - base;
+        }
+        let lerp = |t: f64, a: f64, b: f64| a + (b - a) * t;
+        let plane = |z: usize| lerp(weight[1],
+                                    lerp(weight[0], self.value(lower[0], lower[1], z), self.value(upper[0], lower[1], z)),
+                                    lerp(weight[0], self.value(lower[0], upper[1], z), self.value(upper[0], upper[1], z)));
+        lerp(weight[2], plane(lower[2]), plane(upper[2]))
+    }
+}
+
+/// Upper bounds of a [`VoxelGrid`] over the cells of a coarser grid, so tracking takes long steps through thin regions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MajorantGrid {
+    resolution: [usize; 3],
+    values: Vec<f64>,
+}
+
+impl MajorantGrid {
+    pub fn new(grid: &VoxelGrid, resolution: [usize; 3]) -> Self {
+        assert!(resolution.iter().all(|&n| n > 0), "grid resolution must be positive");
+        // Trilinear lookups inside a cell blend the voxels whose centres lie within one voxel of it.
+        let voxels = |axis: usize, cell: usize| {
+            let (n, m) = (grid.resolution[axis] as f64, resolution[axis] as f64);
+            let lower = (cell as f64 / m * n - 0.5).floor().max(0.0) as usize;
+            let upper = (((cell + 1) as f64 / m * n - 0.5).floor() + 1.0).max(0.0) as usize;
+            lower.min(grid.resolution[axis] - 1)..=upper.min(grid.resolution[axis] - 1)
+        };
+        let [mx, my, mz] = resolution;
+        let values = (0..mz).flat_map(|z| (0..my).flat_map(move |y| (0..mx).map(move |x| (x, y, z))))
+            .map(|(x, y, z)| {
+                voxels(2, z).flat_map(|vz| voxels(1, y).flat_map(move |vy| voxels(0, x).map(move |vx| (vx, vy, vz))))
+                    .map(|(vx, vy, vz)| grid.value(vx, vy, vz))
+                    .fold(0.0, f64::max)
+            })
+            .collect();
+        Self { resolution, values }
+    }
+
+    /// Walks the cells pierced by `initial + direction * t` for `t` in `[t_min, t_max]`, both in unit-cube coordinates,
+    /// calling `f` with each cell's parametric range and majorant until it returns `false`.
+    pub fn traverse(&self, initial: Vec3<f64>, direction: Vec3<f64>, t_min: f64, t_max: f64, mut f: impl FnMut(f64, f64, f64) -> bool) {
+        let unit = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        let inverse_direction = Vec3::new(1.0 / direction.x(), 1.0 / direction.y(), 1.0 / direction.z());
+        let (t0, t1) = match unit.intersect(initial, inverse_direction, t_min, t_max) {
+            Some(range) => range,
+            None => return,
+        };
+        let o = [*initial.x(), *initial.y(), *initial.z()];
+        let d = [*direction.x(), *direction.y(), *direction.z()];
+        let mut cell = [0isize; 3];
+        let mut next = [f64::INFINITY; 3];
+        let mut delta = [f64::INFINITY; 3];
+        for axis in 0..3 {
+            let m = self.resolution[axis] as f64;
+            let p = o[axis] + d[axis] * t0;
+            cell[axis] = ((p * m).floor() as isize).clamp(0, self.resolution[axis] as isize - 1);
+            if d[axis] > 0.0 {
+                next[axis] = t0 + ((cell[axis] + 1) as f64 / m - p) / d[axis];
+                delta[axis] = 1.0 / (m * d[axis]);
+            } else if d[axis] < 0.0 {
+                next[axis] = t0 + (cell[axis] as f64 / m - p) / d[axis];
+                delta[axis] = -1.0 / (m * d[axis]);
+            }
+        }
+        let mut t = t0;
+        loop {
+            let axis = if next[0] < next[1] && next[0] < next[2] { 0 } else if next[1] < next[2] { 1 } else { 2 };
+            let end = next[axis].min(t1);
+            let index = (cell[2] as usize * self.resolution[1] + cell[1] as usize) * self.resolution[0] + cell[0] as usize;
+            if !f(t, end, self.values[index]) || end >= t1 {
+                return;
+            }
+            t = end;
+            cell[axis] += if d[axis] > 0.0 { 1 } else { -1 };
+            if cell[axis] < 0 || cell[axis] >= self.resolution[axis] as isize {
+                return;
+            }
+            next[axis] += delta[axis];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::medium::grid::{MajorantGrid, VoxelGrid};
+
+    #[test]
+    fn voxel_grid_test() {
+        let grid = VoxelGrid::from_fn([4, 2, 3], |p| *p.x() + 2.0 * *p.y() + 3.0 * *p.z());
+        // Trilinear reconstruction is exact for linear fields between the outermost voxel centres.
+        assert!((grid.lookup(Vec3::new(0.5, 0.5, 0.5)) - 3.0).abs() < 1e-6);
+        assert!((grid.lookup(Vec3::new(0.3, 0.4, 0.6)) - (0.3 + 0.8 + 1.8)).abs() < 1e-6);
+        assert_eq!(grid.lookup(Vec3::new(1.5, 0.5, 0.5)), 0.0);
+
+        let mut bytes = Vec::new();
+        grid.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 16 + 4 * 24);
+        assert_eq!(VoxelGrid::read(&bytes[..]).unwrap(), grid);
+        assert!(VoxelGrid::read(&bytes[..20]).is_err());
+        assert!(VoxelGrid::read(&b"NOPE"[..]).is_err());
+        let mut negative = bytes.clone();
+        negative[16..20].copy_from_slice(&(-1.0f32).to_le_bytes());
+        assert!(VoxelGrid::read(&negative[..]).is_err());
+
+        let cloud = VoxelGrid::from_noise([16, 16, 16], 4.0, 3);
+        assert!(cloud.lookup(Vec3::new(0.5, 0.5, 0.5)) >= 0.0);
+        assert_eq!(cloud.lookup(Vec3::new(0.02, 0.02, 0.02)), 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "voxel values must be finite and non-negative")]
+    fn negative_voxel_test() {
+        VoxelGrid::new([2, 1, 1], vec![0.5, -0.5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "voxel values must be finite and non-negative")]
+    fn nan_voxel_test() {
+        VoxelGrid::new([1, 1, 1], vec![f32::NAN]);
+    }
+
+    #[test]
+    fn majorant_grid_test() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let grid = VoxelGrid::from_noise([20, 12, 16], 3.0, 4);
+        let majorant = MajorantGrid::new(&grid, [3, 4, 5]);
+        for _ in 0..200 {
+            let initial = Vec3::new(rng.gen_range(-0.5..1.5), rng.gen_range(-0.5..1.5), rng.gen_range(-0.5..1.5));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let mut previous: Option<f64> = None;
+            majorant.traverse(initial, direction, 0.0, 10.0, |t0, t1, bound| {
+                // Segments are contiguous and bound every lookup within them.
+                assert!(t0 <= t1);
+                if let Some(previous) = previous {
+                    assert!((previous - t0).abs() < 1e-9);
+                }
+                previous = Some(t1);
+                for i in 0..=8 {
+                    let t = t0 + (t1 - t0) * i as f64 / 8.0;
+                    assert!(grid.lookup(initial + direction * t) <= bound + 1e-6);
+                }
+                true
+            });
+        }
+    }
+}
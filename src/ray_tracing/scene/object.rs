@@ -1,59 +1,187 @@
-use crate::geometry::{NormalizedVec3, Vec3};
+use std::f64::consts::PI;
+
+use crate::geometry::{gamma, Aabb, NormalizedVec3, Vec3};
+use crate::ray_tracing::packet::{RayPacket, PACKET_WIDTH};
 use crate::ray_tracing::Ray;
-use crate::ray_tracing::scene::Collision;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::csg::{Interval, Solid};
 use crate::ray_tracing::scene::material::Material;
 
+pub mod cuboid;
+pub mod cylinder;
+pub mod plane;
+pub mod sdf;
+pub mod torus;
+#[cfg(test)]
+pub mod test_util;
+
 #[derive(Clone, Debug)]
 pub struct Sphere {
     center: Vec3<f64>,
+    /// End center and the time span it moves over, for spheres that move linearly during the shutter.
+    motion: Option<(Vec3<f64>, f64, f64)>,
     radius: f64,
     material: Material,
 }
 
 impl Sphere {
     pub fn new(center: Vec3<f64>, radius: f64, material: Material) -> Self {
-        Self { center, radius, material }
+        Self { center, motion: None, radius, material }
+    }
+
+    /// A sphere moving from `center0` at `time0` to `center1` at `time1`, resting at either end outside that span.
+    pub fn moving(center0: Vec3<f64>, time0: f64, center1: Vec3<f64>, time1: f64, radius: f64, material: Material) -> Self {
+        assert!(time0 < time1, "motion must end after it starts");
+        Self { center: center0, motion: Some((center1, time0, time1)), radius, material }
+    }
+
+    fn center(&self, time: f64) -> Vec3<f64> {
+        match self.motion {
+            Some((center1, time0, time1)) => {
+                let t = ((time - time0) / (time1 - time0)).clamp(0.0, 1.0);
+                self.center + (center1 - self.center) * t
+            }
+            None => self.center,
+        }
+    }
+
+    /// `u` goes around the y axis starting from -x, `v` goes from the bottom (-y) to the top (+y).
+    fn uv(normal: NormalizedVec3<f64>) -> (f64, f64) {
+        let normal: Vec3<_> = normal.into();
+        let theta = (-normal.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-normal.z()).atan2(*normal.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    /// Partial derivatives of the position with respect to `u` and `v`.
+    fn dpduv(&self, normal: NormalizedVec3<f64>) -> (Vec3<f64>, Vec3<f64>) {
+        let normal = normal.vec();
+        let (x, y, z) = (*normal.x(), *normal.y(), *normal.z());
+        let sin_theta = (x * x + z * z).sqrt().max(1e-12);
+        (
+            Vec3::new(z, 0.0, -x) * (2.0 * PI * self.radius),
+            Vec3::new(-x * y / sin_theta, sin_theta, -y * z / sin_theta) * (PI * self.radius),
+        )
     }
 }
 
-impl Collision for Sphere {
-    fn collision(&self, ray: &Ray) -> Option<(f64, NormalizedVec3<f64>, Material)> {
+impl Sphere {
+    /// Both parametric distances to the surface along the ray's line, nearest first.
+    fn roots(&self, ray: &Ray) -> Option<(f64, f64)> {
         let d: Vec3<_> = ray.direction.into();
-        let c = self.center - ray.initial;
+        let c = self.center(ray.time) - ray.initial;
         let a = d.squared_len();
         let half_b = -d.inner_product(c);
         let c = c.squared_len() - self.radius * self.radius;
-        if half_b * half_b - a * c < 0.0 {
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
             None
         } else {
-            let x = (-half_b - (half_b * half_b - a * c).sqrt()) / a;
-            let x = if x > 0.0 {
-                x
-            } else {
-                (-half_b + (half_b * half_b - a * c).sqrt()) / a
-            };
-            if x > 0f64 {
-                Some((x, (ray.initial + d * x - self.center).normalize(), self.material.clone()))
-            } else {
-                None
+            Some(((-half_b - discriminant.sqrt()) / a, (-half_b + discriminant.sqrt()) / a))
+        }
+    }
+
+    fn hit(&self, ray: &Ray, x: f64) -> SurfaceInteraction<'_> {
+        let center = self.center(ray.time);
+        let normal = (ray.initial + ray.direction.vec() * x - center).normalize();
+        // Reprojecting onto the surface bounds the error by the few operations below, however inexact `x` is.
+        let local_position = normal.vec() * self.radius;
+        let position = center + local_position;
+        let (dpdu, dpdv) = self.dpduv(normal);
+        SurfaceInteraction {
+            distance: x,
+            normal,
+            front_face: normal.vec().inner_product(ray.direction.vec()) < 0.0,
+            uv: Sphere::uv(normal),
+            position,
+            error: local_position.abs() * gamma(5) + position.abs() * gamma(1),
+            local_position,
+            dpdu,
+            dpdv,
+            dndu: dpdu / self.radius,
+            dndv: dpdv / self.radius,
+            shading: None,
+            primitive_id: 0,
+            material: &self.material,
+        }
+    }
+}
+
+impl Collision for Sphere {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        let (near, far) = self.roots(ray)?;
+        let x = if near > t_min { near } else { far };
+        if x > t_min && x < t_max { Some(self.hit(ray, x)) } else { None }
+    }
+
+    fn collision_packet<'a>(&'a self, packet: &RayPacket<PACKET_WIDTH>, active: &[bool; PACKET_WIDTH], t_min: f64,
+                            t_max: &mut [f64; PACKET_WIDTH], hits: &mut [Option<SurfaceInteraction<'a>>; PACKET_WIDTH]) {
+        let centers = match self.motion {
+            Some(_) => std::array::from_fn(|i| self.center(packet.rays()[i].time)),
+            None => [self.center; PACKET_WIDTH],
+        };
+        let distances = packet.intersect_sphere(&centers, self.radius, t_min, t_max);
+        for i in 0..PACKET_WIDTH {
+            // Rounding the range to single precision may let a distance slip just outside it.
+            let x = distances[i] as f64;
+            if active[i] && x > t_min && x < t_max[i] {
+                t_max[i] = x;
+                hits[i] = Some(self.hit(&packet.rays()[i], x));
             }
         }
     }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        !self.material.is_interface() && self.roots(ray).is_some_and(|(near, far)| (near > 0.0 && near < t_max) || (far > 0.0 && far < t_max))
+    }
+
+    fn sample_area(&self, u: (f64, f64), time: f64) -> Option<(SurfaceInteraction<'_>, f64)> {
+        let z = 1.0 - 2.0 * u.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        // A ray arriving along the normal from unit distance hits exactly the sampled point.
+        let ray = Ray::new(self.center(time) + normal * (self.radius + 1.0), (-normal).normalize(), time);
+        Some((SurfaceInteraction { distance: 0.0, ..self.hit(&ray, 1.0) }, 4.0 * PI * self.radius * self.radius))
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let aabb = Aabb::new(self.center - r, self.center + r);
+        match self.motion {
+            Some((center1, _, _)) => aabb.union(&Aabb::new(center1 - r, center1 + r)),
+            None => aabb,
+        }
+    }
+}
+
+impl Solid for Sphere {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        match self.roots(ray) {
+            Some((near, far)) => vec![Interval { enter: self.hit(ray, near), exit: self.hit(ray, far) }],
+            None => Vec::new(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::geometry::Vec3;
+    use crate::ray_tracing::packet::{RayPacket, PACKET_WIDTH};
     use crate::ray_tracing::Ray;
-    use crate::ray_tracing::scene::Collision;
+    use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
     use crate::ray_tracing::scene::material::{Color, Material};
     use crate::ray_tracing::scene::object::Sphere;
 
     #[test]
     fn sphere_collision_test() {
-        let sphere = Sphere::new(Vec3::new(0f64, 0f64, 0f64), 1.0, Material::Solid(Color { r: 0f64, g: 0f64, b: 0f64 }));
-        let collision = sphere.collision(&Ray { initial: Vec3::new(2f64, 0f64, 0f64), direction: Vec3::new(-1f64, 0f64, 0f64).normalize() });
-        if let Some((x, normal, _)) = collision {
+        let sphere = Sphere::new(Vec3::new(0f64, 0f64, 0f64), 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let collision = sphere.collision(&Ray { initial: Vec3::new(2f64, 0f64, 0f64), direction: Vec3::new(-1f64, 0f64, 0f64).normalize(), differential: None, time: 0.0 }, 0.0, f64::INFINITY);
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = collision {
             let normal: Vec3<_> = normal.into();
             assert!((x - 1.0).abs() < 1e-3);
             assert!((normal.x() - 1.0).abs() < 1e-3);
@@ -63,8 +191,8 @@ mod tests {
             unreachable!()
         }
 
-        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 2f64, 0f64), direction: Vec3::new(0f64, -1f64, 0f64).normalize() });
-        if let Some((x, normal, _)) = collision {
+        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 2f64, 0f64), direction: Vec3::new(0f64, -1f64, 0f64).normalize(), differential: None, time: 0.0 }, 0.0, f64::INFINITY);
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = collision {
             let normal: Vec3<_> = normal.into();
             assert!((x - 1.0).abs() < 1e-3);
             assert!((normal.x() - 0.0).abs() < 1e-3);
@@ -74,8 +202,8 @@ mod tests {
             unreachable!()
         }
 
-        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 2f64), direction: Vec3::new(0f64, 0f64, -1f64).normalize() });
-        if let Some((x, normal, _)) = collision {
+        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 2f64), direction: Vec3::new(0f64, 0f64, -1f64).normalize(), differential: None, time: 0.0 }, 0.0, f64::INFINITY);
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = collision {
             let normal: Vec3<_> = normal.into();
             assert!((x - 1.0).abs() < 1e-3);
             assert!((normal.x() - 0.0).abs() < 1e-3);
@@ -86,9 +214,9 @@ mod tests {
         }
 
 
-        let sphere = Sphere::new(Vec3::new(2f64, 0f64, 0f64), 1.0, Material::Solid(Color { r: 0f64, g: 0f64, b: 0f64 }));
-        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 0f64), direction: Vec3::new(1f64, 0f64, 0f64).normalize() });
-        if let Some((x, normal, _)) = collision {
+        let sphere = Sphere::new(Vec3::new(2f64, 0f64, 0f64), 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 0f64), direction: Vec3::new(1f64, 0f64, 0f64).normalize(), differential: None, time: 0.0 }, 0.0, f64::INFINITY);
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = collision {
             let normal: Vec3<_> = normal.into();
             assert!((x - 1.0).abs() < 1e-3);
             assert!((normal.x() + 1.0).abs() < 1e-3);
@@ -98,9 +226,9 @@ mod tests {
             unreachable!()
         }
 
-        let sphere = Sphere::new(Vec3::new(0f64, 2f64, 0f64), 1.0, Material::Solid(Color { r: 0f64, g: 0f64, b: 0f64 }));
-        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 0f64), direction: Vec3::new(0f64, 1f64, 0f64).normalize() });
-        if let Some((x, normal, _)) = collision {
+        let sphere = Sphere::new(Vec3::new(0f64, 2f64, 0f64), 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 0f64), direction: Vec3::new(0f64, 1f64, 0f64).normalize(), differential: None, time: 0.0 }, 0.0, f64::INFINITY);
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = collision {
             let normal: Vec3<_> = normal.into();
             assert!((x - 1.0).abs() < 1e-3);
             assert!((normal.x() - 0.0).abs() < 1e-3);
@@ -110,9 +238,9 @@ mod tests {
             unreachable!()
         }
 
-        let sphere = Sphere::new(Vec3::new(0f64, 0f64, 2f64), 1.0, Material::Solid(Color { r: 0f64, g: 0f64, b: 0f64 }));
-        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 0f64), direction: Vec3::new(0f64, 0f64, 1f64).normalize() });
-        if let Some((x, normal, _)) = collision {
+        let sphere = Sphere::new(Vec3::new(0f64, 0f64, 2f64), 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 0f64), direction: Vec3::new(0f64, 0f64, 1f64).normalize(), differential: None, time: 0.0 }, 0.0, f64::INFINITY);
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = collision {
             let normal: Vec3<_> = normal.into();
             assert!((x - 1.0).abs() < 1e-3);
             assert!((normal.x() - 0.0).abs() < 1e-3);
@@ -122,9 +250,9 @@ mod tests {
             unreachable!()
         }
 
-        let sphere = Sphere::new(Vec3::new(0f64, 0f64, 0f64), 1.0, Material::Solid(Color { r: 0f64, g: 0f64, b: 0f64 }));
-        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 0f64), direction: Vec3::new(1f64, 0f64, 0f64).normalize() });
-        if let Some((x, normal, _)) = collision {
+        let sphere = Sphere::new(Vec3::new(0f64, 0f64, 0f64), 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let collision = sphere.collision(&Ray { initial: Vec3::new(0f64, 0f64, 0f64), direction: Vec3::new(1f64, 0f64, 0f64).normalize(), differential: None, time: 0.0 }, 0.0, f64::INFINITY);
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = collision {
             let normal: Vec3<_> = normal.into();
             assert!((x - 1.0).abs() < 1e-3);
             assert!((normal.x() - 1.0).abs() < 1e-3);
@@ -134,4 +262,98 @@ mod tests {
             unreachable!()
         }
     }
+
+    #[test]
+    fn sphere_uv_test() {
+        let sphere = Sphere::new(Vec3::new(0f64, 0f64, 0f64), 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let uv = |initial: Vec3<f64>| {
+            let direction = -initial;
+            sphere.collision(&Ray { initial, direction: direction.normalize(), differential: None, time: 0.0 }, 0.0, f64::INFINITY).unwrap().uv
+        };
+        let (u, v) = uv(Vec3::new(0f64, 2f64, 0f64));
+        assert!((v - 1.0).abs() < 1e-9);
+        assert!((0.0..=1.0).contains(&u));
+        let (_, v) = uv(Vec3::new(0f64, -2f64, 0f64));
+        assert!(v.abs() < 1e-9);
+        let (u, v) = uv(Vec3::new(2f64, 0f64, 0f64));
+        assert!((u - 0.5).abs() < 1e-9);
+        assert!((v - 0.5).abs() < 1e-9);
+        let (u, _) = uv(Vec3::new(0f64, 0f64, -2f64));
+        assert!((u - 0.75).abs() < 1e-9);
+        let (u, _) = uv(Vec3::new(0f64, 0f64, 2f64));
+        assert!((u - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn moving_sphere_test() {
+        let sphere = Sphere::moving(Vec3::new(0.0, 0.0, 0.0), 0.0, Vec3::new(4.0, 0.0, 0.0), 1.0, 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let ray = |time| Ray { initial: Vec3::new(2.0, 0.0, 5.0), direction: Vec3::new(0.0, 0.0, -1.0).normalize(), differential: None, time };
+        assert!(sphere.collision(&ray(0.0), 0.0, f64::INFINITY).is_none());
+        let hit = sphere.collision(&ray(0.5), 0.0, f64::INFINITY).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-9);
+        assert!((hit.local_position - Vec3::new(0.0, 0.0, 1.0)).squared_len() < 1e-18);
+        assert!(sphere.collision(&ray(2.0), 0.0, f64::INFINITY).is_none());
+        let aabb = sphere.bounding_box();
+        assert_eq!(aabb.min(), Vec3::new(-1.0, -1.0, -1.0));
+        assert_eq!(aabb.max(), Vec3::new(5.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn sphere_sample_area_test() {
+        let sphere = Sphere::moving(Vec3::new(0.0, 0.0, 0.0), 0.0, Vec3::new(4.0, 0.0, 0.0), 1.0, 2.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let mut z = 0.0;
+        for i in 0..16 {
+            let u = ((i as f64 + 0.5) / 16.0, (i as f64 * 0.618) % 1.0);
+            let (point, area) = sphere.sample_area(u, 0.5).unwrap();
+            assert!((area - 16.0 * std::f64::consts::PI).abs() < 1e-9);
+            assert!(((point.position - Vec3::new(2.0, 0.0, 0.0)).squared_len().sqrt() - 2.0).abs() < 1e-9);
+            assert!((point.normal.vec() * 2.0 - point.local_position).squared_len() < 1e-18);
+            z += point.normal.vec().z();
+        }
+        // Stratified samples cover the sphere evenly.
+        assert!(z.abs() < 1e-9);
+    }
+
+    #[test]
+    fn sphere_packet_test() {
+        let sphere = Sphere::moving(Vec3::new(0.0, 0.0, 0.0), 0.0, Vec3::new(4.0, 0.0, 0.0), 1.0, 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        // Each lane samples the moving sphere at its own time.
+        let packet = RayPacket::new(std::array::from_fn(|i| {
+            Ray::new(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0).normalize(), i as f64 / (PACKET_WIDTH - 1) as f64)
+        }));
+        let mut active = [true; PACKET_WIDTH];
+        active[PACKET_WIDTH / 2] = false;
+        let mut t_max = [f64::INFINITY; PACKET_WIDTH];
+        let mut hits = std::array::from_fn(|_| None);
+        sphere.collision_packet(&packet, &active, 0.0, &mut t_max, &mut hits);
+        for (i, ray) in packet.rays().iter().enumerate() {
+            let expected = sphere.collision(ray, 0.0, f64::INFINITY).filter(|_| active[i]);
+            assert_eq!(hits[i].is_some(), expected.is_some());
+            if let (Some(hit), Some(expected)) = (&hits[i], expected) {
+                assert!((hit.distance - expected.distance).abs() < 1e-5);
+                assert_eq!(t_max[i], hit.distance);
+                // Reprojection puts the hit on the surface however inexact the single-precision distance is.
+                assert!((hit.position - expected.position).squared_len() < 1e-10);
+            }
+        }
+        assert!(hits[PACKET_WIDTH / 2].is_none());
+        assert!(hits[0].is_none() && hits[PACKET_WIDTH - 1].is_none());
+    }
+
+    #[test]
+    fn sphere_interval_test() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() });
+        let ray = Ray { initial: Vec3::new(3.0, 0.0, 0.0), direction: Vec3::new(-1.0, 0.0, 0.0).normalize(), differential: None, time: 0.0 };
+        assert!(sphere.collision(&ray, 0.0, 2.0).is_none());
+        // Past the near side, the far side is the nearest hit in range.
+        assert!((sphere.collision(&ray, 2.5, f64::INFINITY).unwrap().distance - 4.0).abs() < 1e-9);
+        assert!(!sphere.occluded(&ray, 2.0));
+        assert!(sphere.occluded(&ray, 2.5));
+        let inside = Ray { initial: Vec3::new(0.0, 0.0, 0.0), ..ray };
+        assert!(sphere.occluded(&inside, 1.5));
+        assert!(!sphere.occluded(&inside, 0.5));
+        // Interfaces bound media but never block light.
+        let bubble = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Interface);
+        assert!(bubble.collision(&ray, 0.0, 2.5).is_some() && !bubble.occluded(&ray, 2.5));
+    }
 }
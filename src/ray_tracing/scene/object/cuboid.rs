@@ -0,0 +1,150 @@
+use crate::geometry::{gamma, Aabb, Vec3};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::csg::{Interval, Solid};
+use crate::ray_tracing::scene::material::Material;
+
+fn component(v: Vec3<f64>, axis: usize) -> f64 {
+    match axis {
+        0 => *v.x(),
+        1 => *v.y(),
+        _ => *v.z(),
+    }
+}
+
+fn unit(axis: usize, length: f64) -> Vec3<f64> {
+    match axis {
+        0 => Vec3::new(length, 0.0, 0.0),
+        1 => Vec3::new(0.0, length, 0.0),
+        _ => Vec3::new(0.0, 0.0, length),
+    }
+}
+
+/// Axis-aligned box. Each face is parameterized over `[0, 1]^2` along the two axes following its own.
+#[derive(Clone, Debug)]
+pub struct Cuboid {
+    aabb: Aabb,
+    material: Material,
+}
+
+impl Cuboid {
+    pub fn new(min: Vec3<f64>, max: Vec3<f64>, material: Material) -> Self {
+        Self { aabb: Aabb::new(min, max), material }
+    }
+
+    /// Entry and exit distances along the ray's line.
+    fn roots(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let d = ray.direction.vec();
+        let inverse_direction = Vec3::new(1.0 / d.x(), 1.0 / d.y(), 1.0 / d.z());
+        self.aabb.intersect(ray.initial, inverse_direction, f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    fn hit(&self, ray: &Ray, x: f64) -> SurfaceInteraction<'_> {
+        let position = ray.initial + ray.direction.vec() * x;
+        let (min, max) = (self.aabb.min(), self.aabb.max());
+        let size = max - min;
+        // The face hit is the one the position lies closest to.
+        let (axis, sign) = (0..3)
+            .flat_map(|axis| [(axis, -1.0), (axis, 1.0)])
+            .min_by(|&(a, sa), &(b, sb)| {
+                let distance = |axis: usize, sign: f64| {
+                    let bound = if sign < 0.0 { component(min, axis) } else { component(max, axis) };
+                    (component(position, axis) - bound).abs()
+                };
+                distance(a, sa).total_cmp(&distance(b, sb))
+            })
+            .unwrap();
+        let (axis_u, axis_v) = ((axis + 1) % 3, (axis + 2) % 3);
+        // Snap onto the face, which leaves only the in-plane coordinates inexact.
+        let bound = if sign < 0.0 { component(min, axis) } else { component(max, axis) };
+        let position = position + unit(axis, bound - component(position, axis));
+        let relative = |axis: usize| (component(position, axis) - component(min, axis)) / component(size, axis);
+        SurfaceInteraction {
+            distance: x,
+            normal: unit(axis, sign).normalize(),
+            front_face: sign * component(ray.direction.vec(), axis) < 0.0,
+            uv: (relative(axis_u).clamp(0.0, 1.0), relative(axis_v).clamp(0.0, 1.0)),
+            position,
+            error: position.abs() * gamma(7),
+            local_position: position - min,
+            dpdu: unit(axis_u, component(size, axis_u)),
+            dpdv: unit(axis_v, component(size, axis_v)),
+            dndu: Vec3::new(0.0, 0.0, 0.0),
+            dndv: Vec3::new(0.0, 0.0, 0.0),
+            shading: None,
+            primitive_id: 0,
+            material: &self.material,
+        }
+    }
+}
+
+impl Collision for Cuboid {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        let (t0, t1) = self.roots(ray)?;
+        let x = if t0 > t_min { t0 } else if t1 > t_min { t1 } else { return None };
+        if x < t_max { Some(self.hit(ray, x)) } else { None }
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.aabb
+    }
+}
+
+impl Solid for Cuboid {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        match self.roots(ray) {
+            Some((t0, t1)) => vec![Interval { enter: self.hit(ray, t0), exit: self.hit(ray, t1) }],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+    use crate::ray_tracing::scene::csg::Solid;
+    use crate::ray_tracing::scene::object::cuboid::Cuboid;
+    use crate::ray_tracing::scene::object::test_util::{material, ray};
+
+    #[test]
+    fn cuboid_collision_test() {
+        let cuboid = Cuboid::new(Vec3::new(-1.0, -2.0, -3.0), Vec3::new(1.0, 2.0, 3.0), material());
+        if let Some(SurfaceInteraction { distance: x, normal, uv: (u, v), .. }) = cuboid.collision(&ray(Vec3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 4.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(1.0, 0.0, 0.0)).squared_len() < 1e-18);
+            assert!((u - 0.75).abs() < 1e-9);
+            assert!((v - 0.5).abs() < 1e-9);
+        } else {
+            unreachable!()
+        }
+
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = cuboid.collision(&ray(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 3.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, -1.0, 0.0)).squared_len() < 1e-18);
+        } else {
+            unreachable!()
+        }
+
+        // From inside, the exit face is hit.
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = cuboid.collision(&ray(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::INFINITY) {
+            assert!((x - 3.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, 0.0, -1.0)).squared_len() < 1e-18);
+        } else {
+            unreachable!()
+        }
+
+        assert!(cuboid.collision(&ray(Vec3::new(5.0, 3.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert!(cuboid.collision(&ray(Vec3::new(5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert_eq!(cuboid.bounding_box().max(), Vec3::new(1.0, 2.0, 3.0));
+
+        let intervals = cuboid.intervals(&ray(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)));
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].enter.distance + 3.0).abs() < 1e-9);
+        assert!((intervals[0].exit.distance - 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,304 @@
+use std::f64::consts::PI;
+
+use crate::geometry::{gamma, Aabb, Vec3};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::csg::{Interval, Solid};
+use crate::ray_tracing::scene::material::Material;
+
+/// Real roots of `a t^2 + 2 half_b t + c`, ascending.
+fn quadratic_roots(a: f64, half_b: f64, c: f64) -> impl Iterator<Item=f64> {
+    let roots = if a == 0.0 {
+        if half_b == 0.0 { [f64::NAN, f64::NAN] } else { [-c / (2.0 * half_b), f64::NAN] }
+    } else {
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            [f64::NAN, f64::NAN]
+        } else {
+            let (t0, t1) = ((-half_b - discriminant.sqrt()) / a, (-half_b + discriminant.sqrt()) / a);
+            [t0.min(t1), t0.max(t1)]
+        }
+    };
+    IntoIterator::into_iter(roots).filter(|t| !t.is_nan())
+}
+
+/// Azimuth around the y axis with the same convention as [`Sphere`](super::Sphere): `u` starts from -x.
+fn azimuth(local_position: Vec3<f64>) -> f64 {
+    ((-local_position.z()).atan2(*local_position.x()) + PI) / (2.0 * PI)
+}
+
+/// Unit radial direction from the y axis, and the distance to it.
+fn radial(local_position: Vec3<f64>) -> (Vec3<f64>, f64) {
+    let (x, z) = (*local_position.x(), *local_position.z());
+    let rho = (x * x + z * z).sqrt();
+    if rho <= 1e-12 {
+        (Vec3::new(1.0, 0.0, 0.0), rho)
+    } else {
+        (Vec3::new(x / rho, 0.0, z / rho), rho)
+    }
+}
+
+/// Direction of increasing `u` around the y axis at unit radius, scaled to one full turn.
+fn around(radial: Vec3<f64>) -> Vec3<f64> {
+    Vec3::new(*radial.z(), 0.0, -radial.x()) * (2.0 * PI)
+}
+
+/// Hit on a horizontal cap of radius `radius` at height `y` relative to `base`; `v` goes from the center to the rim.
+fn cap<'a>(ray: &Ray, base: Vec3<f64>, y: f64, radius: f64, up: bool, material: &'a Material) -> Option<SurfaceInteraction<'a>> {
+    let o = ray.initial - base;
+    let d = ray.direction.vec();
+    if *d.y() == 0.0 {
+        return None;
+    }
+    let x = (y - o.y()) / d.y();
+    let local_position = o + d * x;
+    let local_position = Vec3::new(*local_position.x(), y, *local_position.z());
+    let (direction, rho) = radial(local_position);
+    if rho > radius {
+        return None;
+    }
+    Some(SurfaceInteraction {
+        distance: x,
+        normal: Vec3::new(0.0, if up { 1.0 } else { -1.0 }, 0.0).normalize(),
+        front_face: (*d.y() < 0.0) == up,
+        uv: (azimuth(local_position), rho / radius),
+        position: base + local_position,
+        error: local_position.abs() * gamma(7) + (base + local_position).abs() * gamma(1),
+        local_position,
+        dpdu: around(direction) * rho,
+        dpdv: direction * radius,
+        dndu: Vec3::new(0.0, 0.0, 0.0),
+        dndv: Vec3::new(0.0, 0.0, 0.0),
+        shading: None,
+        primitive_id: 0,
+        material,
+    })
+}
+
+fn nearest(crossings: Vec<SurfaceInteraction<'_>>, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+    crossings.into_iter().filter(|hit| hit.distance > t_min && hit.distance < t_max).min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+/// Capped cylinder standing on `base` along +y. Use an [`Instance`](super::super::instance::Instance) for other orientations.
+#[derive(Clone, Debug)]
+pub struct Cylinder {
+    base: Vec3<f64>,
+    radius: f64,
+    height: f64,
+    material: Material,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3<f64>, radius: f64, height: f64, material: Material) -> Self {
+        Self { base, radius, height, material }
+    }
+
+    /// Every crossing of the surface along the ray's line.
+    fn crossings(&self, ray: &Ray) -> Vec<SurfaceInteraction<'_>> {
+        let mut crossings: Vec<_> = self.side(ray).collect();
+        crossings.extend(cap(ray, self.base, 0.0, self.radius, false, &self.material));
+        crossings.extend(cap(ray, self.base, self.height, self.radius, true, &self.material));
+        crossings
+    }
+
+    fn side<'a>(&'a self, ray: &Ray) -> impl Iterator<Item=SurfaceInteraction<'a>> + 'a {
+        let o = ray.initial - self.base;
+        let d = ray.direction.vec();
+        let a = d.x() * d.x() + d.z() * d.z();
+        let half_b = o.x() * d.x() + o.z() * d.z();
+        let c = o.x() * o.x() + o.z() * o.z() - self.radius * self.radius;
+        quadratic_roots(a, half_b, c).filter(move |&t| (0.0..=self.height).contains(&(o.y() + d.y() * t))).map(move |x| {
+            let (direction, _) = radial(o + d * x);
+            // Reprojecting radially onto the side keeps the error independent of the error in `x`.
+            let local_position = direction * self.radius + Vec3::new(0.0, *(o + d * x).y(), 0.0);
+            let position = self.base + local_position;
+            SurfaceInteraction {
+                distance: x,
+                normal: direction.normalize(),
+                front_face: direction.inner_product(d) < 0.0,
+                uv: (azimuth(local_position), local_position.y() / self.height),
+                position,
+                error: local_position.abs() * gamma(7) + position.abs() * gamma(1),
+                local_position,
+                dpdu: around(direction) * self.radius,
+                dpdv: Vec3::new(0.0, self.height, 0.0),
+                dndu: around(direction),
+                dndv: Vec3::new(0.0, 0.0, 0.0),
+                shading: None,
+                primitive_id: 0,
+                material: &self.material,
+            }
+        })
+    }
+}
+
+impl Collision for Cylinder {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        nearest(self.crossings(ray), t_min, t_max)
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.base - Vec3::new(self.radius, 0.0, self.radius),
+                  self.base + Vec3::new(self.radius, self.height, self.radius))
+    }
+}
+
+/// Cone with a capped base of radius `radius` at `base` and its apex `height` above it along +y.
+#[derive(Clone, Debug)]
+pub struct Cone {
+    base: Vec3<f64>,
+    radius: f64,
+    height: f64,
+    material: Material,
+}
+
+impl Solid for Cylinder {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        Interval::pair(self.crossings(ray))
+    }
+}
+
+impl Cone {
+    pub fn new(base: Vec3<f64>, radius: f64, height: f64, material: Material) -> Self {
+        Self { base, radius, height, material }
+    }
+
+    /// Every crossing of the surface along the ray's line.
+    fn crossings(&self, ray: &Ray) -> Vec<SurfaceInteraction<'_>> {
+        let mut crossings: Vec<_> = self.side(ray).collect();
+        crossings.extend(cap(ray, self.base, 0.0, self.radius, false, &self.material));
+        crossings
+    }
+
+    fn side<'a>(&'a self, ray: &Ray) -> impl Iterator<Item=SurfaceInteraction<'a>> + 'a {
+        let o = ray.initial - self.base;
+        let d = ray.direction.vec();
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let h = self.height - o.y();
+        let a = d.x() * d.x() + d.z() * d.z() - k2 * d.y() * d.y();
+        let half_b = o.x() * d.x() + o.z() * d.z() + k2 * h * d.y();
+        let c = o.x() * o.x() + o.z() * o.z() - k2 * h * h;
+        let slope = (1.0 + k2).sqrt();
+        quadratic_roots(a, half_b, c).filter(move |&t| (0.0..=self.height).contains(&(o.y() + d.y() * t))).map(move |x| {
+            let local_position = o + d * x;
+            let (direction, rho) = radial(local_position);
+            let normal = direction + Vec3::new(0.0, k, 0.0);
+            SurfaceInteraction {
+                distance: x,
+                normal: normal.normalize(),
+                front_face: normal.inner_product(d) < 0.0,
+                uv: (azimuth(local_position), local_position.y() / self.height),
+                position: self.base + local_position,
+                error: (o.abs() + (d * x).abs()) * gamma(7) + (self.base + local_position).abs() * gamma(1),
+                local_position,
+                dpdu: around(direction) * rho,
+                dpdv: Vec3::new(-direction.x() * self.radius, self.height, -direction.z() * self.radius),
+                dndu: around(direction) / slope,
+                dndv: Vec3::new(0.0, 0.0, 0.0),
+                shading: None,
+                primitive_id: 0,
+                material: &self.material,
+            }
+        })
+    }
+}
+
+impl Collision for Cone {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        nearest(self.crossings(ray), t_min, t_max)
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.base - Vec3::new(self.radius, 0.0, self.radius),
+                  self.base + Vec3::new(self.radius, self.height, self.radius))
+    }
+}
+
+impl Solid for Cone {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        Interval::pair(self.crossings(ray))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+    use crate::ray_tracing::scene::csg::Solid;
+    use crate::ray_tracing::scene::object::cylinder::{Cone, Cylinder};
+    use crate::ray_tracing::scene::object::test_util::{material, ray};
+
+    #[test]
+    fn cylinder_collision_test() {
+        let cylinder = Cylinder::new(Vec3::new(0.0, -1.0, 0.0), 1.0, 2.0, material());
+        if let Some(SurfaceInteraction { distance: x, normal, uv: (_, v), .. }) = cylinder.collision(&ray(Vec3::new(3.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 2.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(1.0, 0.0, 0.0)).squared_len() < 1e-18);
+            assert!((v - 0.75).abs() < 1e-9);
+        } else {
+            unreachable!()
+        }
+
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = cylinder.collision(&ray(Vec3::new(0.5, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 2.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, 1.0, 0.0)).squared_len() < 1e-18);
+        } else {
+            unreachable!()
+        }
+
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = cylinder.collision(&ray(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 1.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, -1.0, 0.0)).squared_len() < 1e-18);
+        } else {
+            unreachable!()
+        }
+
+        assert!(cylinder.collision(&ray(Vec3::new(3.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert!(cylinder.collision(&ray(Vec3::new(0.0, 3.0, 1.5), Vec3::new(0.0, -1.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert_eq!(cylinder.bounding_box().min(), Vec3::new(-1.0, -1.0, -1.0));
+        assert_eq!(cylinder.bounding_box().max(), Vec3::new(1.0, 1.0, 1.0));
+
+        // Along the axis, the line enters through the bottom cap and leaves through the top.
+        let intervals = cylinder.intervals(&ray(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)));
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].enter.distance + 1.0).abs() < 1e-9);
+        assert!((intervals[0].exit.distance - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cone_collision_test() {
+        let cone = Cone::new(Vec3::new(0.0, 0.0, 0.0), 1.0, 1.0, material());
+        if let Some(SurfaceInteraction { distance: x, normal, uv: (_, v), .. }) = cone.collision(&ray(Vec3::new(3.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 2.5).abs() < 1e-9);
+            let expected = Vec3::new(1.0, 1.0, 0.0).normalize().vec();
+            assert!((normal.vec() - expected).squared_len() < 1e-18);
+            assert!((v - 0.5).abs() < 1e-9);
+        } else {
+            unreachable!()
+        }
+
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = cone.collision(&ray(Vec3::new(0.5, -2.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 2.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, -1.0, 0.0)).squared_len() < 1e-18);
+        } else {
+            unreachable!()
+        }
+
+        // Rays aimed above the apex miss, even though they cross the mirrored nappe of the double cone.
+        assert!(cone.collision(&ray(Vec3::new(3.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert_eq!(cone.bounding_box().max(), Vec3::new(1.0, 1.0, 1.0));
+
+        let intervals = cone.intervals(&ray(Vec3::new(3.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)));
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].exit.distance - 3.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,274 @@
+use std::f64::consts::PI;
+
+use crate::geometry::{gamma, Aabb, Frame, NormalizedVec3, Vec3};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::material::Material;
+
+/// Distance along `ray` to the plane through `point` with normal `normal` if it is within `(t_min, t_max)`,
+/// with the hit position reprojected onto the plane and its error bound.
+fn plane_hit(ray: &Ray, t_min: f64, t_max: f64, point: Vec3<f64>, normal: Vec3<f64>) -> Option<(f64, Vec3<f64>, Vec3<f64>)> {
+    let denominator = normal.inner_product(ray.direction.vec());
+    if denominator == 0.0 {
+        return None;
+    }
+    let x = normal.inner_product(point - ray.initial) / denominator;
+    if x <= t_min || x >= t_max {
+        return None;
+    }
+    let position = ray.initial + ray.direction.vec() * x;
+    let position = position - normal * (normal.inner_product(position - point) / normal.squared_len());
+    Some((x, position, (position.abs() + point.abs()) * gamma(7)))
+}
+
+/// Infinite plane. `u` and `v` repeat with period 1 along the tangent and bitangent of the normal's frame.
+#[derive(Clone, Debug)]
+pub struct Plane {
+    point: Vec3<f64>,
+    frame: Frame,
+    material: Material,
+}
+
+impl Plane {
+    pub fn new(point: Vec3<f64>, normal: NormalizedVec3<f64>, material: Material) -> Self {
+        Self { point, frame: Frame::from_normal(normal), material }
+    }
+}
+
+impl Collision for Plane {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        let (x, position, error) = plane_hit(ray, t_min, t_max, self.point, self.frame.normal())?;
+        let local_position = self.frame.to_local(position - self.point);
+        Some(SurfaceInteraction {
+            distance: x,
+            normal: self.frame.normal().normalize(),
+            front_face: self.frame.normal().inner_product(ray.direction.vec()) < 0.0,
+            uv: (local_position.x().rem_euclid(1.0), local_position.y().rem_euclid(1.0)),
+            position,
+            error,
+            local_position,
+            dpdu: self.frame.tangent(),
+            dpdv: self.frame.bitangent(),
+            dndu: Vec3::new(0.0, 0.0, 0.0),
+            dndv: Vec3::new(0.0, 0.0, 0.0),
+            shading: None,
+            primitive_id: 0,
+            material: &self.material,
+        })
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::infinite()
+    }
+}
+
+/// Parallelogram spanned by `edge_u` and `edge_v` from `corner`, facing along `edge_u × edge_v`.
+#[derive(Clone, Debug)]
+pub struct Rectangle {
+    corner: Vec3<f64>,
+    edge_u: Vec3<f64>,
+    edge_v: Vec3<f64>,
+    normal: Vec3<f64>,
+    material: Material,
+}
+
+impl Rectangle {
+    pub fn new(corner: Vec3<f64>, edge_u: Vec3<f64>, edge_v: Vec3<f64>, material: Material) -> Self {
+        let normal = edge_u.outer_product(edge_v);
+        assert!(normal.squared_len() > 0.0, "rectangle edges must not be parallel");
+        Self { corner, edge_u, edge_v, normal, material }
+    }
+}
+
+impl Collision for Rectangle {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        let (x, position, error) = plane_hit(ray, t_min, t_max, self.corner, self.normal)?;
+        let w = position - self.corner;
+        let area = self.normal.squared_len();
+        let u = self.normal.inner_product(w.outer_product(self.edge_v)) / area;
+        let v = self.normal.inner_product(self.edge_u.outer_product(w)) / area;
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some(SurfaceInteraction {
+            distance: x,
+            normal: self.normal.normalize(),
+            front_face: self.normal.inner_product(ray.direction.vec()) < 0.0,
+            uv: (u, v),
+            position,
+            error,
+            local_position: Vec3::new(u, v, 0.0),
+            dpdu: self.edge_u,
+            dpdv: self.edge_v,
+            dndu: Vec3::new(0.0, 0.0, 0.0),
+            dndv: Vec3::new(0.0, 0.0, 0.0),
+            shading: None,
+            primitive_id: 0,
+            material: &self.material,
+        })
+    }
+
+    fn sample_area(&self, u: (f64, f64), time: f64) -> Option<(SurfaceInteraction<'_>, f64)> {
+        let point = self.corner + self.edge_u * u.0 + self.edge_v * u.1;
+        let normal = self.normal.normalize().vec();
+        let ray = Ray::new(point + normal, (-normal).normalize(), time);
+        let hit = self.collision(&ray, 0.0, f64::INFINITY)?;
+        Some((SurfaceInteraction { distance: 0.0, ..hit }, self.normal.squared_len().sqrt()))
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let corners = [self.corner, self.corner + self.edge_u, self.corner + self.edge_v, self.corner + self.edge_u + self.edge_v];
+        corners.iter().fold(Aabb::empty(), |aabb, &corner| aabb.union(&Aabb::new(corner, corner)))
+    }
+}
+
+/// Flat disk. `u` goes around the center starting from the frame's tangent, `v` goes from the center to the rim.
+#[derive(Clone, Debug)]
+pub struct Disk {
+    center: Vec3<f64>,
+    frame: Frame,
+    radius: f64,
+    material: Material,
+}
+
+impl Disk {
+    pub fn new(center: Vec3<f64>, normal: NormalizedVec3<f64>, radius: f64, material: Material) -> Self {
+        Self { center, frame: Frame::from_normal(normal), radius, material }
+    }
+}
+
+impl Collision for Disk {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        let (x, position, error) = plane_hit(ray, t_min, t_max, self.center, self.frame.normal())?;
+        let local_position = self.frame.to_local(position - self.center);
+        let (a, b) = (*local_position.x(), *local_position.y());
+        let r = (a * a + b * b).sqrt();
+        if r > self.radius {
+            return None;
+        }
+        let phi = b.atan2(a).rem_euclid(2.0 * PI);
+        let radial = self.frame.tangent() * phi.cos() + self.frame.bitangent() * phi.sin();
+        let around = self.frame.bitangent() * phi.cos() - self.frame.tangent() * phi.sin();
+        Some(SurfaceInteraction {
+            distance: x,
+            normal: self.frame.normal().normalize(),
+            front_face: self.frame.normal().inner_product(ray.direction.vec()) < 0.0,
+            uv: (phi / (2.0 * PI), r / self.radius),
+            position,
+            error,
+            local_position,
+            dpdu: around * (2.0 * PI * r),
+            dpdv: radial * self.radius,
+            dndu: Vec3::new(0.0, 0.0, 0.0),
+            dndv: Vec3::new(0.0, 0.0, 0.0),
+            shading: None,
+            primitive_id: 0,
+            material: &self.material,
+        })
+    }
+
+    fn sample_area(&self, u: (f64, f64), time: f64) -> Option<(SurfaceInteraction<'_>, f64)> {
+        // Slightly inside the rim, so the probe ray below cannot miss by rounding.
+        let r = self.radius * u.0.sqrt() * (1.0 - 1e-9);
+        let phi = 2.0 * PI * u.1;
+        let point = self.center + self.frame.to_world(Vec3::new(r * phi.cos(), r * phi.sin(), 0.0));
+        let ray = Ray::new(point + self.frame.normal(), (-self.frame.normal()).normalize(), time);
+        let hit = self.collision(&ray, 0.0, f64::INFINITY)?;
+        Some((SurfaceInteraction { distance: 0.0, ..hit }, PI * self.radius * self.radius))
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let n = self.frame.normal();
+        let extent = |c: f64| self.radius * (1.0 - c * c).max(0.0).sqrt();
+        let r = Vec3::new(extent(*n.x()), extent(*n.y()), extent(*n.z()));
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+    use crate::ray_tracing::scene::object::plane::{Disk, Plane, Rectangle};
+    use crate::ray_tracing::scene::object::test_util::{material, ray};
+
+    #[test]
+    fn plane_collision_test() {
+        let plane = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0).normalize(), material());
+        if let Some(SurfaceInteraction { distance: x, normal, uv: (u, v), .. }) = plane.collision(&ray(Vec3::new(0.25, 1.0, 0.5), Vec3::new(0.0, -1.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 2.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, 1.0, 0.0)).squared_len() < 1e-18);
+            assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+        } else {
+            unreachable!()
+        }
+        assert!(plane.collision(&ray(Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert!(plane.collision(&ray(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert!(!plane.bounding_box().max().x().is_finite());
+    }
+
+    #[test]
+    fn rectangle_collision_test() {
+        let rectangle = Rectangle::new(Vec3::new(-1.0, -1.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 4.0, 0.0), material());
+        if let Some(SurfaceInteraction { distance: x, normal, uv: (u, v), .. }) = rectangle.collision(&ray(Vec3::new(0.5, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::INFINITY) {
+            assert!((x - 3.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, 0.0, 1.0)).squared_len() < 1e-18);
+            assert!((u - 0.75).abs() < 1e-9);
+            assert!((v - 0.25).abs() < 1e-9);
+        } else {
+            unreachable!()
+        }
+        assert!(rectangle.collision(&ray(Vec3::new(1.5, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::INFINITY).is_none());
+        let aabb = rectangle.bounding_box();
+        assert_eq!(aabb.min(), Vec3::new(-1.0, -1.0, 0.0));
+        assert_eq!(aabb.max(), Vec3::new(1.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn disk_collision_test() {
+        let disk = Disk::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0).normalize(), 0.5, material());
+        if let Some(SurfaceInteraction { distance: x, normal, uv: (_, v), .. }) = disk.collision(&ray(Vec3::new(0.25, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 1.0).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, -1.0, 0.0)).squared_len() < 1e-18);
+            assert!((v - 0.5).abs() < 1e-9);
+        } else {
+            unreachable!()
+        }
+        assert!(disk.collision(&ray(Vec3::new(0.0, 0.0, 0.6), Vec3::new(0.0, 1.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        let aabb = disk.bounding_box();
+        assert!((aabb.min() - Vec3::new(-0.5, 1.0, -0.5)).squared_len() < 1e-18);
+        assert!((aabb.max() - Vec3::new(0.5, 1.0, 0.5)).squared_len() < 1e-18);
+    }
+
+    #[test]
+    fn sample_area_test() {
+        let rectangle = Rectangle::new(Vec3::new(-1.0, -1.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 4.0, 0.0), material());
+        let (point, area) = rectangle.sample_area((0.75, 0.25), 0.0).unwrap();
+        assert!((area - 8.0).abs() < 1e-9);
+        assert!((point.position - Vec3::new(0.5, 0.0, 0.0)).squared_len() < 1e-18);
+        assert_eq!(point.distance, 0.0);
+
+        let disk = Disk::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0).normalize(), 0.5, material());
+        for u in [(0.0, 0.0), (0.3, 0.6), (1.0, 0.99)] {
+            let (point, area) = disk.sample_area(u, 0.0).unwrap();
+            assert!((area - std::f64::consts::PI * 0.25).abs() < 1e-9);
+            assert!((point.uv.1 - u.0.sqrt()).abs() < 1e-6, "{:?}", point.uv);
+            assert!((point.position.y() - 1.0).abs() < 1e-12);
+        }
+
+        let plane = Plane::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0).normalize(), material());
+        assert!(plane.sample_area((0.5, 0.5), 0.0).is_none());
+    }
+}
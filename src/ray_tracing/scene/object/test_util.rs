@@ -0,0 +1,14 @@
+//! Fixtures shared by the scene tests.
+
+use crate::geometry::Vec3;
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::material::{Color, Material};
+
+/// Black, non-emissive material for tests that only look at geometry.
+pub fn material() -> Material {
+    Material::Solid { color: Color::zero().into(), illuminate: Color::zero().into() }
+}
+
+pub fn ray(initial: Vec3<f64>, direction: Vec3<f64>) -> Ray {
+    Ray { initial, direction: direction.normalize(), differential: None, time: 0.0 }
+}
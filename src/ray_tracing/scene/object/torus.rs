@@ -0,0 +1,201 @@
+use std::f64::consts::PI;
+
+use crate::geometry::{gamma, Aabb, Vec3};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::csg::{Interval, Solid};
+use crate::ray_tracing::scene::material::Material;
+
+/// Evaluates the polynomial with coefficients in increasing order of degree.
+fn evaluate(coefficients: &[f64], t: f64) -> f64 {
+    coefficients.iter().rev().fold(0.0, |sum, &c| sum * t + c)
+}
+
+/// Real roots in `[lo, hi]`, ascending. The roots of the derivative split the interval into monotone pieces,
+/// each of which holds at most one root that bisection finds reliably, unlike the closed-form quartic solution.
+fn roots(coefficients: &[f64], lo: f64, hi: f64) -> Vec<f64> {
+    if coefficients.len() <= 1 {
+        return Vec::new();
+    }
+    let derivative: Vec<_> = coefficients.iter().enumerate().skip(1).map(|(i, &c)| c * i as f64).collect();
+    let mut bounds = vec![lo];
+    bounds.extend(roots(&derivative, lo, hi));
+    bounds.push(hi);
+    bounds.windows(2).filter_map(|w| {
+        let (mut a, mut b) = (w[0], w[1]);
+        let (fa, fb) = (evaluate(coefficients, a), evaluate(coefficients, b));
+        if fa == 0.0 {
+            return Some(a);
+        }
+        if fa.signum() == fb.signum() {
+            return None;
+        }
+        for _ in 0..100 {
+            let m = 0.5 * (a + b);
+            if m <= a || m >= b {
+                break;
+            }
+            if evaluate(coefficients, m).signum() == fa.signum() { a = m } else { b = m }
+        }
+        Some(0.5 * (a + b))
+    }).collect()
+}
+
+/// Torus around the y axis through `center`. `u` goes around the y axis like [`Sphere`](super::Sphere),
+/// `v` goes around the tube starting from its outer equator.
+#[derive(Clone, Debug)]
+pub struct Torus {
+    center: Vec3<f64>,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Material,
+}
+
+impl Torus {
+    pub fn new(center: Vec3<f64>, major_radius: f64, minor_radius: f64, material: Material) -> Self {
+        Self { center, major_radius, minor_radius, material }
+    }
+
+    /// Every crossing of the surface along the ray's line, ascending.
+    fn crossings(&self, ray: &Ray) -> Vec<SurfaceInteraction<'_>> {
+        let d = ray.direction.vec();
+        let (big, small) = (self.major_radius, self.minor_radius);
+        // Start from the bounding sphere so the polynomial is well conditioned regardless of the ray origin.
+        let o = ray.initial - self.center;
+        let bound = big + small;
+        let half_b = o.inner_product(d);
+        let discriminant = half_b * half_b - (o.squared_len() - bound * bound);
+        if discriminant < 0.0 {
+            return Vec::new();
+        }
+        let (near, far) = (-half_b - discriminant.sqrt(), -half_b + discriminant.sqrt());
+        let o = o + d * near;
+
+        let m = o.squared_len();
+        let n = o.inner_product(d);
+        let k = m + big * big - small * small;
+        let (dxz, oxz, odxz) = (d.x() * d.x() + d.z() * d.z(), o.x() * o.x() + o.z() * o.z(), o.x() * d.x() + o.z() * d.z());
+        let coefficients = [
+            k * k - 4.0 * big * big * oxz,
+            4.0 * n * k - 8.0 * big * big * odxz,
+            4.0 * n * n + 2.0 * k - 4.0 * big * big * dxz,
+            4.0 * n,
+            1.0,
+        ];
+        roots(&coefficients, 0.0, far - near).into_iter().map(|t| self.hit(ray, t + near)).collect()
+    }
+
+    fn hit(&self, ray: &Ray, x: f64) -> SurfaceInteraction<'_> {
+        let d = ray.direction.vec();
+        let (big, small) = (self.major_radius, self.minor_radius);
+        let estimate = ray.initial - self.center + d * x;
+        let (px, pz) = (*estimate.x(), *estimate.z());
+        let rho = (px * px + pz * pz).sqrt().max(1e-12);
+        let radial = Vec3::new(px / rho, 0.0, pz / rho);
+        let theta = estimate.y().atan2(rho - big);
+        let normal = radial * theta.cos() + Vec3::new(0.0, theta.sin(), 0.0);
+        // Reprojecting onto the tube bounds the error independently of the root's accuracy.
+        let local_position = radial * big + normal * small;
+        let rho = big + small * theta.cos();
+        let position = self.center + local_position;
+        let around = Vec3::new(*radial.z(), 0.0, -radial.x()) * (2.0 * PI);
+        let tube = (Vec3::new(0.0, theta.cos(), 0.0) - radial * theta.sin()) * (2.0 * PI);
+        SurfaceInteraction {
+            distance: x,
+            normal: normal.normalize(),
+            front_face: normal.inner_product(d) < 0.0,
+            uv: (((-pz).atan2(px) + PI) / (2.0 * PI), theta.rem_euclid(2.0 * PI) / (2.0 * PI)),
+            position,
+            error: local_position.abs() * gamma(9) + position.abs() * gamma(1),
+            local_position,
+            dpdu: around * rho,
+            dpdv: tube * small,
+            dndu: around * theta.cos(),
+            dndv: tube,
+            shading: None,
+            primitive_id: 0,
+            material: &self.material,
+        }
+    }
+}
+
+impl Collision for Torus {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        self.crossings(ray).into_iter().find(|hit| hit.distance > t_min && hit.distance < t_max)
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.major_radius + self.minor_radius, self.minor_radius, self.major_radius + self.minor_radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+impl Solid for Torus {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        Interval::pair(self.crossings(ray))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+    use crate::ray_tracing::scene::csg::Solid;
+    use crate::ray_tracing::scene::object::torus::{roots, Torus};
+    use crate::ray_tracing::scene::object::test_util::{material, ray};
+
+    #[test]
+    fn roots_test() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        let found = roots(&[24.0, -50.0, 35.0, -10.0, 1.0], -10.0, 10.0);
+        assert_eq!(found.len(), 4);
+        for (root, expected) in found.iter().zip([1.0, 2.0, 3.0, 4.0]) {
+            assert!((root - expected).abs() < 1e-9);
+        }
+        assert!(roots(&[1.0, 0.0, 1.0], -10.0, 10.0).is_empty());
+    }
+
+    #[test]
+    fn torus_collision_test() {
+        let torus = Torus::new(Vec3::new(0.0, 0.0, 0.0), 2.0, 0.5, material());
+        if let Some(SurfaceInteraction { distance: x, normal, uv: (_, v), .. }) = torus.collision(&ray(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 2.5).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(1.0, 0.0, 0.0)).squared_len() < 1e-12);
+            assert!(v.abs() < 1e-9 || (v - 1.0).abs() < 1e-9);
+        } else {
+            unreachable!()
+        }
+
+        // Straight down through the tube.
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = torus.collision(&ray(Vec3::new(0.0, 3.0, 2.0), Vec3::new(0.0, -1.0, 0.0)), 0.0, f64::INFINITY) {
+            assert!((x - 2.5).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, 1.0, 0.0)).squared_len() < 1e-12);
+        } else {
+            unreachable!()
+        }
+
+        // From the hole's center, the inner equator is hit.
+        if let Some(SurfaceInteraction { distance: x, normal, .. }) = torus.collision(&ray(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)), 0.0, f64::INFINITY) {
+            assert!((x - 1.5).abs() < 1e-9);
+            assert!((normal.vec() - Vec3::new(0.0, 0.0, -1.0)).squared_len() < 1e-12);
+        } else {
+            unreachable!()
+        }
+
+        assert!(torus.collision(&ray(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert!(torus.collision(&ray(Vec3::new(5.0, 0.6, 0.0), Vec3::new(-1.0, 0.0, 0.0)), 0.0, f64::INFINITY).is_none());
+        assert_eq!(torus.bounding_box().max(), Vec3::new(2.5, 0.5, 2.5));
+
+        // Across the hole, the line passes through the tube twice.
+        let intervals = torus.intervals(&ray(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)));
+        assert_eq!(intervals.len(), 2);
+        for (interval, (enter, exit)) in intervals.iter().zip([(2.5, 3.5), (6.5, 7.5)]) {
+            assert!((interval.enter.distance - enter).abs() < 1e-9);
+            assert!((interval.exit.distance - exit).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,503 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use image::ImageResult;
+
+use crate::geometry::Vec3;
+use crate::ray_tracing::color_space::{ColorSpace, TransferFunction};
+use crate::ray_tracing::scene::material::Color;
+use crate::ray_tracing::scene::texture::procedural::{checker, ColorRamp, Pattern, TextureSpace};
+
+pub mod noise;
+pub mod procedural;
+
+/// Where a texture is looked up: the surface parameterization and the hit position in world and object space.
+#[derive(Debug, Clone, Copy)]
+pub struct TextureCoordinate {
+    pub uv: (f64, f64),
+    /// Change of `uv` towards the neighbouring pixels, zero when the footprint is unknown.
+    pub duv_dx: (f64, f64),
+    pub duv_dy: (f64, f64),
+    pub position: Vec3<f64>,
+    pub local_position: Vec3<f64>,
+}
+
+impl TextureCoordinate {
+    fn point(&self, space: TextureSpace) -> Vec3<f64> {
+        match space {
+            TextureSpace::Uv => Vec3::new(self.uv.0, self.uv.1, 0.0),
+            TextureSpace::Object => self.local_position,
+            TextureSpace::World => self.position,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Constant(Color),
+    Image(Arc<ImageTexture>),
+    /// Alternates between two textures on a lattice of `scale` cells per unit; planar in [`TextureSpace::Uv`].
+    Checker { even: Box<Texture>, odd: Box<Texture>, space: TextureSpace, scale: f64 },
+    Procedural { pattern: Pattern, ramp: ColorRamp, space: TextureSpace, scale: f64 },
+}
+
+impl Texture {
+    pub fn evaluate(&self, coordinate: &TextureCoordinate) -> Color {
+        match self {
+            Texture::Constant(color) => color.clone(),
+            Texture::Image(image) => image.sample_filtered(coordinate.uv, coordinate.duv_dx, coordinate.duv_dy),
+            Texture::Checker { even, odd, space, scale } => {
+                if checker(coordinate.point(*space) * *scale, *space == TextureSpace::Uv) {
+                    even.evaluate(coordinate)
+                } else {
+                    odd.evaluate(coordinate)
+                }
+            }
+            Texture::Procedural { pattern, ramp, space, scale } => ramp.evaluate(pattern.evaluate(coordinate.point(*space) * *scale)),
+        }
+    }
+
+    /// Scalar parameters such as roughness are read from the red channel.
+    pub fn evaluate_scalar(&self, coordinate: &TextureCoordinate) -> f64 {
+        self.evaluate(coordinate).r
+    }
+}
+
+impl From<Color> for Texture {
+    fn from(color: Color) -> Self {
+        Texture::Constant(color)
+    }
+}
+
+impl From<ImageTexture> for Texture {
+    fn from(image: ImageTexture) -> Self {
+        Texture::Image(Arc::new(image))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+impl WrapMode {
+    fn apply(self, i: isize, size: usize) -> usize {
+        let size = size as isize;
+        match self {
+            WrapMode::Repeat => i.rem_euclid(size) as usize,
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(size * 2);
+                (if i < size { i } else { size * 2 - 1 - i }) as usize
+            }
+            WrapMode::Clamp => i.clamp(0, size - 1) as usize,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    /// Bilinear lookups in the two MIP levels closest to the footprint size.
+    Trilinear,
+    /// Elliptically weighted average over the anisotropic footprint.
+    Ewa,
+}
+
+/// How 8-bit texel values are decoded into linear values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorEncoding {
+    Linear,
+    Srgb,
+}
+
+impl ColorEncoding {
+    pub fn decode(self, value: u8) -> f64 {
+        let value = value as f64 / 255.0;
+        match self {
+            ColorEncoding::Linear => value,
+            ColorEncoding::Srgb => TransferFunction::Srgb.decode(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl MipLevel {
+    /// Box-filters 2x2 blocks, repeating the last row or column of odd-sized levels.
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Color::zero();
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (x * 2 + dx).min(self.width - 1);
+                    let sy = (y * 2 + dy).min(self.height - 1);
+                    sum = sum + self.pixels[sy * self.width + sx].clone();
+                }
+                pixels.push(Color { r: sum.r / 4.0, g: sum.g / 4.0, b: sum.b / 4.0 });
+            }
+        }
+        Self { width, height, pixels }
+    }
+}
+
+/// Texture backed by linear texels and their MIP pyramid. `v = 0` is the bottom row of the image.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    levels: Vec<MipLevel>,
+    wrap: WrapMode,
+    filter: Filter,
+}
+
+impl ImageTexture {
+    const MAX_ANISOTROPY: f64 = 8.0;
+    const EWA_ALPHA: f64 = 2.0;
+
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        assert!(width > 0 && height > 0);
+        let mut levels = vec![MipLevel { width, height, pixels }];
+        while let Some(last) = levels.last().filter(|level| level.width > 1 || level.height > 1) {
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Self { levels, wrap: WrapMode::Repeat, filter: Filter::Trilinear }
+    }
+
+    pub fn open(path: impl AsRef<Path>, encoding: ColorEncoding) -> ImageResult<Self> {
+        let image = image::open(path)?.to_rgb8();
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels()
+            .map(|pixel| Color { r: encoding.decode(pixel[0]), g: encoding.decode(pixel[1]), b: encoding.decode(pixel[2]) })
+            .collect();
+        Ok(Self::new(width as usize, height as usize, pixels))
+    }
+
+    /// Converts texels from the primaries of `source`, sRGB for most images, to those of the working space `target`.
+    pub fn with_color_space(self, source: ColorSpace, target: ColorSpace) -> Self {
+        let levels = self.levels.into_iter().map(|level| MipLevel {
+            pixels: level.pixels.iter().map(|pixel| source.convert(pixel, target)).collect(),
+            ..level
+        }).collect();
+        Self { levels, ..self }
+    }
+
+    pub fn with_wrap(self, wrap: WrapMode) -> Self {
+        Self { wrap, ..self }
+    }
+
+    pub fn with_filter(self, filter: Filter) -> Self {
+        Self { filter, ..self }
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn texel(&self, level: usize, x: isize, y: isize) -> &Color {
+        let level = &self.levels[level];
+        let x = self.wrap.apply(x, level.width);
+        let y = self.wrap.apply(y, level.height);
+        &level.pixels[y * level.width + x]
+    }
+
+    /// Point lookup without footprint information.
+    pub fn sample(&self, uv: (f64, f64)) -> Color {
+        self.sample_filtered(uv, (0.0, 0.0), (0.0, 0.0))
+    }
+
+    pub fn sample_filtered(&self, uv: (f64, f64), duv_dx: (f64, f64), duv_dy: (f64, f64)) -> Color {
+        match self.filter {
+            Filter::Nearest => {
+                let (x, y) = self.raster(0, uv);
+                self.texel(0, x.floor() as isize, y.floor() as isize).clone()
+            }
+            Filter::Bilinear => self.bilinear(0, uv),
+            Filter::Trilinear => {
+                let width = [duv_dx.0, duv_dy.0].iter().map(|du| du.abs() * self.width() as f64)
+                    .chain([duv_dx.1, duv_dy.1].iter().map(|dv| dv.abs() * self.height() as f64))
+                    .fold(0.0, f64::max);
+                self.between_levels(width, |level| self.bilinear(level, uv))
+            }
+            Filter::Ewa => self.ewa(uv, duv_dx, duv_dy),
+        }
+    }
+
+    fn raster(&self, level: usize, (u, v): (f64, f64)) -> (f64, f64) {
+        let level = &self.levels[level];
+        (u * level.width as f64, (1.0 - v) * level.height as f64)
+    }
+
+    /// Blends `lookup` between the two levels whose texel size brackets `width` level-0 texels.
+    fn between_levels(&self, width: f64, lookup: impl Fn(usize) -> Color) -> Color {
+        let last = self.levels.len() - 1;
+        let level = width.max(1e-8).log2();
+        if level <= 0.0 {
+            return lookup(0);
+        }
+        if level >= last as f64 {
+            return lookup(last);
+        }
+        let base = level.floor() as usize;
+        let w = level - base as f64;
+        let c0 = lookup(base);
+        let c1 = lookup(base + 1);
+        Color {
+            r: c0.r * (1.0 - w) + c1.r * w,
+            g: c0.g * (1.0 - w) + c1.g * w,
+            b: c0.b * (1.0 - w) + c1.b * w,
+        }
+    }
+
+    fn bilinear(&self, level: usize, uv: (f64, f64)) -> Color {
+        let (x, y) = self.raster(level, uv);
+        let x = x - 0.5;
+        let y = y - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let dx = x - x0;
+        let dy = y - y0;
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let c00 = self.texel(level, x0, y0);
+        let c10 = self.texel(level, x0 + 1, y0);
+        let c01 = self.texel(level, x0, y0 + 1);
+        let c11 = self.texel(level, x0 + 1, y0 + 1);
+        let w00 = (1.0 - dx) * (1.0 - dy);
+        let w10 = dx * (1.0 - dy);
+        let w01 = (1.0 - dx) * dy;
+        let w11 = dx * dy;
+        Color {
+            r: c00.r * w00 + c10.r * w10 + c01.r * w01 + c11.r * w11,
+            g: c00.g * w00 + c10.g * w10 + c01.g * w01 + c11.g * w11,
+            b: c00.b * w00 + c10.b * w10 + c01.b * w01 + c11.b * w11,
+        }
+    }
+
+    /// EWA as in pbrt: pick levels from the minor axis, clamping eccentricity to `MAX_ANISOTROPY`.
+    fn ewa(&self, uv: (f64, f64), duv_dx: (f64, f64), duv_dy: (f64, f64)) -> Color {
+        let length = |(du, dv): (f64, f64)| (du * du + dv * dv).sqrt();
+        let (major, mut minor) = if length(duv_dx) >= length(duv_dy) { (duv_dx, duv_dy) } else { (duv_dy, duv_dx) };
+        let major_length = length(major);
+        let minor_length = length(minor);
+        if minor_length == 0.0 {
+            return self.bilinear(0, uv);
+        }
+        if minor_length * Self::MAX_ANISOTROPY < major_length {
+            let scale = major_length / (minor_length * Self::MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+        }
+        let width = length(minor) * self.width().max(self.height()) as f64;
+        self.between_levels(width, |level| self.ewa_level(level, uv, major, minor))
+    }
+
+    fn ewa_level(&self, level: usize, uv: (f64, f64), axis0: (f64, f64), axis1: (f64, f64)) -> Color {
+        let (s, t) = self.raster(level, uv);
+        let (s, t) = (s - 0.5, t - 0.5);
+        let (width, height) = (self.levels[level].width as f64, self.levels[level].height as f64);
+        // Raster t grows downwards, so v derivatives change sign.
+        let (ds0, dt0) = (axis0.0 * width, -axis0.1 * height);
+        let (ds1, dt1) = (axis1.0 * width, -axis1.1 * height);
+        let a = dt0 * dt0 + dt1 * dt1 + 1.0;
+        let b = -2.0 * (ds0 * dt0 + ds1 * dt1);
+        let c = ds0 * ds0 + ds1 * ds1 + 1.0;
+        let inverse_f = 1.0 / (a * c - b * b * 0.25);
+        let (a, b, c) = (a * inverse_f, b * inverse_f, c * inverse_f);
+        let det = -b * b + 4.0 * a * c;
+        let inverse_det = 1.0 / det;
+        let s_extent = 2.0 * inverse_det * (det * c).sqrt();
+        let t_extent = 2.0 * inverse_det * (det * a).sqrt();
+        let (s0, s1) = ((s - s_extent).ceil() as isize, (s + s_extent).floor() as isize);
+        let (t0, t1) = ((t - t_extent).ceil() as isize, (t + t_extent).floor() as isize);
+        let mut sum = Color::zero();
+        let mut weight_sum = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f64 - t;
+            for is in s0..=s1 {
+                let ss = is as f64 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let weight = (-Self::EWA_ALPHA * r2).exp() - (-Self::EWA_ALPHA).exp();
+                    let texel = self.texel(level, is, it);
+                    sum = sum + Color { r: texel.r * weight, g: texel.g * weight, b: texel.b * weight };
+                    weight_sum += weight;
+                }
+            }
+        }
+        if weight_sum <= 0.0 {
+            return self.bilinear(level, uv);
+        }
+        Color { r: sum.r / weight_sum, g: sum.g / weight_sum, b: sum.b / weight_sum }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::material::Color;
+    use crate::ray_tracing::color_space::ColorSpace;
+    use crate::ray_tracing::scene::texture::{ColorEncoding, Filter, ImageTexture, Texture, TextureCoordinate, WrapMode};
+    use crate::ray_tracing::scene::texture::procedural::{ColorRamp, Pattern, TextureSpace};
+
+    fn gray(value: f64) -> Color {
+        Color { r: value, g: value, b: value }
+    }
+
+    fn checker() -> ImageTexture {
+        // top row: 0, 1 / bottom row: 1, 0
+        ImageTexture::new(2, 2, vec![gray(0.0), gray(1.0), gray(1.0), gray(0.0)])
+    }
+
+    #[test]
+    fn image_texture_nearest_test() {
+        let texture = checker().with_filter(Filter::Nearest);
+        assert_eq!(texture.sample((0.25, 0.75)).r, 0.0);
+        assert_eq!(texture.sample((0.75, 0.75)).r, 1.0);
+        assert_eq!(texture.sample((0.25, 0.25)).r, 1.0);
+        assert_eq!(texture.sample((0.75, 0.25)).r, 0.0);
+    }
+
+    #[test]
+    fn image_texture_bilinear_test() {
+        let texture = checker();
+        assert!((texture.sample((0.25, 0.75)).r - 0.0).abs() < 1e-9);
+        assert!((texture.sample((0.5, 0.75)).r - 0.5).abs() < 1e-9);
+        assert!((texture.sample((0.5, 0.5)).r - 0.5).abs() < 1e-9);
+        assert!((texture.sample((0.375, 0.75)).r - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn image_texture_wrap_test() {
+        let texture = checker().with_filter(Filter::Nearest);
+        assert_eq!(texture.sample((1.25, 0.75)).r, 0.0);
+        assert_eq!(texture.sample((-0.25, 0.75)).r, 1.0);
+
+        let texture = checker().with_filter(Filter::Nearest).with_wrap(WrapMode::Clamp);
+        assert_eq!(texture.sample((1.25, 0.75)).r, 1.0);
+        assert_eq!(texture.sample((-0.25, 0.75)).r, 0.0);
+
+        let texture = checker().with_filter(Filter::Nearest).with_wrap(WrapMode::Mirror);
+        assert_eq!(texture.sample((1.25, 0.75)).r, 1.0);
+        assert_eq!(texture.sample((-0.25, 0.75)).r, 0.0);
+    }
+
+    #[test]
+    fn image_texture_open_test() {
+        let path = std::env::temp_dir().join("ray_tracing_image_texture_open_test.png");
+        image::save_buffer(&path, &[255, 0, 0, 0, 128, 0], 2, 1, image::ColorType::Rgb8).unwrap();
+        let texture = ImageTexture::open(&path, ColorEncoding::Srgb).unwrap().with_filter(Filter::Nearest);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((texture.width(), texture.height()), (2, 1));
+        let left = texture.sample((0.25, 0.5));
+        let right = texture.sample((0.75, 0.5));
+        assert!((left.r - 1.0).abs() < 1e-9 && left.g == 0.0);
+        assert!((right.g - 0.2158605).abs() < 1e-6 && right.r == 0.0);
+    }
+
+    #[test]
+    fn image_texture_color_space_test() {
+        let texture = ImageTexture::new(2, 1, vec![Color { r: 1.0, g: 0.0, b: 0.0 }, gray(0.5)])
+            .with_color_space(ColorSpace::LinearSrgb, ColorSpace::Rec2020)
+            .with_filter(Filter::Nearest);
+        let red = texture.sample((0.25, 0.5));
+        assert!((red.r - 0.6274).abs() < 1e-4 && (red.g - 0.0691).abs() < 1e-4 && (red.b - 0.0164).abs() < 1e-4, "{:?}", red);
+        let grey = texture.sample((0.75, 0.5));
+        assert!((grey.r - 0.5).abs() < 1e-9 && (grey.b - 0.5).abs() < 1e-9);
+        // Coarser MIP levels are converted too.
+        assert!((texture.texel(1, 0, 0).g - (0.0691 + 0.5) / 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn color_encoding_test() {
+        assert_eq!(ColorEncoding::Linear.decode(255), 1.0);
+        assert_eq!(ColorEncoding::Srgb.decode(0), 0.0);
+        assert!((ColorEncoding::Srgb.decode(255) - 1.0).abs() < 1e-9);
+        assert!((ColorEncoding::Srgb.decode(128) - 0.2158605).abs() < 1e-6);
+        assert!((ColorEncoding::Srgb.decode(10) - 0.003035).abs() < 1e-6);
+    }
+
+    #[test]
+    fn checker_texture_test() {
+        let texture = Texture::Checker { even: Box::new(gray(1.0).into()), odd: Box::new(gray(0.0).into()), space: TextureSpace::World, scale: 2.0 };
+        let at = |position| TextureCoordinate { uv: (0.0, 0.0), duv_dx: (0.0, 0.0), duv_dy: (0.0, 0.0), position, local_position: Vec3::new(0.0, 0.0, 0.0) };
+        assert_eq!(texture.evaluate(&at(Vec3::new(0.25, 0.25, 0.25))).r, 1.0);
+        assert_eq!(texture.evaluate(&at(Vec3::new(0.75, 0.25, 0.25))).r, 0.0);
+
+        let texture = Texture::Checker { even: Box::new(gray(1.0).into()), odd: Box::new(gray(0.0).into()), space: TextureSpace::Uv, scale: 4.0 };
+        let at = |uv| TextureCoordinate { uv, duv_dx: (0.0, 0.0), duv_dy: (0.0, 0.0), position: Vec3::new(0.0, 0.0, 0.0), local_position: Vec3::new(0.0, 0.0, 0.0) };
+        assert_eq!(texture.evaluate(&at((0.1, 0.1))).r, 1.0);
+        assert_eq!(texture.evaluate(&at((0.3, 0.1))).r, 0.0);
+        assert_eq!(texture.evaluate(&at((0.3, 0.3))).r, 1.0);
+    }
+
+    #[test]
+    fn procedural_texture_space_test() {
+        let texture = Texture::Procedural {
+            pattern: Pattern::Linear { axis: Vec3::new(1.0, 0.0, 0.0) },
+            ramp: ColorRamp::two(gray(0.0), gray(1.0)),
+            space: TextureSpace::Object,
+            scale: 1.0,
+        };
+        let coordinate = TextureCoordinate { uv: (0.0, 0.0), duv_dx: (0.0, 0.0), duv_dy: (0.0, 0.0), position: Vec3::new(10.0, 0.0, 0.0), local_position: Vec3::new(0.25, 0.0, 0.0) };
+        assert!((texture.evaluate(&coordinate).r - 0.25).abs() < 1e-9);
+    }
+
+    fn stripes() -> ImageTexture {
+        // 8x8 texels, columns alternating 0 and 1
+        ImageTexture::new(8, 8, (0..64).map(|i| gray((i % 2) as f64)).collect())
+    }
+
+    #[test]
+    fn mip_pyramid_test() {
+        let texture = stripes();
+        assert_eq!(texture.levels(), 4);
+        assert_eq!(texture.texel(1, 0, 0).r, 0.5);
+        assert_eq!(texture.texel(3, 0, 0).r, 0.5);
+
+        let texture = ImageTexture::new(5, 3, vec![gray(1.0); 15]);
+        assert_eq!(texture.levels(), 3);
+        assert_eq!(texture.texel(2, 0, 0).r, 1.0);
+    }
+
+    #[test]
+    fn trilinear_filter_test() {
+        let texture = stripes().with_filter(Filter::Trilinear);
+        // centre of a 0 column
+        let uv = (1.0 / 16.0, 0.5 + 1.0 / 16.0);
+        assert!(texture.sample(uv).r.abs() < 1e-9);
+        let minified = texture.sample_filtered(uv, (0.5, 0.0), (0.0, 0.5));
+        assert!((minified.r - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ewa_filter_test() {
+        let texture = stripes().with_filter(Filter::Ewa);
+        let uv = (1.0 / 16.0, 0.5 + 1.0 / 16.0);
+        assert!(texture.sample(uv).r.abs() < 1e-9);
+        // footprint stretched across the stripes averages them
+        let across = texture.sample_filtered(uv, (0.5, 0.0), (0.0, 1.0 / 16.0));
+        assert!((across.r - 0.5).abs() < 0.1, "{:?}", across);
+        // footprint stretched along the stripes keeps them sharp
+        let along = texture.sample_filtered(uv, (0.0, 0.5), (1.0 / 64.0, 0.0));
+        assert!(along.r < 0.1, "{:?}", along);
+        let trilinear = stripes().with_filter(Filter::Trilinear).sample_filtered(uv, (0.0, 0.5), (1.0 / 64.0, 0.0));
+        assert!((trilinear.r - 0.5).abs() < 1e-9);
+    }
+}
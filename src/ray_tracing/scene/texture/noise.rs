@@ -0,0 +1,141 @@
+//! Deterministic lattice noise functions used by procedural textures.
+
+use crate::geometry::Vec3;
+
+/// Ken Perlin's reference permutation.
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69, 142, 8, 99, 37, 240, 21, 10, 23,
+    190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219, 203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174,
+    20, 125, 136, 171, 168, 68, 175, 74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76, 132, 187, 208, 89, 18, 169,
+    200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173, 186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118,
+    126, 255, 82, 85, 212, 207, 206, 59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232, 178, 185, 112, 104, 218, 246,
+    97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162, 241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181,
+    199, 106, 157, 184, 84, 204, 176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+fn permutation(i: i64) -> usize {
+    PERMUTATION[(i & 255) as usize] as usize
+}
+
+fn hash(x: i64, y: i64, z: i64) -> usize {
+    permutation(x + permutation(y + permutation(z) as i64) as i64)
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn gradient(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Improved Perlin noise, roughly in `[-1, 1]` and zero on every lattice point.
+pub fn perlin(p: Vec3<f64>) -> f64 {
+    let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+    let (xi, yi, zi) = (fx as i64, fy as i64, fz as i64);
+    let (x, y, z) = (p.x() - fx, p.y() - fy, p.z() - fz);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+    lerp(w,
+         lerp(v,
+              lerp(u, gradient(hash(xi, yi, zi), x, y, z), gradient(hash(xi + 1, yi, zi), x - 1.0, y, z)),
+              lerp(u, gradient(hash(xi, yi + 1, zi), x, y - 1.0, z), gradient(hash(xi + 1, yi + 1, zi), x - 1.0, y - 1.0, z))),
+         lerp(v,
+              lerp(u, gradient(hash(xi, yi, zi + 1), x, y, z - 1.0), gradient(hash(xi + 1, yi, zi + 1), x - 1.0, y, z - 1.0)),
+              lerp(u, gradient(hash(xi, yi + 1, zi + 1), x, y - 1.0, z - 1.0), gradient(hash(xi + 1, yi + 1, zi + 1), x - 1.0, y - 1.0, z - 1.0))))
+}
+
+/// Fractal Brownian motion: octaves of Perlin noise with halving amplitude and doubling frequency.
+pub fn fbm(p: Vec3<f64>, octaves: usize) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut p = p;
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p);
+        amplitude *= 0.5;
+        p = p * 2.0;
+    }
+    sum
+}
+
+/// Like [`fbm`] but summing absolute values, which gives sharp creases.
+pub fn turbulence(p: Vec3<f64>, octaves: usize) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut p = p;
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p).abs();
+        amplitude *= 0.5;
+        p = p * 2.0;
+    }
+    sum
+}
+
+fn feature_point(x: i64, y: i64, z: i64) -> Vec3<f64> {
+    let h0 = hash(x, y, z);
+    let h1 = hash(x + 71, y + 113, z + 37);
+    let h2 = hash(x + 191, y + 29, z + 151);
+    Vec3::new(x as f64 + h0 as f64 / 256.0, y as f64 + h1 as f64 / 256.0, z as f64 + h2 as f64 / 256.0)
+}
+
+/// Cellular noise: distance from `p` to the nearest of one jittered feature point per unit cell.
+pub fn worley(p: Vec3<f64>) -> f64 {
+    let (xi, yi, zi) = (p.x().floor() as i64, p.y().floor() as i64, p.z().floor() as i64);
+    let mut nearest = f64::INFINITY;
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let distance = (feature_point(xi + dx, yi + dy, zi + dz) - p).squared_len();
+                nearest = nearest.min(distance);
+            }
+        }
+    }
+    nearest.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::texture::noise::{fbm, perlin, turbulence, worley, feature_point};
+
+    #[test]
+    fn perlin_lattice_test() {
+        for &(x, y, z) in &[(0.0, 0.0, 0.0), (1.0, 2.0, 3.0), (-4.0, 7.0, -2.0)] {
+            assert_eq!(perlin(Vec3::new(x, y, z)), 0.0);
+        }
+    }
+
+    #[test]
+    fn perlin_range_test() {
+        let mut any_non_zero = false;
+        for i in 0..1000 {
+            let t = i as f64 * 0.137;
+            let value = perlin(Vec3::new(t, t * 0.71 + 0.3, t * 1.37 - 0.2));
+            assert!((-1.0..=1.0).contains(&value));
+            any_non_zero |= value.abs() > 0.1;
+            assert!(turbulence(Vec3::new(t, 0.5, 0.25), 4) >= 0.0);
+            assert!(fbm(Vec3::new(t, 0.5, 0.25), 4).abs() <= 2.0);
+        }
+        assert!(any_non_zero);
+    }
+
+    #[test]
+    fn worley_test() {
+        let point = feature_point(3, -2, 5);
+        assert!(worley(point) < 1e-12);
+        for i in 0..1000 {
+            let t = i as f64 * 0.173;
+            let value = worley(Vec3::new(t, t * 0.3, -t));
+            assert!((0.0..=3f64.sqrt()).contains(&value));
+        }
+    }
+}
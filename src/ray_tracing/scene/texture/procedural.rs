@@ -0,0 +1,147 @@
+use crate::geometry::Vec3;
+use crate::ray_tracing::scene::material::Color;
+use crate::ray_tracing::scene::texture::noise::{fbm, turbulence, worley};
+
+/// Domain in which a procedural texture is evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureSpace {
+    /// `(u, v, 0)` from the surface parameterization.
+    Uv,
+    /// Hit position relative to the primitive, so the pattern moves with the object.
+    Object,
+    World,
+}
+
+/// Scalar field in `[0, 1]` mapped to colours through a [`ColorRamp`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// fBm Perlin noise remapped from `[-1, 1]`.
+    Noise { octaves: usize },
+    Turbulence { octaves: usize },
+    /// Sine stripes along x distorted by turbulence.
+    Marble { octaves: usize, distortion: f64 },
+    /// Concentric rings around the y axis distorted by noise.
+    Wood { octaves: usize, distortion: f64 },
+    /// Distance to the nearest Worley feature point.
+    Worley,
+    /// Linear gradient along `axis`, covering `[0, 1]` between the origin and `axis`; 0 everywhere for a zero axis.
+    Linear { axis: Vec3<f64> },
+    /// Radial gradient, 0 at the origin and 1 at unit distance.
+    Radial,
+}
+
+impl Pattern {
+    pub fn evaluate(&self, p: Vec3<f64>) -> f64 {
+        let value = match self {
+            Pattern::Noise { octaves } => 0.5 + 0.5 * fbm(p, *octaves),
+            Pattern::Turbulence { octaves } => turbulence(p, *octaves),
+            Pattern::Marble { octaves, distortion } => 0.5 + 0.5 * (p.x() + distortion * turbulence(p, *octaves)).sin(),
+            Pattern::Wood { octaves, distortion } => {
+                let r = (p.x() * p.x() + p.z() * p.z()).sqrt() + distortion * fbm(p, *octaves);
+                r - r.floor()
+            }
+            Pattern::Worley => worley(p),
+            Pattern::Linear { axis } => {
+                let length2: f64 = axis.squared_len();
+                if length2 > 0.0 { p.inner_product(*axis) / length2 } else { 0.0 }
+            }
+            Pattern::Radial => p.squared_len().sqrt(),
+        };
+        value.clamp(0.0, 1.0)
+    }
+}
+
+/// Piecewise linear colour gradient. Stops must be sorted by position.
+#[derive(Debug, Clone)]
+pub struct ColorRamp {
+    stops: Vec<(f64, Color)>,
+}
+
+impl ColorRamp {
+    pub fn new(stops: Vec<(f64, Color)>) -> Self {
+        assert!(!stops.is_empty());
+        assert!(stops.windows(2).all(|w| w[0].0 <= w[1].0), "stops must be sorted");
+        Self { stops }
+    }
+
+    pub fn two(from: Color, to: Color) -> Self {
+        Self::new(vec![(0.0, from), (1.0, to)])
+    }
+
+    pub fn evaluate(&self, t: f64) -> Color {
+        let index = self.stops.iter().position(|(position, _)| t < *position).unwrap_or(self.stops.len());
+        if index == 0 {
+            return self.stops[0].1.clone();
+        }
+        if index == self.stops.len() {
+            return self.stops[index - 1].1.clone();
+        }
+        let (p0, c0) = &self.stops[index - 1];
+        let (p1, c1) = &self.stops[index];
+        let w = (t - p0) / (p1 - p0);
+        Color {
+            r: c0.r + (c1.r - c0.r) * w,
+            g: c0.g + (c1.g - c0.g) * w,
+            b: c0.b + (c1.b - c0.b) * w,
+        }
+    }
+}
+
+/// Parity of the lattice cell containing `p`; the z component is ignored when `planar`.
+pub fn checker(p: Vec3<f64>, planar: bool) -> bool {
+    let sum = p.x().floor() as i64 + p.y().floor() as i64 + if planar { 0 } else { p.z().floor() as i64 };
+    sum.rem_euclid(2) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::scene::material::Color;
+    use crate::ray_tracing::scene::texture::procedural::{checker, ColorRamp, Pattern};
+
+    #[test]
+    fn checker_test() {
+        assert!(checker(Vec3::new(0.5, 0.5, 0.5), false));
+        assert!(!checker(Vec3::new(1.5, 0.5, 0.5), false));
+        assert!(!checker(Vec3::new(0.5, 0.5, 1.5), false));
+        assert!(checker(Vec3::new(0.5, 0.5, 1.5), true));
+        assert!(checker(Vec3::new(-0.5, -0.5, 0.5), false));
+        assert!(!checker(Vec3::new(-0.5, 0.5, 0.5), false));
+    }
+
+    #[test]
+    fn color_ramp_test() {
+        let ramp = ColorRamp::new(vec![
+            (0.0, Color { r: 0.0, g: 0.0, b: 0.0 }),
+            (0.5, Color { r: 1.0, g: 0.0, b: 0.0 }),
+            (1.0, Color { r: 1.0, g: 1.0, b: 1.0 }),
+        ]);
+        assert_eq!(ramp.evaluate(-1.0).r, 0.0);
+        assert!((ramp.evaluate(0.25).r - 0.5).abs() < 1e-9);
+        assert!((ramp.evaluate(0.75).g - 0.5).abs() < 1e-9);
+        assert_eq!(ramp.evaluate(2.0).b, 1.0);
+    }
+
+    #[test]
+    fn pattern_range_test() {
+        let patterns = [
+            Pattern::Noise { octaves: 4 },
+            Pattern::Turbulence { octaves: 4 },
+            Pattern::Marble { octaves: 4, distortion: 5.0 },
+            Pattern::Wood { octaves: 2, distortion: 0.5 },
+            Pattern::Worley,
+            Pattern::Linear { axis: Vec3::new(0.0, 2.0, 0.0) },
+            Pattern::Linear { axis: Vec3::new(0.0, 0.0, 0.0) },
+            Pattern::Radial,
+        ];
+        for pattern in &patterns {
+            for i in 0..200 {
+                let t = i as f64 * 0.0731;
+                let value = pattern.evaluate(Vec3::new(t, 1.3 - t, t * 0.5));
+                assert!((0.0..=1.0).contains(&value), "{:?} {}", pattern, value);
+            }
+        }
+        assert!((Pattern::Linear { axis: Vec3::new(0.0, 2.0, 0.0) }.evaluate(Vec3::new(5.0, 1.0, 0.0)) - 0.5).abs() < 1e-9);
+        assert!((Pattern::Radial.evaluate(Vec3::new(0.3, 0.4, 0.0)) - 0.5).abs() < 1e-9);
+    }
+}
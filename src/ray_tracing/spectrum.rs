@@ -0,0 +1,628 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::ops::{Add, Mul};
+use std::sync::OnceLock;
+
+use crate::geometry::{Matrix3, Vec3};
+use crate::ray_tracing::color_space::ColorSpace;
+use crate::ray_tracing::scene::material::Color;
+
+/// Visible range sampled by spectral rendering, in nanometres.
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+/// Wavelengths standing in for the red, green and blue channels where a spectrum has to be reduced to RGB
+/// by point sampling, as for indices of refraction. These are the CIE RGB primaries.
+pub const RGB_WAVELENGTHS: [f64; 3] = [700.0, 546.1, 435.8];
+
+/// Number of wavelengths traced together along each path.
+pub const WAVELENGTH_SAMPLES: usize = 4;
+
+/// Spectral radiance of a black body at `temperature` Kelvin and `lambda` nanometres (Planck's law, SI units).
+pub fn planck(lambda: f64, temperature: f64) -> f64 {
+    if temperature <= 0.0 {
+        return 0.0;
+    }
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const K: f64 = 1.380_649e-23;
+    let lambda = lambda * 1e-9;
+    2.0 * H * C * C / (lambda.powi(5) * ((H * C / (lambda * K * temperature)).exp() - 1.0))
+}
+
+/// CIE 1931 2° colour matching functions, by the multi-lobe Gaussian fit of Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(lambda: f64) -> Vec3<f64> {
+    let g = |mu: f64, below: f64, above: f64| {
+        let t = (lambda - mu) / if lambda < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Illuminant that uplifted colours are relative to and that maps to RGB white: a black body at the correlated
+/// colour temperature of D65, normalized to one at 560 nm.
+pub fn reference_white(lambda: f64) -> f64 {
+    planck(lambda, 6504.0) / planck(560.0, 6504.0)
+}
+
+/// Integrals needed to turn spectra into RGB, computed once.
+struct Colorimetry {
+    /// Luminance of [`reference_white`], which normalizes XYZ.
+    white_luminance: f64,
+    /// Linear sRGB from CIE XYZ.
+    xyz_to_srgb: Matrix3,
+    /// Per-channel scale balancing [`reference_white`] to RGB `(1, 1, 1)`.
+    white_balance: Vec3<f64>,
+    /// Quadrature nodes over the visible range with their colour matching functions times the reference white and step.
+    nodes: Vec<(f64, Vec3<f64>)>,
+}
+
+impl Colorimetry {
+    const STEP: f64 = 5.0;
+
+    fn get() -> &'static Colorimetry {
+        static COLORIMETRY: OnceLock<Colorimetry> = OnceLock::new();
+        COLORIMETRY.get_or_init(|| {
+            let count = ((LAMBDA_MAX - LAMBDA_MIN) / Self::STEP) as usize + 1;
+            let nodes: Vec<_> = (0..count).map(|i| {
+                let lambda = LAMBDA_MIN + i as f64 * Self::STEP;
+                (lambda, cie_xyz(lambda) * (reference_white(lambda) * Self::STEP))
+            }).collect();
+            let white = nodes.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &(_, weight)| sum + weight);
+            let white_luminance = *white.y();
+            let xyz_to_srgb = ColorSpace::LinearSrgb.from_xyz();
+            let rgb = xyz_to_srgb * (white / white_luminance);
+            Colorimetry { white_luminance, xyz_to_srgb, white_balance: Vec3::new(1.0 / rgb.x(), 1.0 / rgb.y(), 1.0 / rgb.z()), nodes }
+        })
+    }
+
+    fn xyz_to_rgb(&self, xyz: Vec3<f64>) -> Color {
+        let rgb = self.xyz_to_srgb * (xyz / self.white_luminance);
+        let balance = self.white_balance;
+        Color { r: rgb.x() * balance.x(), g: rgb.y() * balance.y(), b: rgb.z() * balance.z() }
+    }
+
+    /// RGB of a reflectance spectrum lit by the reference white.
+    fn reflectance_to_rgb(&self, reflectance: impl Fn(f64) -> f64) -> Color {
+        self.xyz_to_rgb(self.nodes.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &(lambda, weight)| sum + weight * reflectance(lambda)))
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+/// Smooth reflectance spectrum bounded to `[0, 1]`: a sigmoid of a quadratic over the visible range (Jakob and Hanika 2019).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SigmoidPolynomial {
+    coefficients: [f64; 3],
+}
+
+impl SigmoidPolynomial {
+    /// Fits are tabulated at this many values of each normalized coordinate.
+    const RESOLUTION: usize = 16;
+
+    /// Spectrum whose RGB under the reference white is `rgb`, clamped to `[0, 1]`.
+    pub fn from_rgb(rgb: &Color) -> Self {
+        let rgb = [rgb.r, rgb.g, rgb.b].map(|c| c.clamp(0.0, 1.0));
+        if rgb[0] == rgb[1] && rgb[1] == rgb[2] {
+            // Greys are constant, which the quadratic represents exactly.
+            let value = rgb[0];
+            let x = if value <= 0.0 { f64::NEG_INFINITY } else if value >= 1.0 { f64::INFINITY } else { (value - 0.5) / (value * (1.0 - value)).sqrt() };
+            return Self { coefficients: [0.0, 0.0, x] };
+        }
+        let largest = if rgb[0] >= rgb[1] && rgb[0] >= rgb[2] { 0 } else if rgb[1] >= rgb[2] { 1 } else { 2 };
+        let z = rgb[largest];
+        let x = rgb[(largest + 1) % 3] / z * (Self::RESOLUTION - 1) as f64;
+        let y = rgb[(largest + 2) % 3] / z * (Self::RESOLUTION - 1) as f64;
+        let table = Self::table();
+        let scale = Self::scale();
+        let zi = scale.partition_point(|&s| s <= z).clamp(1, Self::RESOLUTION - 1) - 1;
+        let (xi, yi) = ((x as usize).min(Self::RESOLUTION - 2), (y as usize).min(Self::RESOLUTION - 2));
+        let (tx, ty, tz) = (x - xi as f64, y - yi as f64, (z - scale[zi]) / (scale[zi + 1] - scale[zi]));
+        let mut coefficients = [0.0; 3];
+        for (dz, wz) in [(0, 1.0 - tz), (1, tz)] {
+            for (dy, wy) in [(0, 1.0 - ty), (1, ty)] {
+                for (dx, wx) in [(0, 1.0 - tx), (1, tx)] {
+                    let c = table[Self::index(largest, zi + dz, yi + dy, xi + dx)];
+                    for (sum, c) in coefficients.iter_mut().zip(c) {
+                        *sum += wx * wy * wz * c;
+                    }
+                }
+            }
+        }
+        Self { coefficients }
+    }
+
+    pub fn evaluate(&self, lambda: f64) -> f64 {
+        let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+        let [a, b, c] = self.coefficients;
+        sigmoid((a * t + b) * t + c)
+    }
+
+    /// Values of the largest component at which fits are tabulated, denser towards black and full saturation.
+    fn scale() -> &'static [f64] {
+        static SCALE: OnceLock<Vec<f64>> = OnceLock::new();
+        SCALE.get_or_init(|| {
+            let smoothstep = |x: f64| x * x * (3.0 - 2.0 * x);
+            (0..Self::RESOLUTION).map(|i| smoothstep(smoothstep(i as f64 / (Self::RESOLUTION - 1) as f64))).collect()
+        })
+    }
+
+    fn index(largest: usize, z: usize, y: usize, x: usize) -> usize {
+        ((largest * Self::RESOLUTION + z) * Self::RESOLUTION + y) * Self::RESOLUTION + x
+    }
+
+    /// Coefficients fitted for every table entry. Each fit starts from its neighbour's solution, walking outwards from
+    /// mid brightness, since Newton's method only converges near a solution.
+    fn table() -> &'static [[f64; 3]] {
+        static TABLE: OnceLock<Vec<[f64; 3]>> = OnceLock::new();
+        TABLE.get_or_init(|| {
+            let n = Self::RESOLUTION;
+            let scale = Self::scale();
+            let mut table = vec![[0.0; 3]; 3 * n * n * n];
+            let middle = n / 5;
+            for largest in 0..3 {
+                let order = (middle..n).chain((0..middle).rev());
+                for z in order {
+                    for y in 0..n {
+                        for x in 0..n {
+                            let start = if x > 0 {
+                                table[Self::index(largest, z, y, x - 1)]
+                            } else if y > 0 {
+                                table[Self::index(largest, z, y - 1, x)]
+                            } else if z > middle {
+                                table[Self::index(largest, z - 1, y, x)]
+                            } else if z < middle {
+                                table[Self::index(largest, z + 1, y, x)]
+                            } else {
+                                [0.0; 3]
+                            };
+                            let mut target = [0.0; 3];
+                            target[largest] = scale[z];
+                            target[(largest + 1) % 3] = x as f64 / (n - 1) as f64 * scale[z];
+                            target[(largest + 2) % 3] = y as f64 / (n - 1) as f64 * scale[z];
+                            table[Self::index(largest, z, y, x)] = Self::fit(Vec3::new(target[0], target[1], target[2]), start);
+                        }
+                    }
+                }
+            }
+            table
+        })
+    }
+
+    /// Newton's method on the RGB residual, with a finite-difference Jacobian.
+    fn fit(target: Vec3<f64>, start: [f64; 3]) -> [f64; 3] {
+        let colorimetry = Colorimetry::get();
+        let residual = |coefficients: [f64; 3]| {
+            let rgb = colorimetry.reflectance_to_rgb(|lambda| Self { coefficients }.evaluate(lambda));
+            Vec3::new(rgb.r, rgb.g, rgb.b) - target
+        };
+        let mut coefficients = start;
+        for _ in 0..30 {
+            let r = residual(coefficients);
+            if r.squared_len() < 1e-12 {
+                break;
+            }
+            const EPSILON: f64 = 1e-5;
+            let column = |i: usize| {
+                let mut shifted = coefficients;
+                shifted[i] += EPSILON;
+                (residual(shifted) - r) / EPSILON
+            };
+            let step = match Matrix3::from_columns(column(0), column(1), column(2)).inverse() {
+                Some(inverse) => inverse * r,
+                None => break,
+            };
+            // Bounded steps keep the quadratic from overshooting into a flat region of the sigmoid.
+            let length = step.squared_len().sqrt();
+            let step = if length > 50.0 { step * (50.0 / length) } else { step };
+            coefficients = [coefficients[0] - step.x(), coefficients[1] - step.y(), coefficients[2] - step.z()];
+        }
+        coefficients
+    }
+}
+
+/// Wavelengths carried by one path: a hero wavelength importance-sampled over the visible range and companions
+/// spaced evenly after it, wrapping around (Wilkie et al. 2014).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledWavelengths {
+    lambda: [f64; WAVELENGTH_SAMPLES],
+    pdf: [f64; WAVELENGTH_SAMPLES],
+    /// Space of the RGB colours converted to and from spectra along the path.
+    space: ColorSpace,
+}
+
+impl SampledWavelengths {
+    /// Samples from `u` in `[0, 1)` with density roughly proportional to the luminous efficiency (pbrt's visible distribution).
+    pub fn sample_visible(u: f64) -> Self {
+        let mut lambda = [0.0; WAVELENGTH_SAMPLES];
+        let mut pdf = [0.0; WAVELENGTH_SAMPLES];
+        for i in 0..WAVELENGTH_SAMPLES {
+            let u = (u + i as f64 / WAVELENGTH_SAMPLES as f64).fract();
+            lambda[i] = (538.0 - 138.888_889 * (0.856_910_62 - 1.827_501_97 * u).atanh()).clamp(LAMBDA_MIN, LAMBDA_MAX);
+            let c = (0.0072 * (lambda[i] - 538.0)).cosh();
+            pdf[i] = 0.003_939_804_2 / (c * c);
+        }
+        Self { lambda, pdf, space: ColorSpace::LinearSrgb }
+    }
+
+    /// Interprets colours in `space` instead of linear sRGB, in which uplifting happens.
+    pub fn with_working_space(self, space: ColorSpace) -> Self {
+        Self { space, ..self }
+    }
+
+    pub fn lambda(&self) -> &[f64; WAVELENGTH_SAMPLES] {
+        &self.lambda
+    }
+
+    pub fn pdf(&self) -> &[f64; WAVELENGTH_SAMPLES] {
+        &self.pdf
+    }
+
+    /// Drops all but the hero wavelength, for events such as dispersion that send each wavelength a different way.
+    pub fn terminate_secondary(&mut self) {
+        if self.is_secondary_terminated() {
+            return;
+        }
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= WAVELENGTH_SAMPLES as f64;
+    }
+
+    pub fn is_secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+
+    /// Monte Carlo estimate of the XYZ colour of `spectrum`, averaging over the wavelengths still alive.
+    pub fn to_xyz(&self, spectrum: &SampledSpectrum) -> Vec3<f64> {
+        (0..WAVELENGTH_SAMPLES)
+            .filter(|&i| self.pdf[i] > 0.0)
+            .fold(Vec3::new(0.0, 0.0, 0.0), |sum, i| sum + cie_xyz(self.lambda[i]) * (spectrum.0[i] / self.pdf[i]))
+            / WAVELENGTH_SAMPLES as f64
+    }
+
+    /// Colour of `spectrum` in the working space, scaled so that [`reference_white`] maps to white.
+    pub fn to_rgb(&self, spectrum: &SampledSpectrum) -> Color {
+        ColorSpace::LinearSrgb.convert(&Colorimetry::get().xyz_to_rgb(self.to_xyz(spectrum)), self.space)
+    }
+
+    /// Linear sRGB of a working-space colour, ready to be uplifted.
+    fn in_srgb(&self, color: &Color) -> Color {
+        self.space.convert(color, ColorSpace::LinearSrgb)
+    }
+}
+
+/// Values of a spectrum at the [`SampledWavelengths`] of a path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledSpectrum(pub [f64; WAVELENGTH_SAMPLES]);
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+/// Spectral distribution given directly rather than through an RGB colour, such as measured optical constants.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Spectrum {
+    Constant(f64),
+    /// Piecewise linear through `(wavelength, value)` pairs sorted by wavelength, constant beyond the ends.
+    Sampled(Vec<(f64, f64)>),
+    /// Black body at a temperature in Kelvin, normalized to one at its peak.
+    Blackbody(f64),
+}
+
+impl Spectrum {
+    /// Panics unless `samples` is non-empty and sorted by wavelength.
+    pub fn sampled(samples: Vec<(f64, f64)>) -> Self {
+        assert!(!samples.is_empty(), "a sampled spectrum needs samples");
+        assert!(samples.windows(2).all(|w| w[0].0 < w[1].0), "samples must be sorted by wavelength");
+        Spectrum::Sampled(samples)
+    }
+
+    /// Reads whitespace-separated pairs of wavelength in nanometres and value, as in `.spd` files.
+    /// Text after `#` on a line is a comment.
+    pub fn read(reader: impl BufRead) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut numbers = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("");
+            for word in line.split_whitespace() {
+                numbers.push(word.parse::<f64>().map_err(|_| invalid("invalid number in spectrum"))?);
+            }
+        }
+        if numbers.is_empty() || numbers.len() % 2 != 0 {
+            return Err(invalid("spectrum needs wavelength and value pairs"));
+        }
+        let samples: Vec<_> = numbers.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+        if !samples.windows(2).all(|w| w[0].0 < w[1].0) {
+            return Err(invalid("spectrum wavelengths must increase"));
+        }
+        Ok(Spectrum::Sampled(samples))
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn evaluate(&self, lambda: f64) -> f64 {
+        match self {
+            Spectrum::Constant(value) => *value,
+            Spectrum::Sampled(samples) => {
+                let i = samples.partition_point(|&(wavelength, _)| wavelength < lambda);
+                if i == 0 {
+                    return samples[0].1;
+                }
+                if i == samples.len() {
+                    return samples[i - 1].1;
+                }
+                let ((l0, v0), (l1, v1)) = (samples[i - 1], samples[i]);
+                v0 + (v1 - v0) * (lambda - l0) / (l1 - l0)
+            }
+            Spectrum::Blackbody(temperature) if *temperature <= 0.0 => 0.0,
+            // Wien's displacement law gives the peak wavelength.
+            Spectrum::Blackbody(temperature) => planck(lambda, *temperature) / planck(2.897_771_955e6 / temperature, *temperature),
+        }
+    }
+}
+
+
+/// Quantity carried along paths: RGB triples, or spectra at the wavelengths of a path in spectral mode.
+/// Colours from textures and materials are converted where they enter the path.
+pub trait Radiance: Clone + Debug + Add<Output=Self> + Mul<Output=Self> {
+    /// What a path needs to know to convert colours, i.e. its wavelengths.
+    type Wavelengths: Copy + Debug;
+
+    const CHANNELS: usize;
+
+    fn from_fn(f: impl FnMut(usize) -> f64) -> Self;
+
+    fn channel(&self, i: usize) -> f64;
+
+    /// Uplifts a reflectance, clamping it to `[0, 1]`.
+    fn albedo(color: &Color, wavelengths: &Self::Wavelengths) -> Self;
+
+    /// Uplifts a non-negative coefficient without an upper bound, such as a scattering coefficient.
+    fn unbounded(color: &Color, wavelengths: &Self::Wavelengths) -> Self;
+
+    /// Uplifts emitted light, relative to [`reference_white`].
+    fn illuminant(color: &Color, wavelengths: &Self::Wavelengths) -> Self;
+
+    fn spectrum(spectrum: &Spectrum, wavelengths: &Self::Wavelengths) -> Self;
+
+    /// Restricts a path to one channel before an event that sends each wavelength a different way, such as dispersion.
+    /// Returns the wavelength of that channel and a factor for the throughput that zeroes every other channel.
+    fn select_channel(throughput: &Self, wavelengths: &mut Self::Wavelengths, u: f64) -> (f64, Self);
+
+    fn splat(value: f64) -> Self {
+        Self::from_fn(|_| value)
+    }
+
+    fn zero() -> Self {
+        Self::splat(0.0)
+    }
+
+    fn map(&self, mut f: impl FnMut(f64) -> f64) -> Self {
+        Self::from_fn(|i| f(self.channel(i)))
+    }
+
+    fn mean(&self) -> f64 {
+        (0..Self::CHANNELS).map(|i| self.channel(i)).sum::<f64>() / Self::CHANNELS as f64
+    }
+
+    fn max_value(&self) -> f64 {
+        (0..Self::CHANNELS).map(|i| self.channel(i)).fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+impl Radiance for Color {
+    type Wavelengths = ();
+
+    const CHANNELS: usize = 3;
+
+    fn from_fn(mut f: impl FnMut(usize) -> f64) -> Self {
+        Color { r: f(0), g: f(1), b: f(2) }
+    }
+
+    fn channel(&self, i: usize) -> f64 {
+        match i {
+            0 => self.r,
+            1 => self.g,
+            _ => self.b,
+        }
+    }
+
+    fn albedo(color: &Color, _: &()) -> Self {
+        color.map(|c| c.clamp(0.0, 1.0))
+    }
+
+    fn unbounded(color: &Color, _: &()) -> Self {
+        color.clone()
+    }
+
+    fn illuminant(color: &Color, _: &()) -> Self {
+        color.clone()
+    }
+
+    fn spectrum(spectrum: &Spectrum, _: &()) -> Self {
+        Self::from_fn(|i| spectrum.evaluate(RGB_WAVELENGTHS[i]))
+    }
+
+    /// Picks a channel in proportion to `throughput`, so paths already reduced to one channel keep it.
+    fn select_channel(throughput: &Self, _: &mut (), u: f64) -> (f64, Self) {
+        let total: f64 = (0..3).map(|i| throughput.channel(i).abs()).sum();
+        if total <= 0.0 {
+            return (RGB_WAVELENGTHS[1], Self::zero());
+        }
+        let mut remaining = u * total;
+        let selected = (0..3).find(|&i| {
+            remaining -= throughput.channel(i).abs();
+            remaining < 0.0
+        }).unwrap_or_else(|| (0..3).rev().find(|&i| throughput.channel(i) != 0.0).unwrap_or(2));
+        let probability = throughput.channel(selected).abs() / total;
+        (RGB_WAVELENGTHS[selected], Self::from_fn(|i| if i == selected { 1.0 / probability } else { 0.0 }))
+    }
+}
+
+impl SampledSpectrum {
+    /// Scale and sigmoid polynomial representing `color` as `scale * s(lambda)`, for colours above one.
+    fn scaled(color: &Color) -> (f64, SigmoidPolynomial) {
+        let scale = 2.0 * color.max_value().max(0.0);
+        let normalized = if scale > 0.0 { color.map(|c| c / scale) } else { Color::zero() };
+        (scale, SigmoidPolynomial::from_rgb(&normalized))
+    }
+}
+
+impl Radiance for SampledSpectrum {
+    type Wavelengths = SampledWavelengths;
+
+    const CHANNELS: usize = WAVELENGTH_SAMPLES;
+
+    fn from_fn(f: impl FnMut(usize) -> f64) -> Self {
+        SampledSpectrum(std::array::from_fn(f))
+    }
+
+    fn channel(&self, i: usize) -> f64 {
+        self.0[i]
+    }
+
+    fn albedo(color: &Color, wavelengths: &SampledWavelengths) -> Self {
+        let polynomial = SigmoidPolynomial::from_rgb(&wavelengths.in_srgb(color));
+        Self::from_fn(|i| polynomial.evaluate(wavelengths.lambda[i]))
+    }
+
+    fn unbounded(color: &Color, wavelengths: &SampledWavelengths) -> Self {
+        let (scale, polynomial) = Self::scaled(&wavelengths.in_srgb(color));
+        Self::from_fn(|i| scale * polynomial.evaluate(wavelengths.lambda[i]))
+    }
+
+    fn illuminant(color: &Color, wavelengths: &SampledWavelengths) -> Self {
+        let (scale, polynomial) = Self::scaled(&wavelengths.in_srgb(color));
+        Self::from_fn(|i| {
+            let lambda = wavelengths.lambda[i];
+            scale * polynomial.evaluate(lambda) * reference_white(lambda)
+        })
+    }
+
+    fn spectrum(spectrum: &Spectrum, wavelengths: &SampledWavelengths) -> Self {
+        Self::from_fn(|i| spectrum.evaluate(wavelengths.lambda[i]))
+    }
+
+    /// Keeps the hero wavelength; [`SampledWavelengths::to_rgb`] accounts for the others being dropped.
+    fn select_channel(_: &Self, wavelengths: &mut SampledWavelengths, _: f64) -> (f64, Self) {
+        wavelengths.terminate_secondary();
+        (wavelengths.lambda[0], Self::from_fn(|i| if i == 0 { 1.0 } else { 0.0 }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::ray_tracing::scene::material::Color;
+    use crate::ray_tracing::spectrum::{cie_xyz, Colorimetry, Radiance, SampledSpectrum, SampledWavelengths, SigmoidPolynomial, Spectrum, LAMBDA_MAX, LAMBDA_MIN};
+
+    #[test]
+    fn uplift_test() {
+        let colorimetry = Colorimetry::get();
+        let white = colorimetry.reflectance_to_rgb(|_| 1.0);
+        for c in [white.r, white.g, white.b] {
+            assert!((c - 1.0).abs() < 1e-9, "{:?}", white);
+        }
+        // Smooth spectra reproduce any reflectance within the gamut closely.
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..200 {
+            let color = Color { r: rng.gen_range(0.0..1.0), g: rng.gen_range(0.0..1.0), b: rng.gen_range(0.0..1.0) };
+            let polynomial = SigmoidPolynomial::from_rgb(&color);
+            let rgb = colorimetry.reflectance_to_rgb(|lambda| polynomial.evaluate(lambda));
+            for (a, b) in [(rgb.r, color.r), (rgb.g, color.g), (rgb.b, color.b)] {
+                assert!((a - b).abs() < 0.03, "{:?} became {:?}", color, rgb);
+            }
+            for lambda in [LAMBDA_MIN, 500.0, LAMBDA_MAX] {
+                assert!((0.0..=1.0).contains(&polynomial.evaluate(lambda)));
+            }
+        }
+        let grey = SigmoidPolynomial::from_rgb(&Color { r: 0.3, g: 0.3, b: 0.3 });
+        assert!((grey.evaluate(400.0) - 0.3).abs() < 1e-12 && (grey.evaluate(700.0) - 0.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sampled_wavelengths_test() {
+        // Averaging over sampled wavelengths recovers the colour of uplifted lights, including bright ones.
+        let mut rng = StdRng::seed_from_u64(1);
+        let color = Color { r: 2.7, g: 0.9, b: 0.3 };
+        let mut sum = Color::zero();
+        let mut range = 0.0;
+        const COUNT: usize = 20000;
+        for _ in 0..COUNT {
+            let wavelengths = SampledWavelengths::sample_visible(rng.gen_range(0.0..1.0));
+            for (&lambda, &pdf) in wavelengths.lambda().iter().zip(wavelengths.pdf()) {
+                assert!((LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda));
+                range += 1.0 / pdf;
+            }
+            sum = sum + wavelengths.to_rgb(&SampledSpectrum::illuminant(&color, &wavelengths));
+        }
+        let count = COUNT as f64;
+        assert!((range / (4.0 * count) - (LAMBDA_MAX - LAMBDA_MIN)).abs() < 5.0, "{}", range / (4.0 * count));
+        let (scale, polynomial) = SampledSpectrum::scaled(&color);
+        let uplifted = Colorimetry::get().reflectance_to_rgb(|lambda| scale * polynomial.evaluate(lambda));
+        for (estimate, expected, original) in [(sum.r, uplifted.r, color.r), (sum.g, uplifted.g, color.g), (sum.b, uplifted.b, color.b)] {
+            assert!((estimate / count - expected).abs() < expected * 0.02, "{:?}", sum.map(|c| c / count));
+            assert!((expected - original).abs() < 0.03 * scale);
+        }
+
+        // After termination the hero wavelength alone estimates the spectrum, so it carries the weight of all four.
+        let mut wavelengths = SampledWavelengths::sample_visible(0.3);
+        let spectrum = SampledSpectrum([1.0, 2.0, 3.0, 4.0]);
+        let hero = wavelengths.lambda()[0];
+        wavelengths.terminate_secondary();
+        assert!(wavelengths.is_secondary_terminated());
+        assert_eq!(wavelengths.lambda()[0], hero);
+        let xyz = wavelengths.to_xyz(&spectrum);
+        let expected = cie_xyz(hero) / (4.0 * wavelengths.pdf()[0]);
+        assert!((xyz - expected).squared_len() < 1e-18);
+    }
+
+    #[test]
+    fn spectrum_test() {
+        let text = "# wavelength value\n400 1.0\n500 2.0 # green\n600 4.0\n";
+        let spectrum = Spectrum::read(text.as_bytes()).unwrap();
+        assert_eq!(spectrum, Spectrum::sampled(vec![(400.0, 1.0), (500.0, 2.0), (600.0, 4.0)]));
+        assert_eq!(spectrum.evaluate(450.0), 1.5);
+        assert_eq!(spectrum.evaluate(575.0), 3.5);
+        assert_eq!(spectrum.evaluate(300.0), 1.0);
+        assert_eq!(spectrum.evaluate(700.0), 4.0);
+        assert!(Spectrum::read("400 1.0 500".as_bytes()).is_err());
+        assert!(Spectrum::read("500 1.0 400 2.0".as_bytes()).is_err());
+        assert!(Spectrum::read("400 red".as_bytes()).is_err());
+
+        let sun = Spectrum::Blackbody(5800.0);
+        assert!((sun.evaluate(2.897_771_955e6 / 5800.0) - 1.0).abs() < 1e-12);
+        assert!(sun.evaluate(500.0) > sun.evaluate(800.0));
+        let rgb = Color::spectrum(&Spectrum::Constant(0.5), &());
+        assert_eq!((rgb.r, rgb.g, rgb.b), (0.5, 0.5, 0.5));
+    }
+}
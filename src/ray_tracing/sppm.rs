@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rand::{Rng, thread_rng};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+
+use crate::geometry::Vec3;
+use crate::ray_tracing::{leaks, sample_cosine, scatter_delta, Ray, RenderOptions, SurfacePoint};
+use crate::ray_tracing::aov::{PathRecord, PixelAovs};
+use crate::ray_tracing::bdpt::{Emission, Scene, SplatFilm};
+use crate::ray_tracing::scene::{offset_ray_origin, Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::material::{Color, Material, ShadingGeometry};
+use crate::ray_tracing::spectrum::Radiance;
+use crate::ray_tracing::statistics::{Counters, PathCounters, Progress};
+
+/// Stochastic progressive photon mapping (Hachisuka and Jensen 2009). Every iteration follows one camera path per
+/// pixel through mirrors and glass to its first diffuse surface, then shoots photons from the lights and gathers those
+/// landing within a radius of that point. The radius shrinks as photons accumulate, so the estimate converges even
+/// for caustics, which paths from the camera almost never find.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhotonMapping {
+    /// Photons shot per iteration; one iteration runs per sample per pixel.
+    pub photons_per_iteration: usize,
+    /// Gather radius every pixel starts with, in scene units.
+    pub initial_radius: f64,
+    /// Fraction of newly gathered photons kept per iteration; smaller values shrink the radius faster.
+    pub alpha: f64,
+}
+
+impl Default for PhotonMapping {
+    fn default() -> Self {
+        Self { photons_per_iteration: 100_000, initial_radius: 0.05, alpha: 2.0 / 3.0 }
+    }
+}
+
+/// What a pixel has gathered over all iterations so far.
+#[derive(Debug, Clone)]
+struct Pixel {
+    radius: f64,
+    /// Photons accounted for within the current radius.
+    photons: f64,
+    /// Reflected flux of those photons.
+    flux: Color,
+    /// Emission its camera paths saw, summed over iterations.
+    emitted: Color,
+    luminance_sum: f64,
+    luminance_squares: f64,
+    aovs: PixelAovs,
+}
+
+/// First diffuse surface a camera path reached in the current iteration.
+#[derive(Debug, Clone)]
+struct VisiblePoint {
+    position: Vec3<f64>,
+    /// Geometric normal facing the camera path.
+    normal: Vec3<f64>,
+    /// Camera path throughput times the Lambertian reflectance at this point.
+    weight: Color,
+}
+
+/// Visible points of one iteration in a hash grid whose cells are as wide as the largest gather radius, so a photon
+/// only has to look at its own cell.
+struct Gather<'a> {
+    points: &'a [Option<VisiblePoint>],
+    radii: Vec<f64>,
+    cell_size: f64,
+    grid: HashMap<[i64; 3], Vec<usize>>,
+    flux: SplatFilm,
+    counts: Vec<AtomicU64>,
+}
+
+impl<'a> Gather<'a> {
+    fn new(points: &'a [Option<VisiblePoint>], pixels: &[Pixel], width: usize, height: usize) -> Self {
+        let radii: Vec<f64> = pixels.iter().map(|pixel| pixel.radius).collect();
+        let cell_size = points.iter().zip(&radii).filter(|(point, _)| point.is_some()).fold(0.0, |size: f64, (_, radius)| size.max(*radius));
+        let mut gather = Self {
+            points,
+            radii,
+            cell_size,
+            grid: HashMap::new(),
+            flux: SplatFilm::new(width, height),
+            counts: (0..width * height).map(|_| AtomicU64::new(0)).collect(),
+        };
+        for (i, point) in points.iter().enumerate() {
+            if let Some(point) = point {
+                let radius = gather.radii[i];
+                let offset = Vec3::new(radius, radius, radius);
+                let (low, high) = (gather.cell(point.position - offset), gather.cell(point.position + offset));
+                for x in low[0]..=high[0] {
+                    for y in low[1]..=high[1] {
+                        for z in low[2]..=high[2] {
+                            gather.grid.entry([x, y, z]).or_default().push(i);
+                        }
+                    }
+                }
+            }
+        }
+        gather
+    }
+
+    fn cell(&self, position: Vec3<f64>) -> [i64; 3] {
+        [*position.x(), *position.y(), *position.z()].map(|c| (c / self.cell_size).floor() as i64)
+    }
+
+    /// Adds a photon of `power` arriving at `position` from direction `wi` to every visible point around it that
+    /// faces it.
+    fn deposit(&self, position: Vec3<f64>, wi: Vec3<f64>, power: &Color) {
+        if self.cell_size == 0.0 {
+            return;
+        }
+        for &i in self.grid.get(&self.cell(position)).into_iter().flatten() {
+            let point = self.points[i].as_ref().expect("only visible points are in the grid");
+            let offset = point.position - position;
+            if offset.squared_len::<f64>() > self.radii[i] * self.radii[i] || wi.inner_product(point.normal) <= 0.0 {
+                continue;
+            }
+            self.flux.add_pixel(i, &(point.weight.clone() * power.clone()));
+            self.counts[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl PhotonMapping {
+    /// Renders `width * height` pixels in `options.samples_per_pixel` iterations, returning each pixel's colour, the
+    /// variance of its luminance between iterations and what its camera paths saw. Light is traced in RGB even for
+    /// spectral renders, and participating media are ignored. Lighting AOVs only hold emission seen directly or through
+    /// mirrors and glass.
+    pub fn render<T: Collision>(&self, scene: &Scene<T>, width: usize, height: usize, options: &RenderOptions, counters: &Counters) -> Vec<(Color, f64, PixelAovs)> {
+        let mut pixels = vec![Pixel {
+            radius: self.initial_radius,
+            photons: 0.0,
+            flux: Color::zero(),
+            emitted: Color::zero(),
+            luminance_sum: 0.0,
+            luminance_squares: 0.0,
+            aovs: PixelAovs::default(),
+        }; width * height];
+        let record = !options.aovs.is_empty() || options.denoise.is_some();
+        let progress = Progress::new(options.samples_per_pixel, options.progress);
+        for _ in 0..options.samples_per_pixel {
+            let (points, emitted): (Vec<_>, Vec<_>) = pixels.par_iter_mut().enumerate().map(|(i, pixel)| {
+                let mut rng = thread_rng();
+                let mut path_counters = PathCounters::default();
+                let mut path = PathRecord::default();
+                let ray = {
+                    let (x, y) = ((i % width) as f64, (i / width) as f64);
+                    let time = scene.camera().sample_time(rng.gen_range(0.0..1.0));
+                    scene.camera().create_ray(rng.gen_range(x..x + 1.0), rng.gen_range(y..y + 1.0), time)
+                };
+                let (point, emitted) = visible_point(scene, ray, options, &mut rng, &mut path_counters, record.then_some(&mut path));
+                pixel.aovs.add(path, Color::clone);
+                counters.record(&path_counters);
+                (point, emitted)
+            }).unzip();
+
+            let gather = Gather::new(&points, &pixels, width, height);
+            (0..self.photons_per_iteration).into_par_iter()
+                .fold(PathCounters::default, |mut path_counters, _| {
+                    trace_photon(scene, &gather, options, &mut thread_rng(), &mut path_counters);
+                    path_counters
+                })
+                .for_each(|path_counters| counters.record(&path_counters));
+
+            let flux = gather.flux.colors(1.0);
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                let estimate = emitted[i].clone() + flux[i].clone() * Color::splat(1.0 / (self.photons_per_iteration as f64 * PI * pixel.radius * pixel.radius));
+                let luminance = options.working_space.luminance(&estimate);
+                pixel.luminance_sum += luminance;
+                pixel.luminance_squares += luminance * luminance;
+                pixel.emitted = pixel.emitted.clone() + emitted[i].clone();
+                let count = gather.counts[i].load(Ordering::Relaxed) as f64;
+                if count > 0.0 {
+                    let photons = pixel.photons + self.alpha * count;
+                    let radius = pixel.radius * (photons / (pixel.photons + count)).sqrt();
+                    let shrink = (radius / pixel.radius).powi(2);
+                    pixel.flux = (pixel.flux.clone() + flux[i].clone()) * Color::splat(shrink);
+                    pixel.photons = photons;
+                    pixel.radius = radius;
+                }
+            }
+            progress.advance(1);
+        }
+        progress.finish();
+
+        let iterations = options.samples_per_pixel as f64;
+        let photons = iterations * self.photons_per_iteration as f64;
+        pixels.into_iter().map(|pixel| {
+            let color = pixel.emitted * Color::splat(1.0 / iterations)
+                + pixel.flux * Color::splat(1.0 / (photons * PI * pixel.radius * pixel.radius));
+            let mean = pixel.luminance_sum / iterations;
+            let variance = if iterations > 1.0 { (pixel.luminance_squares / iterations - mean * mean).max(0.0) / (iterations - 1.0) } else { pixel.luminance_squares };
+            (color, variance, pixel.aovs)
+        }).collect()
+    }
+}
+
+/// Follows a camera ray through mirrors and glass to the first diffuse surface, returning it with the emission seen
+/// on the way.
+fn visible_point<T: Collision>(scene: &Scene<T>, mut ray: Ray, options: &RenderOptions, rng: &mut impl Rng, path_counters: &mut PathCounters,
+                               mut record: Option<&mut PathRecord<Color>>) -> (Option<VisiblePoint>, Color) {
+    path_counters.samples += 1;
+    let mut beta = Color::splat(1.0);
+    let mut emitted = Color::zero();
+    for bounce in 0..=options.max_depth {
+        let (collision, travelled) = scene.intersect(ray.clone(), path_counters);
+        let mut interaction = match collision {
+            Some(interaction) => interaction,
+            None => {
+                path_counters.escaped += 1;
+                return (None, emitted);
+            }
+        };
+        path_counters.path_length_total += 1;
+        let point = SurfacePoint::new(&mut interaction, &ray);
+        if let Some(record) = record.as_deref_mut().filter(|_| bounce == 0) {
+            record.first_hit = Some(point.first_hit(scene.objects(), &interaction, travelled + interaction.distance));
+        }
+        let SurfacePoint { coordinate, material, geometry, shading_normal, .. } = point;
+        let ShadingGeometry { normal, .. } = geometry;
+        let SurfaceInteraction { position, error, .. } = interaction;
+        match material {
+            Material::Solid { color, illuminate } => {
+                let light = beta.clone() * illuminate.evaluate(&coordinate);
+                if let Some(record) = record {
+                    record.add(&light, bounce, Some(interaction.primitive_id));
+                }
+                emitted = emitted + light;
+                let weight = beta * color.evaluate(&coordinate) * Color::splat(1.0 / PI);
+                let point = (weight.max_value() > 0.0).then_some(VisiblePoint { position, normal, weight });
+                return (point, emitted);
+            }
+            Material::Conductor { .. } | Material::Dielectric { .. } => {
+                let scatter = scatter_delta(material, ray.direction.vec(), shading_normal, interaction.front_face, &coordinate, &beta, &mut (), rng);
+                if leaks(scatter.direction, normal, scatter.transmitted) {
+                    return (None, emitted);
+                }
+                beta = beta * scatter.weight;
+                ray = Ray::new(offset_ray_origin(position, error, normal, scatter.direction), scatter.direction.normalize(), ray.time);
+            }
+            _ => unreachable!("intersect skips interfaces and resolve strips bump and fill layers"),
+        }
+    }
+    path_counters.terminated_by_depth += 1;
+    (None, emitted)
+}
+
+/// Shoots one photon from a light, depositing it at every diffuse surface it reaches.
+fn trace_photon<T: Collision>(scene: &Scene<T>, gather: &Gather, options: &RenderOptions, rng: &mut impl Rng, path_counters: &mut PathCounters) {
+    let time = scene.camera().sample_time(rng.gen_range(0.0..1.0));
+    let emission: Emission<Color> = match scene.sample_emission(time, &(), rng) {
+        Some(emission) => emission,
+        None => return,
+    };
+    let mut power = emission.power();
+    let mut ray = emission.ray(time);
+    for bounce in 0..=options.max_depth {
+        let mut interaction = match scene.intersect(ray.clone(), path_counters).0 {
+            Some(interaction) => interaction,
+            None => return,
+        };
+        let SurfacePoint { coordinate, material, geometry, shading_normal, .. } = SurfacePoint::new(&mut interaction, &ray);
+        let ShadingGeometry { normal, .. } = geometry;
+        let SurfaceInteraction { position, error, dpdu, .. } = interaction;
+        let wi = -ray.direction.vec();
+        let (direction, weight) = match material {
+            Material::Solid { color, .. } => {
+                gather.deposit(position, wi, &power);
+                let (direction, _) = sample_cosine(shading_normal, dpdu, rng);
+                if leaks(direction, normal, false) {
+                    return;
+                }
+                (direction, color.evaluate(&coordinate))
+            }
+            Material::Conductor { .. } | Material::Dielectric { .. } => {
+                let scatter = scatter_delta(material, ray.direction.vec(), shading_normal, interaction.front_face, &coordinate, &power, &mut (), rng);
+                if leaks(scatter.direction, normal, scatter.transmitted) {
+                    return;
+                }
+                (scatter.direction, scatter.weight)
+            }
+            _ => unreachable!("intersect skips interfaces and resolve strips bump and fill layers"),
+        };
+        let scattered = power.clone() * weight;
+        // Photon powers are arbitrary, so roulette compares what survives the bounce with what arrived.
+        if bounce + 1 >= options.min_depth {
+            let survival = (scattered.max_value() / power.max_value()).min(1.0);
+            if survival <= 0.0 || rng.gen_range(0.0..1.0) >= survival {
+                return;
+            }
+            power = scattered * Color::splat(1.0 / survival);
+        } else {
+            power = scattered;
+        }
+        ray = Ray::new(offset_ray_origin(position, error, normal, direction), direction.normalize(), ray.time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::geometry::Vec3;
+    use crate::ray_tracing::{trace_path, RenderOptions};
+    use crate::ray_tracing::bdpt::Scene;
+    use crate::ray_tracing::scene::material::Color;
+    use crate::ray_tracing::sppm::PhotonMapping;
+    use crate::ray_tracing::statistics::{Counters, PathCounters};
+    use crate::ray_tracing::test_util::{camera, caustic, furnace, SIZE};
+
+    fn mean(image: &[(Color, f64, crate::ray_tracing::aov::PixelAovs)]) -> f64 {
+        image.iter().map(|(color, _, _)| color.g).sum::<f64>() / image.len() as f64
+    }
+
+    #[test]
+    fn sppm_furnace_test() {
+        let objects = furnace();
+        let camera = camera(Vec3::new(0.0, 0.0, 0.5), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, -1.0, 0.0));
+        let scene = Scene::new(&objects, &camera);
+        let options = RenderOptions { samples_per_pixel: 16, ..RenderOptions::default() };
+        let photon_mapping = PhotonMapping { photons_per_iteration: 20_000, initial_radius: 0.2, ..PhotonMapping::default() };
+        let image = photon_mapping.render(&scene, SIZE, SIZE, &options, &Counters::default());
+        for (color, variance, _) in &image {
+            assert!((color.g - 1.0).abs() < 0.1, "{:?}", color);
+            assert!(*variance > 0.0);
+        }
+        assert!((mean(&image) - 1.0).abs() < 0.03, "{}", mean(&image));
+    }
+
+    #[test]
+    fn sppm_caustic_test() {
+        // Looking across the floor at the caustic under the glass ball from below the light, which stays out of view.
+        // Path tracing converges there too, as the ball magnifies the light it sees through it.
+        let objects = caustic();
+        let camera = camera(Vec3::new(0.0, -0.6, 1.2), Vec3::new(0.0, -0.4, -1.2), Vec3::new(0.0, -1.2, 0.4));
+        let scene = Scene::new(&objects, &camera);
+        // The caustic has sharp edges inside pixels, so many iterations with few photons each converge fastest.
+        let options = RenderOptions { samples_per_pixel: 512, ..RenderOptions::default() };
+        let photon_mapping = PhotonMapping { photons_per_iteration: 1_250, initial_radius: 0.05, ..PhotonMapping::default() };
+        let image = photon_mapping.render(&scene, SIZE, SIZE, &options, &Counters::default());
+
+        let mut rng = StdRng::seed_from_u64(0);
+        const COUNT: usize = 4096;
+        // Mean brightness of the central pixels, which see the caustic.
+        let area = [(3, 3), (4, 3), (3, 4), (4, 4)];
+        let (mut expected, mut actual) = (0.0, 0.0);
+        for &(x, y) in &area {
+            actual += image[y * SIZE + x].0.g / area.len() as f64;
+            for _ in 0..COUNT {
+                let ray = camera.create_ray(x as f64 + rng.gen_range(0.0..1.0), y as f64 + rng.gen_range(0.0..1.0), 0.0);
+                expected += trace_path::<_, Color>(&objects, ray, &options, &mut (), &mut rng, &mut PathCounters::default(), None).g / (area.len() * COUNT) as f64;
+            }
+        }
+        assert!(expected > 4.0 * mean(&image), "{} {}", expected, mean(&image));
+        // Photon mapping stays noisy at a few pixels; without the caustic the estimate would be off by nearly all of it.
+        assert!((actual - expected).abs() < 0.3 * expected, "{} {}", actual, expected);
+    }
+}
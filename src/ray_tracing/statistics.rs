@@ -0,0 +1,219 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Per-path counters accumulated without synchronization and merged into [`Counters`] once per pixel.
+#[derive(Debug, Clone, Default)]
+pub struct PathCounters {
+    pub rays_traced: u64,
+    pub intersection_tests: u64,
+    pub samples: u64,
+    pub path_length_total: u64,
+    pub terminated_by_depth: u64,
+    pub terminated_by_roulette: u64,
+    pub escaped: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Counters {
+    rays_traced: AtomicU64,
+    intersection_tests: AtomicU64,
+    samples: AtomicU64,
+    path_length_total: AtomicU64,
+    terminated_by_depth: AtomicU64,
+    terminated_by_roulette: AtomicU64,
+    escaped: AtomicU64,
+}
+
+impl Counters {
+    pub fn record(&self, counters: &PathCounters) {
+        self.rays_traced.fetch_add(counters.rays_traced, Ordering::Relaxed);
+        self.intersection_tests.fetch_add(counters.intersection_tests, Ordering::Relaxed);
+        self.samples.fetch_add(counters.samples, Ordering::Relaxed);
+        self.path_length_total.fetch_add(counters.path_length_total, Ordering::Relaxed);
+        self.terminated_by_depth.fetch_add(counters.terminated_by_depth, Ordering::Relaxed);
+        self.terminated_by_roulette.fetch_add(counters.terminated_by_roulette, Ordering::Relaxed);
+        self.escaped.fetch_add(counters.escaped, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> PathCounters {
+        PathCounters {
+            rays_traced: self.rays_traced.load(Ordering::Relaxed),
+            intersection_tests: self.intersection_tests.load(Ordering::Relaxed),
+            samples: self.samples.load(Ordering::Relaxed),
+            path_length_total: self.path_length_total.load(Ordering::Relaxed),
+            terminated_by_depth: self.terminated_by_depth.load(Ordering::Relaxed),
+            terminated_by_roulette: self.terminated_by_roulette.load(Ordering::Relaxed),
+            escaped: self.escaped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Timing {
+    pub scene_build: Duration,
+    pub render: Duration,
+    pub denoise: Duration,
+    pub output: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Statistics {
+    pub counters: Counters,
+    pub timing: Timing,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn average_path_length(&self) -> f64 {
+        let counters = self.counters.snapshot();
+        if counters.samples == 0 {
+            0.0
+        } else {
+            counters.path_length_total as f64 / counters.samples as f64
+        }
+    }
+
+    pub fn samples_per_second(&self) -> f64 {
+        let render = self.timing.render.as_secs_f64();
+        if render == 0.0 {
+            0.0
+        } else {
+            self.counters.snapshot().samples as f64 / render
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let counters = self.counters.snapshot();
+        format!(
+            "Rays traced:          {}\n\
+             Intersection tests:   {}\n\
+             Samples:              {}\n\
+             Average path length:  {:.3}\n\
+             Terminated by depth:  {}\n\
+             Terminated by RR:     {}\n\
+             Escaped:              {}\n\
+             Samples per second:   {:.0}\n\
+             Time: scene build {}ms, render {}ms, denoise {}ms, output {}ms",
+            counters.rays_traced,
+            counters.intersection_tests,
+            counters.samples,
+            self.average_path_length(),
+            counters.terminated_by_depth,
+            counters.terminated_by_roulette,
+            counters.escaped,
+            self.samples_per_second(),
+            self.timing.scene_build.as_millis(),
+            self.timing.render.as_millis(),
+            self.timing.denoise.as_millis(),
+            self.timing.output.as_millis(),
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let counters = self.counters.snapshot();
+        format!(
+            "{{\n  \"rays_traced\": {},\n  \"intersection_tests\": {},\n  \"samples\": {},\n  \"average_path_length\": {},\n  \
+             \"terminated_by_depth\": {},\n  \"terminated_by_roulette\": {},\n  \"escaped\": {},\n  \"samples_per_second\": {},\n  \
+             \"timing_ms\": {{\n    \"scene_build\": {},\n    \"render\": {},\n    \"denoise\": {},\n    \"output\": {}\n  }}\n}}\n",
+            counters.rays_traced,
+            counters.intersection_tests,
+            counters.samples,
+            self.average_path_length(),
+            counters.terminated_by_depth,
+            counters.terminated_by_roulette,
+            counters.escaped,
+            self.samples_per_second(),
+            self.timing.scene_build.as_secs_f64() * 1e3,
+            self.timing.render.as_secs_f64() * 1e3,
+            self.timing.denoise.as_secs_f64() * 1e3,
+            self.timing.output.as_secs_f64() * 1e3,
+        )
+    }
+}
+
+/// Progress bar written to stderr, redrawn at most every 100ms. A hidden bar only counts.
+pub struct Progress {
+    total: usize,
+    visible: bool,
+    done: AtomicUsize,
+    start: Instant,
+    last_draw: Mutex<Instant>,
+}
+
+impl Progress {
+    const WIDTH: usize = 40;
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new(total: usize, visible: bool) -> Self {
+        let start = Instant::now();
+        Self {
+            total,
+            visible,
+            done: AtomicUsize::new(0),
+            start,
+            last_draw: Mutex::new(start),
+        }
+    }
+
+    pub fn advance(&self, count: usize) {
+        let done = self.done.fetch_add(count, Ordering::Relaxed) + count;
+        if !self.visible {
+            return;
+        }
+        if let Ok(mut last_draw) = self.last_draw.try_lock() {
+            if last_draw.elapsed() >= Self::INTERVAL {
+                *last_draw = Instant::now();
+                self.draw(done);
+            }
+        }
+    }
+
+    pub fn finish(&self) {
+        if !self.visible {
+            return;
+        }
+        self.draw(self.done.load(Ordering::Relaxed));
+        eprintln!();
+    }
+
+    fn draw(&self, done: usize) {
+        let ratio = if self.total == 0 { 1.0 } else { done as f64 / self.total as f64 };
+        let filled = (ratio * Self::WIDTH as f64) as usize;
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let eta = if done == 0 { 0.0 } else { elapsed * (self.total - done) as f64 / done as f64 };
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r[{}{}] {:5.1}% {:.1}s elapsed, ETA {:.1}s ",
+                       "#".repeat(filled),
+                       ".".repeat(Self::WIDTH - filled),
+                       ratio * 100.0,
+                       elapsed,
+                       eta);
+        let _ = stderr.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ray_tracing::statistics::{PathCounters, Statistics};
+
+    #[test]
+    fn statistics_record_test() {
+        let statistics = Statistics::new();
+        statistics.counters.record(&PathCounters { rays_traced: 3, intersection_tests: 24, samples: 2, path_length_total: 3, terminated_by_depth: 1, terminated_by_roulette: 0, escaped: 1 });
+        statistics.counters.record(&PathCounters { rays_traced: 1, intersection_tests: 8, samples: 1, path_length_total: 1, terminated_by_depth: 0, terminated_by_roulette: 1, escaped: 0 });
+        let counters = statistics.counters.snapshot();
+        assert_eq!(counters.rays_traced, 4);
+        assert_eq!(counters.intersection_tests, 32);
+        assert_eq!(counters.samples, 3);
+        assert_eq!(counters.terminated_by_depth, 1);
+        assert_eq!(counters.terminated_by_roulette, 1);
+        assert_eq!(counters.escaped, 1);
+        assert!((statistics.average_path_length() - 4.0 / 3.0).abs() < 1e-9);
+        assert!(statistics.to_json().contains("\"rays_traced\": 4"));
+    }
+}
@@ -0,0 +1,76 @@
+//! Scenes and cameras shared by the integrator tests.
+
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::geometry::{Aabb, Vec3};
+use crate::ray_tracing::Ray;
+use crate::ray_tracing::scene::{Collision, SurfaceInteraction};
+use crate::ray_tracing::scene::camera::Camera;
+use crate::ray_tracing::scene::material::{Color, Ior, Material};
+use crate::ray_tracing::scene::object::plane::{Plane, Rectangle};
+use crate::ray_tracing::scene::object::Sphere;
+
+/// Width and height of test images in pixels.
+pub const SIZE: usize = 8;
+
+pub fn camera(position: Vec3<f64>, direction: Vec3<f64>, up: Vec3<f64>) -> Camera {
+    Camera::new(position, direction.normalize(), up.normalize(), Vec3::new(1.0, 0.0, 0.0).normalize(), SIZE, SIZE, PI / 6.0)
+}
+
+/// Object that counts the shadow rays tested against it.
+pub struct Counting<T> {
+    pub object: T,
+    occluded: AtomicUsize,
+}
+
+impl<T> Counting<T> {
+    pub fn new(object: T) -> Self {
+        Self { object, occluded: AtomicUsize::new(0) }
+    }
+
+    pub fn occluded_count(&self) -> usize {
+        self.occluded.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Collision> Collision for Counting<T> {
+    fn collision(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceInteraction<'_>> {
+        self.object.collision(ray, t_min, t_max)
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.occluded.fetch_add(1, Ordering::Relaxed);
+        self.object.occluded(ray, t_max)
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        self.object.materials()
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.object.bounding_box()
+    }
+
+    fn sample_area(&self, u: (f64, f64), time: f64) -> Option<(SurfaceInteraction<'_>, f64)> {
+        self.object.sample_area(u, time)
+    }
+}
+
+/// Closed sphere of radius one with albedo a and emission e, inside which every pixel sees e / (1 - a) = 1.
+pub fn furnace() -> [Sphere; 1] {
+    [Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Solid {
+        color: Color { r: 0.5, g: 0.5, b: 0.5 }.into(),
+        illuminate: Color { r: 0.5, g: 0.5, b: 0.5 }.into(),
+    })]
+}
+
+/// Glass ball resting above a diffuse floor at `y = -1`, focusing a small light at `y = 1.5` onto it.
+pub fn caustic() -> Vec<Box<dyn Collision>> {
+    let floor = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0).normalize(),
+                           Material::Solid { color: Color { r: 0.8, g: 0.8, b: 0.8 }.into(), illuminate: Color::zero().into() });
+    let glass = Sphere::new(Vec3::new(0.0, -0.4, 0.0), 0.5, Material::Dielectric { ior: Ior::Constant(1.5) });
+    let light = Rectangle::new(Vec3::new(-0.2, 1.5, -0.2), Vec3::new(0.0, 0.0, 0.4), Vec3::new(0.4, 0.0, 0.0),
+                               Material::Solid { color: Color::zero().into(), illuminate: Color { r: 20.0, g: 20.0, b: 20.0 }.into() });
+    vec![Box::new(floor), Box::new(glass), Box::new(light)]
+}